@@ -4,5 +4,5 @@ fn main() {
     println!("cargo:rerun-if-changed=gen_consts.py");
 
     let c = Command::new("./gen_consts.py").output().unwrap();
-    fs::write("src/consts.rs", c.stdout);
+    fs::write("src/consts.rs", c.stdout).unwrap();
 }
@@ -10,7 +10,10 @@ kwds_main = [
         "init", "new",
         "remove",
         "inputs",
-        "outputs"
+        "outputs",
+        "save",
+        "control",
+        "subscribe", "unsubscribe"
     ]),
 
     ("metacommands", [
@@ -23,18 +26,287 @@ kwds_main = [
         "channelfilter",
         "channelmerge",
         "dummyplayer",
+        "humanize",
+        "latch",
+        "progmap",
+        "monitor",
+        "record",
+        "player",
+        "looper",
+        "seq",
+        "lfo",
+        "thin",
+        "rotate",
+        "mpedown",
+        "paramconv",
+        "netin",
+        "netout",
+        "rtpmidi",
+        "oscin",
+        "oscout",
+        "tcp",
+        "pipein",
+        "pipeout",
+        "serial",
     ]),
 
-    (["processor_ctors", "&[fn(String, &[String]) -> crate::utils::Result<Box<dyn crate::MidiIO>>]"], [
+    (["processor_ctors", "&[crate::utils::ProcessorCtor]"], [
         "crate::processors::connection::MidiIn::new_args",
         "crate::processors::connection::MidiOut::new_args",
         "crate::processors::channelfilter::ChannelFilter::new_args",
         "crate::processors::channelmerge::ChannelMerge::new_args",
         "crate::processors::dummyplayer::DummyPlayer::new_args",
+        "crate::processors::humanize::Humanize::new_args",
+        "crate::processors::latch::Latch::new_args",
+        "crate::processors::progmap::ProgMap::new_args",
+        "crate::processors::monitor::Monitor::new_args",
+        "crate::processors::record::Record::new_args",
+        "crate::processors::player::Player::new_args",
+        "crate::processors::looper::Looper::new_args",
+        "crate::processors::seq::Seq::new_args",
+        "crate::processors::lfo::Lfo::new_args",
+        "crate::processors::thin::Thin::new_args",
+        "crate::processors::rotate::Rotate::new_args",
+        "crate::processors::mpedown::MpeDown::new_args",
+        "crate::processors::paramconv::ParamConv::new_args",
+        "crate::processors::net::NetIn::new_args",
+        "crate::processors::net::NetOut::new_args",
+        "crate::processors::rtpmidi::RtpMidi::new_args",
+        "crate::processors::osc::OscIn::new_args",
+        "crate::processors::osc::OscOut::new_args",
+        "crate::processors::tcp::TcpServer::new_args",
+        "crate::processors::pipe::PipeIn::new_args",
+        "crate::processors::pipe::PipeOut::new_args",
+        "crate::processors::serial::Serial::new_args",
     ]),
 
     ("channelfilter_cmds", [
         "channel"
+    ]),
+
+    ("humanize_cmds", [
+        "timing",
+        "velocity",
+        "seed"
+    ]),
+
+    ("latch_cmds", [
+        "mode",
+        "toggle"
+    ]),
+    ("latch_modes", [
+        "latch",
+        "sustain"
+    ]),
+
+    ("progmap_cmds", [
+        "load",
+        "clear"
+    ]),
+    ("progmap_line_kwds", [
+        "bank",
+        "program",
+        "cc"
+    ]),
+
+    ("monitor_cmds", [
+        "output",
+        "show",
+        "hide"
+    ]),
+    ("monitor_outputs", [
+        "shell",
+        "file",
+        "off"
+    ]),
+    ("monitor_types", [
+        "note",
+        "polytouch",
+        "cc",
+        "program",
+        "chantouch",
+        "pitchbend",
+        "common",
+        "realtime"
+    ]),
+
+    ("record_cmds", [
+        "start",
+        "stop",
+        "clear",
+        "save"
+    ]),
+
+    ("player_cmds", [
+        "play",
+        "stop",
+        "seek",
+        "loop",
+        "tempo",
+        "follow",
+        "load"
+    ]),
+
+    ("looper_cmds", [
+        "record",
+        "overdub",
+        "undo",
+        "clear",
+        "mute",
+        "length",
+        "tempo",
+        "sync",
+        "map"
+    ]),
+
+    ("seq_cmds", [
+        "play",
+        "stop",
+        "step",
+        "steps",
+        "pattern",
+        "tempo",
+        "rate",
+        "channel",
+        "sync"
+    ]),
+    ("seq_step_kwds", [
+        "note",
+        "velocity",
+        "gate",
+        "probability",
+        "on",
+        "off"
+    ]),
+    ("lfo_cmds", [
+        "shape",
+        "rate",
+        "sync",
+        "depth",
+        "offset",
+        "channel",
+        "target",
+        "maxrate"
+    ]),
+    ("lfo_shapes", [
+        "sine",
+        "triangle",
+        "saw",
+        "square",
+        "random"
+    ]),
+    ("lfo_targets", [
+        "cc",
+        "pitchbend",
+        "aftertouch"
+    ]),
+    ("thin_cmds", [
+        "rate",
+        "window",
+        "dedup",
+        "flush"
+    ]),
+    ("rotate_cmds", [
+        "master",
+        "size",
+        "policy",
+        "steal",
+        "reset"
+    ]),
+    ("rotate_policies", [
+        "roundrobin",
+        "lru",
+        "lowest"
+    ]),
+    ("rotate_steal", [
+        "oldest",
+        "newest",
+        "none"
+    ]),
+    ("mpedown_cmds", [
+        "channel",
+        "master",
+        "bend"
+    ]),
+    ("mpedown_bend_modes", [
+        "highest",
+        "drop"
+    ]),
+    ("paramconv_cmds", [
+        "map",
+        "unmap",
+        "clear"
+    ]),
+    ("param_kinds", [
+        "cc",
+        "cc14",
+        "rpn",
+        "nrpn"
+    ]),
+    ("netin_cmds", [
+        "reset"
+    ]),
+    ("netout_cmds", [
+        "address"
+    ]),
+    ("rtpmidi_cmds", [
+        "invite",
+        "bye"
+    ]),
+    ("oscin_cmds", [
+        "load",
+        "clear"
+    ]),
+    ("oscout_cmds", [
+        "load",
+        "clear",
+        "address"
+    ]),
+    ("osc_targets", [
+        "cc",
+        "note",
+        "pitchbend",
+        "aftertouch",
+        "program"
+    ]),
+    ("tcp_cmds", [
+        "kick"
+    ]),
+    ("tcp_protocols", [
+        "raw",
+        "json",
+        "websocket"
+    ]),
+    ("message_kinds", [
+        "note_on",
+        "note_off",
+        "poly_pressure",
+        "cc",
+        "program",
+        "channel_pressure",
+        "pitch_bend",
+        "raw"
+    ]),
+    ("pipein_cmds", [
+        "format"
+    ]),
+    ("pipeout_cmds", [
+        "format"
+    ]),
+    ("pipe_formats", [
+        "raw",
+        "text"
+    ]),
+    ("serial_cmds", [
+        "baud",
+        "running"
+    ]),
+
+    ("event_kinds", [
+        "processors",
+        "connections",
+        "ports",
+        "messages"
     ])
 ]
 
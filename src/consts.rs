@@ -1,5 +1,5 @@
 pub mod commands {
-	pub const COMMANDS: &[&str] = &["exit", "list", "ls", "rename", "connect", "disconnect", "cfg", "init", "new", "remove", "inputs", "outputs"];
+	pub const COMMANDS: &[&str] = &["exit", "list", "ls", "rename", "connect", "disconnect", "cfg", "init", "new", "remove", "inputs", "outputs", "save", "control", "subscribe", "unsubscribe"];
 	pub const IDX_EXIT: usize = 0;
 	pub const IDX_LIST: usize = 1;
 	pub const IDX_LS: usize = 2;
@@ -12,6 +12,10 @@ pub mod commands {
 	pub const IDX_REMOVE: usize = 9;
 	pub const IDX_INPUTS: usize = 10;
 	pub const IDX_OUTPUTS: usize = 11;
+	pub const IDX_SAVE: usize = 12;
+	pub const IDX_CONTROL: usize = 13;
+	pub const IDX_SUBSCRIBE: usize = 14;
+	pub const IDX_UNSUBSCRIBE: usize = 15;
 }
 
 pub mod metacommands {
@@ -21,16 +25,38 @@ pub mod metacommands {
 }
 
 pub mod processors {
-	pub const PROCESSORS: &[&str] = &["input", "output", "channelfilter", "channelmerge", "dummyplayer"];
+	pub const PROCESSORS: &[&str] = &["input", "output", "channelfilter", "channelmerge", "dummyplayer", "humanize", "latch", "progmap", "monitor", "record", "player", "looper", "seq", "lfo", "thin", "rotate", "mpedown", "paramconv", "netin", "netout", "rtpmidi", "oscin", "oscout", "tcp", "pipein", "pipeout", "serial"];
 	pub const IDX_INPUT: usize = 0;
 	pub const IDX_OUTPUT: usize = 1;
 	pub const IDX_CHANNELFILTER: usize = 2;
 	pub const IDX_CHANNELMERGE: usize = 3;
 	pub const IDX_DUMMYPLAYER: usize = 4;
+	pub const IDX_HUMANIZE: usize = 5;
+	pub const IDX_LATCH: usize = 6;
+	pub const IDX_PROGMAP: usize = 7;
+	pub const IDX_MONITOR: usize = 8;
+	pub const IDX_RECORD: usize = 9;
+	pub const IDX_PLAYER: usize = 10;
+	pub const IDX_LOOPER: usize = 11;
+	pub const IDX_SEQ: usize = 12;
+	pub const IDX_LFO: usize = 13;
+	pub const IDX_THIN: usize = 14;
+	pub const IDX_ROTATE: usize = 15;
+	pub const IDX_MPEDOWN: usize = 16;
+	pub const IDX_PARAMCONV: usize = 17;
+	pub const IDX_NETIN: usize = 18;
+	pub const IDX_NETOUT: usize = 19;
+	pub const IDX_RTPMIDI: usize = 20;
+	pub const IDX_OSCIN: usize = 21;
+	pub const IDX_OSCOUT: usize = 22;
+	pub const IDX_TCP: usize = 23;
+	pub const IDX_PIPEIN: usize = 24;
+	pub const IDX_PIPEOUT: usize = 25;
+	pub const IDX_SERIAL: usize = 26;
 }
 
 pub mod processor_ctors {
-	pub const PROCESSOR_CTORS: &[fn(String, &[String]) -> crate::utils::Result<Box<dyn crate::MidiIO>>] = &[crate::processors::connection::MidiIn::new_args, crate::processors::connection::MidiOut::new_args, crate::processors::channelfilter::ChannelFilter::new_args, crate::processors::channelmerge::ChannelMerge::new_args, crate::processors::dummyplayer::DummyPlayer::new_args, ];
+	pub const PROCESSOR_CTORS: &[crate::utils::ProcessorCtor] = &[crate::processors::connection::MidiIn::new_args, crate::processors::connection::MidiOut::new_args, crate::processors::channelfilter::ChannelFilter::new_args, crate::processors::channelmerge::ChannelMerge::new_args, crate::processors::dummyplayer::DummyPlayer::new_args, crate::processors::humanize::Humanize::new_args, crate::processors::latch::Latch::new_args, crate::processors::progmap::ProgMap::new_args, crate::processors::monitor::Monitor::new_args, crate::processors::record::Record::new_args, crate::processors::player::Player::new_args, crate::processors::looper::Looper::new_args, crate::processors::seq::Seq::new_args, crate::processors::lfo::Lfo::new_args, crate::processors::thin::Thin::new_args, crate::processors::rotate::Rotate::new_args, crate::processors::mpedown::MpeDown::new_args, crate::processors::paramconv::ParamConv::new_args, crate::processors::net::NetIn::new_args, crate::processors::net::NetOut::new_args, crate::processors::rtpmidi::RtpMidi::new_args, crate::processors::osc::OscIn::new_args, crate::processors::osc::OscOut::new_args, crate::processors::tcp::TcpServer::new_args, crate::processors::pipe::PipeIn::new_args, crate::processors::pipe::PipeOut::new_args, crate::processors::serial::Serial::new_args, ];
 }
 
 pub mod channelfilter_cmds {
@@ -38,3 +64,295 @@ pub mod channelfilter_cmds {
 	pub const IDX_CHANNEL: usize = 0;
 }
 
+pub mod humanize_cmds {
+	pub const HUMANIZE_CMDS: &[&str] = &["timing", "velocity", "seed"];
+	pub const IDX_TIMING: usize = 0;
+	pub const IDX_VELOCITY: usize = 1;
+	pub const IDX_SEED: usize = 2;
+}
+
+pub mod latch_cmds {
+	pub const LATCH_CMDS: &[&str] = &["mode", "toggle"];
+	pub const IDX_MODE: usize = 0;
+	pub const IDX_TOGGLE: usize = 1;
+}
+
+pub mod latch_modes {
+	pub const LATCH_MODES: &[&str] = &["latch", "sustain"];
+	pub const IDX_LATCH: usize = 0;
+	pub const IDX_SUSTAIN: usize = 1;
+}
+
+pub mod progmap_cmds {
+	pub const PROGMAP_CMDS: &[&str] = &["load", "clear"];
+	pub const IDX_LOAD: usize = 0;
+	pub const IDX_CLEAR: usize = 1;
+}
+
+pub mod progmap_line_kwds {
+	pub const PROGMAP_LINE_KWDS: &[&str] = &["bank", "program", "cc"];
+	pub const IDX_BANK: usize = 0;
+	pub const IDX_PROGRAM: usize = 1;
+	pub const IDX_CC: usize = 2;
+}
+
+pub mod monitor_cmds {
+	pub const MONITOR_CMDS: &[&str] = &["output", "show", "hide"];
+	pub const IDX_OUTPUT: usize = 0;
+	pub const IDX_SHOW: usize = 1;
+	pub const IDX_HIDE: usize = 2;
+}
+
+pub mod monitor_outputs {
+	pub const MONITOR_OUTPUTS: &[&str] = &["shell", "file", "off"];
+	pub const IDX_SHELL: usize = 0;
+	pub const IDX_FILE: usize = 1;
+	pub const IDX_OFF: usize = 2;
+}
+
+pub mod monitor_types {
+	pub const MONITOR_TYPES: &[&str] = &["note", "polytouch", "cc", "program", "chantouch", "pitchbend", "common", "realtime"];
+	pub const IDX_NOTE: usize = 0;
+	pub const IDX_POLYTOUCH: usize = 1;
+	pub const IDX_CC: usize = 2;
+	pub const IDX_PROGRAM: usize = 3;
+	pub const IDX_CHANTOUCH: usize = 4;
+	pub const IDX_PITCHBEND: usize = 5;
+	pub const IDX_COMMON: usize = 6;
+	pub const IDX_REALTIME: usize = 7;
+}
+
+pub mod record_cmds {
+	pub const RECORD_CMDS: &[&str] = &["start", "stop", "clear", "save"];
+	pub const IDX_START: usize = 0;
+	pub const IDX_STOP: usize = 1;
+	pub const IDX_CLEAR: usize = 2;
+	pub const IDX_SAVE: usize = 3;
+}
+
+pub mod player_cmds {
+	pub const PLAYER_CMDS: &[&str] = &["play", "stop", "seek", "loop", "tempo", "follow", "load"];
+	pub const IDX_PLAY: usize = 0;
+	pub const IDX_STOP: usize = 1;
+	pub const IDX_SEEK: usize = 2;
+	pub const IDX_LOOP: usize = 3;
+	pub const IDX_TEMPO: usize = 4;
+	pub const IDX_FOLLOW: usize = 5;
+	pub const IDX_LOAD: usize = 6;
+}
+
+pub mod looper_cmds {
+	pub const LOOPER_CMDS: &[&str] = &["record", "overdub", "undo", "clear", "mute", "length", "tempo", "sync", "map"];
+	pub const IDX_RECORD: usize = 0;
+	pub const IDX_OVERDUB: usize = 1;
+	pub const IDX_UNDO: usize = 2;
+	pub const IDX_CLEAR: usize = 3;
+	pub const IDX_MUTE: usize = 4;
+	pub const IDX_LENGTH: usize = 5;
+	pub const IDX_TEMPO: usize = 6;
+	pub const IDX_SYNC: usize = 7;
+	pub const IDX_MAP: usize = 8;
+}
+
+pub mod seq_cmds {
+	pub const SEQ_CMDS: &[&str] = &["play", "stop", "step", "steps", "pattern", "tempo", "rate", "channel", "sync"];
+	pub const IDX_PLAY: usize = 0;
+	pub const IDX_STOP: usize = 1;
+	pub const IDX_STEP: usize = 2;
+	pub const IDX_STEPS: usize = 3;
+	pub const IDX_PATTERN: usize = 4;
+	pub const IDX_TEMPO: usize = 5;
+	pub const IDX_RATE: usize = 6;
+	pub const IDX_CHANNEL: usize = 7;
+	pub const IDX_SYNC: usize = 8;
+}
+
+pub mod seq_step_kwds {
+	pub const SEQ_STEP_KWDS: &[&str] = &["note", "velocity", "gate", "probability", "on", "off"];
+	pub const IDX_NOTE: usize = 0;
+	pub const IDX_VELOCITY: usize = 1;
+	pub const IDX_GATE: usize = 2;
+	pub const IDX_PROBABILITY: usize = 3;
+	pub const IDX_ON: usize = 4;
+	pub const IDX_OFF: usize = 5;
+}
+
+pub mod lfo_cmds {
+	pub const LFO_CMDS: &[&str] = &["shape", "rate", "sync", "depth", "offset", "channel", "target", "maxrate"];
+	pub const IDX_SHAPE: usize = 0;
+	pub const IDX_RATE: usize = 1;
+	pub const IDX_SYNC: usize = 2;
+	pub const IDX_DEPTH: usize = 3;
+	pub const IDX_OFFSET: usize = 4;
+	pub const IDX_CHANNEL: usize = 5;
+	pub const IDX_TARGET: usize = 6;
+	pub const IDX_MAXRATE: usize = 7;
+}
+
+pub mod lfo_shapes {
+	pub const LFO_SHAPES: &[&str] = &["sine", "triangle", "saw", "square", "random"];
+	pub const IDX_SINE: usize = 0;
+	pub const IDX_TRIANGLE: usize = 1;
+	pub const IDX_SAW: usize = 2;
+	pub const IDX_SQUARE: usize = 3;
+	pub const IDX_RANDOM: usize = 4;
+}
+
+pub mod lfo_targets {
+	pub const LFO_TARGETS: &[&str] = &["cc", "pitchbend", "aftertouch"];
+	pub const IDX_CC: usize = 0;
+	pub const IDX_PITCHBEND: usize = 1;
+	pub const IDX_AFTERTOUCH: usize = 2;
+}
+
+pub mod thin_cmds {
+	pub const THIN_CMDS: &[&str] = &["rate", "window", "dedup", "flush"];
+	pub const IDX_RATE: usize = 0;
+	pub const IDX_WINDOW: usize = 1;
+	pub const IDX_DEDUP: usize = 2;
+	pub const IDX_FLUSH: usize = 3;
+}
+
+pub mod rotate_cmds {
+	pub const ROTATE_CMDS: &[&str] = &["master", "size", "policy", "steal", "reset"];
+	pub const IDX_MASTER: usize = 0;
+	pub const IDX_SIZE: usize = 1;
+	pub const IDX_POLICY: usize = 2;
+	pub const IDX_STEAL: usize = 3;
+	pub const IDX_RESET: usize = 4;
+}
+
+pub mod rotate_policies {
+	pub const ROTATE_POLICIES: &[&str] = &["roundrobin", "lru", "lowest"];
+	pub const IDX_ROUNDROBIN: usize = 0;
+	pub const IDX_LRU: usize = 1;
+	pub const IDX_LOWEST: usize = 2;
+}
+
+pub mod rotate_steal {
+	pub const ROTATE_STEAL: &[&str] = &["oldest", "newest", "none"];
+	pub const IDX_OLDEST: usize = 0;
+	pub const IDX_NEWEST: usize = 1;
+	pub const IDX_NONE: usize = 2;
+}
+
+pub mod mpedown_cmds {
+	pub const MPEDOWN_CMDS: &[&str] = &["channel", "master", "bend"];
+	pub const IDX_CHANNEL: usize = 0;
+	pub const IDX_MASTER: usize = 1;
+	pub const IDX_BEND: usize = 2;
+}
+
+pub mod mpedown_bend_modes {
+	pub const MPEDOWN_BEND_MODES: &[&str] = &["highest", "drop"];
+	pub const IDX_HIGHEST: usize = 0;
+	pub const IDX_DROP: usize = 1;
+}
+
+pub mod paramconv_cmds {
+	pub const PARAMCONV_CMDS: &[&str] = &["map", "unmap", "clear"];
+	pub const IDX_MAP: usize = 0;
+	pub const IDX_UNMAP: usize = 1;
+	pub const IDX_CLEAR: usize = 2;
+}
+
+pub mod param_kinds {
+	pub const PARAM_KINDS: &[&str] = &["cc", "cc14", "rpn", "nrpn"];
+	pub const IDX_CC: usize = 0;
+	pub const IDX_CC14: usize = 1;
+	pub const IDX_RPN: usize = 2;
+	pub const IDX_NRPN: usize = 3;
+}
+
+pub mod netin_cmds {
+	pub const NETIN_CMDS: &[&str] = &["reset"];
+	pub const IDX_RESET: usize = 0;
+}
+
+pub mod netout_cmds {
+	pub const NETOUT_CMDS: &[&str] = &["address"];
+	pub const IDX_ADDRESS: usize = 0;
+}
+
+pub mod rtpmidi_cmds {
+	pub const RTPMIDI_CMDS: &[&str] = &["invite", "bye"];
+	pub const IDX_INVITE: usize = 0;
+	pub const IDX_BYE: usize = 1;
+}
+
+pub mod oscin_cmds {
+	pub const OSCIN_CMDS: &[&str] = &["load", "clear"];
+	pub const IDX_LOAD: usize = 0;
+	pub const IDX_CLEAR: usize = 1;
+}
+
+pub mod oscout_cmds {
+	pub const OSCOUT_CMDS: &[&str] = &["load", "clear", "address"];
+	pub const IDX_LOAD: usize = 0;
+	pub const IDX_CLEAR: usize = 1;
+	pub const IDX_ADDRESS: usize = 2;
+}
+
+pub mod osc_targets {
+	pub const OSC_TARGETS: &[&str] = &["cc", "note", "pitchbend", "aftertouch", "program"];
+	pub const IDX_CC: usize = 0;
+	pub const IDX_NOTE: usize = 1;
+	pub const IDX_PITCHBEND: usize = 2;
+	pub const IDX_AFTERTOUCH: usize = 3;
+	pub const IDX_PROGRAM: usize = 4;
+}
+
+pub mod tcp_cmds {
+	pub const TCP_CMDS: &[&str] = &["kick"];
+	pub const IDX_KICK: usize = 0;
+}
+
+pub mod tcp_protocols {
+	pub const TCP_PROTOCOLS: &[&str] = &["raw", "json", "websocket"];
+	pub const IDX_RAW: usize = 0;
+	pub const IDX_JSON: usize = 1;
+	pub const IDX_WEBSOCKET: usize = 2;
+}
+
+pub mod message_kinds {
+	pub const MESSAGE_KINDS: &[&str] = &["note_on", "note_off", "poly_pressure", "cc", "program", "channel_pressure", "pitch_bend", "raw"];
+	pub const IDX_NOTE_ON: usize = 0;
+	pub const IDX_NOTE_OFF: usize = 1;
+	pub const IDX_POLY_PRESSURE: usize = 2;
+	pub const IDX_CC: usize = 3;
+	pub const IDX_PROGRAM: usize = 4;
+	pub const IDX_CHANNEL_PRESSURE: usize = 5;
+	pub const IDX_PITCH_BEND: usize = 6;
+	pub const IDX_RAW: usize = 7;
+}
+
+pub mod pipein_cmds {
+	pub const PIPEIN_CMDS: &[&str] = &["format"];
+	pub const IDX_FORMAT: usize = 0;
+}
+
+pub mod pipeout_cmds {
+	pub const PIPEOUT_CMDS: &[&str] = &["format"];
+	pub const IDX_FORMAT: usize = 0;
+}
+
+pub mod pipe_formats {
+	pub const PIPE_FORMATS: &[&str] = &["raw", "text"];
+	pub const IDX_RAW: usize = 0;
+	pub const IDX_TEXT: usize = 1;
+}
+
+pub mod serial_cmds {
+	pub const SERIAL_CMDS: &[&str] = &["baud", "running"];
+	pub const IDX_BAUD: usize = 0;
+	pub const IDX_RUNNING: usize = 1;
+}
+
+pub mod event_kinds {
+	pub const EVENT_KINDS: &[&str] = &["processors", "connections", "ports", "messages"];
+	pub const IDX_PROCESSORS: usize = 0;
+	pub const IDX_CONNECTIONS: usize = 1;
+	pub const IDX_PORTS: usize = 2;
+	pub const IDX_MESSAGES: usize = 3;
+}
+
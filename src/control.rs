@@ -0,0 +1,256 @@
+use crate::json::Json;
+use crate::utils::Id;
+use crate::consts::event_kinds::*;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// clients that send a line this long, or don't read their responses, get dropped
+const MAX_BUFFER: usize = 1 << 20;
+
+/// one line from a client: either a plain command, or `{"id": ..., "command": "..."}`, where the id (anything at all)
+/// is sent back with the response so it can be matched up
+pub struct Request {
+    pub client: u64,
+    pub id: Option<Json>,
+    pub command: Option<String>, // None if the line couldn't be understood, which still needs answering in turn
+}
+
+struct Client {
+    id: u64,
+    stream: UnixStream,
+    inbuf: Vec<u8>,
+    outbuf: Vec<u8>,
+    closed: bool,
+    /// from EVENT_KINDS, apart from messages
+    events: Vec<usize>,
+    /// processors whose messages are wanted
+    taps: Vec<Id>,
+}
+impl Client {
+    fn wants(&self, kind: usize, processor: Option<Id>) -> bool {
+        match processor {
+            Some(id) if kind == IDX_MESSAGES => self.taps.contains(&id),
+            _ => self.events.contains(&kind)
+        }
+    }
+    fn flush(&mut self) {
+        while !self.outbuf.is_empty() {
+            match self.stream.write(&self.outbuf) {
+                Ok(len) if len > 0 => {
+                    self.outbuf.drain(..len);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                _ => {
+                    self.closed = true;
+                    break
+                }
+            }
+        }
+        if self.outbuf.len() > MAX_BUFFER {
+            self.closed = true
+        }
+    }
+}
+
+/// a unix socket that takes the same commands as the shell, one per line, and answers each with a line of json
+pub struct ControlServer {
+    listener: UnixListener,
+    path: String,
+    clients: Vec<Client>,
+    next_id: u64,
+}
+impl ControlServer {
+    pub fn bind(path: &str) -> io::Result<ControlServer> {
+        // a socket left behind by a copy of mmm that didn't get to tidy up. one that's still in use is left alone
+        let stale = std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) && UnixStream::connect(path).is_err();
+        if stale {
+            std::fs::remove_file(path)?
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(ControlServer {
+            listener,
+            path: String::from(path),
+            clients: Vec::new(),
+            next_id: 0
+        })
+    }
+    pub fn path(&self) -> &str { &self.path }
+    pub fn client_count(&self) -> usize { self.clients.len() }
+
+    /// accepts new clients and returns any complete lines from them
+    pub fn poll(&mut self) -> Vec<Request> {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client {
+                    id: self.next_id, stream,
+                    inbuf: Vec::new(),
+                    outbuf: Vec::new(),
+                    closed: false,
+                    events: Vec::new(),
+                    taps: Vec::new()
+                });
+                self.next_id += 1
+            }
+        }
+        let mut ret = Vec::new();
+        let mut buf = [0; 4096];
+        for c in &mut self.clients {
+            loop {
+                match c.stream.read(&mut buf) {
+                    Ok(0) => {
+                        c.closed = true;
+                        break
+                    }
+                    Ok(len) => c.inbuf.extend(&buf[..len]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => {
+                        c.closed = true;
+                        break
+                    }
+                }
+            }
+            while let Some(end) = c.inbuf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = c.inbuf.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim();
+                if line.is_empty() {
+                    continue
+                }
+                let (id, command) = parse_request(line);
+                ret.push(Request { client: c.id, id, command })
+            }
+            if c.inbuf.len() > MAX_BUFFER {
+                c.closed = true
+            }
+            c.flush()
+        }
+        self.clients.retain(|c| !c.closed);
+        ret
+    }
+    pub fn respond(&mut self, client: u64, response: &Json) {
+        if let Some(c) = self.clients.iter_mut().find(|c| c.id == client) {
+            c.outbuf.extend(format!("{}\n", response).as_bytes());
+            c.flush()
+        }
+    }
+
+    pub fn subscribe(&mut self, client: u64, events: &[usize], taps: &[Id]) {
+        if let Some(c) = self.clients.iter_mut().find(|c| c.id == client) {
+            for e in events {
+                if !c.events.contains(e) {
+                    c.events.push(*e)
+                }
+            }
+            for t in taps {
+                if !c.taps.contains(t) {
+                    c.taps.push(*t)
+                }
+            }
+        }
+    }
+    /// with nothing given, unsubscribes from everything
+    pub fn unsubscribe(&mut self, client: u64, events: &[usize], taps: &[Id]) {
+        if let Some(c) = self.clients.iter_mut().find(|c| c.id == client) {
+            if events.is_empty() && taps.is_empty() {
+                c.events.clear();
+                c.taps.clear()
+            }
+            else {
+                c.events.retain(|e| !events.contains(e));
+                c.taps.retain(|t| !taps.contains(t))
+            }
+        }
+    }
+    /// for processors that have gone, so their ids can be reused without the new ones being tapped
+    pub fn untap(&mut self, id: Id) {
+        for c in &mut self.clients {
+            c.taps.retain(|t| *t != id)
+        }
+    }
+    /// whether anyone would get an event. worth checking before making one, since messages come thick and fast
+    pub fn wants(&self, kind: usize, processor: Option<Id>) -> bool {
+        self.clients.iter().any(|c| c.wants(kind, processor))
+    }
+    /// sends an event to everyone subscribed to its kind, or for messages, everyone tapping the processor
+    pub fn send_event(&mut self, kind: usize, processor: Option<Id>, event: &Json) {
+        let line = format!("{}\n", event);
+        for c in &mut self.clients {
+            if c.wants(kind, processor) {
+                c.outbuf.extend(line.as_bytes());
+                c.flush()
+            }
+        }
+    }
+}
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// (id, command), either of which might be missing
+fn parse_request(line: &str) -> (Option<Json>, Option<String>) {
+    if !line.starts_with('{') {
+        return (None, Some(String::from(line)))
+    }
+    match Json::parse(line) {
+        Some(j) => (j.get("id").cloned(), j.get("command").and_then(|c| c.as_str()).map(String::from)),
+        None => (None, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn requests_and_responses() {
+        let path = std::env::temp_dir().join(format!("mmm-control-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut server = ControlServer::bind(path).unwrap();
+
+        let mut a = UnixStream::connect(path).unwrap();
+        let b = UnixStream::connect(path).unwrap();
+        a.write_all(b"list\n{\"id\":7,\"command\":\"ls\"}\n{\"id\":8}\n{nonsense\n").unwrap();
+        let mut requests = Vec::new();
+        for _ in 0..100 {
+            requests.extend(server.poll());
+            if requests.len() == 4 {
+                break
+            }
+            crate::utils::sleep_ms(10)
+        }
+        assert_eq!(server.client_count(), 2);
+        let summary: Vec<_> = requests.iter().map(|r| (r.client, r.id.clone(), r.command.as_deref())).collect();
+        assert_eq!(summary, vec![(0, None, Some("list")), (0, Some(Json::from(7)), Some("ls")), (0, Some(Json::from(8)), None), (0, None, None)]);
+
+        server.respond(0, &Json::object([("success", Json::from(true))]));
+        server.respond(0, &Json::object([("success", Json::from(false))]));
+        let mut lines = BufReader::new(a).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"success\":true}");
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"success\":false}");
+
+        server.subscribe(1, &[IDX_PROCESSORS], &[3]);
+        assert!(server.wants(IDX_PROCESSORS, None) && server.wants(IDX_MESSAGES, Some(3)));
+        assert!(!server.wants(IDX_PORTS, None) && !server.wants(IDX_MESSAGES, Some(4)));
+        server.send_event(IDX_PROCESSORS, None, &Json::object([("event", Json::from("processor_removed"))]));
+        server.send_event(IDX_MESSAGES, Some(4), &Json::object([("event", Json::from("message"))]));
+        server.send_event(IDX_MESSAGES, Some(3), &Json::object([("event", Json::from("message"))]));
+        let mut lines_b = BufReader::new(b).lines();
+        assert_eq!(lines_b.next().unwrap().unwrap(), "{\"event\":\"processor_removed\"}");
+        assert_eq!(lines_b.next().unwrap().unwrap(), "{\"event\":\"message\"}");
+        server.untap(3);
+        assert!(server.wants(IDX_PROCESSORS, None) && !server.wants(IDX_MESSAGES, Some(3)));
+        server.unsubscribe(1, &[], &[]);
+        assert!(!server.wants(IDX_PROCESSORS, None));
+
+        drop(lines_b);
+        // still in use, so it can't be taken over
+        assert!(ControlServer::bind(path).is_err());
+        drop(server);
+        assert!(std::fs::metadata(path).is_err())
+    }
+}
@@ -0,0 +1,349 @@
+use crate::utils::*;
+use crate::consts::message_kinds::*;
+use crate::stream::StreamParser;
+use std::fmt;
+
+/// arrays and objects nested deeper than this don't parse, rather than running out of stack
+const MAX_DEPTH: usize = 64;
+
+/// just enough json for exchanging messages with scripts and browsers. objects keep their keys in order
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+impl Json {
+    pub fn parse(s: &str) -> Option<Json> {
+        let mut p = Parser { s: s.as_bytes(), pos: 0, depth: 0 };
+        let ret = p.value()?;
+        p.skip_ws();
+        if p.pos == s.len() { Some(ret) } else { None }
+    }
+    pub fn object<'a>(fields: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (String::from(k), v)).collect())
+    }
+
+    /// None if it isn't an object or doesn't have the key
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None
+        }
+    }
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(a) => Some(a),
+            _ => None
+        }
+    }
+    /// whole numbers in the range only
+    pub fn as_int(&self, min: i64, max: i64) -> Option<i64> {
+        match self {
+            Json::Number(n) if n.fract() == 0.0 && (min as f64..=max as f64).contains(n) => Some(*n as i64),
+            _ => None
+        }
+    }
+}
+impl From<&str> for Json {
+    fn from(s: &str) -> Json { Json::Str(String::from(s)) }
+}
+impl From<String> for Json {
+    fn from(s: String) -> Json { Json::Str(s) }
+}
+impl From<i64> for Json {
+    fn from(n: i64) -> Json { Json::Number(n as f64) }
+}
+impl From<bool> for Json {
+    fn from(b: bool) -> Json { Json::Bool(b) }
+}
+impl From<Vec<Json>> for Json {
+    fn from(a: Vec<Json>) -> Json { Json::Array(a) }
+}
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            // json has no way of writing these
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::Str(s) => write_str(f, s),
+            Json::Array(a) => {
+                write!(f, "[")?;
+                for (idx, v) in a.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ",")?
+                    }
+                    write!(f, "{}", v)?
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (idx, (k, v)) in fields.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ",")?
+                    }
+                    write_str(f, k)?;
+                    write!(f, ":{}", v)?
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+    depth: usize, // arrays and objects we're inside
+}
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        while self.s.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1
+        }
+    }
+    /// skips whitespace first
+    fn eat(&mut self, b: u8) -> bool {
+        self.skip_ws();
+        let ret = self.s.get(self.pos) == Some(&b);
+        if ret {
+            self.pos += 1
+        }
+        ret
+    }
+    fn literal(&mut self, word: &str, value: Json) -> Option<Json> {
+        if self.s[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Some(value)
+        }
+        else {
+            None
+        }
+    }
+    fn value(&mut self) -> Option<Json> {
+        self.skip_ws();
+        if matches!(self.s.get(self.pos), Some(b'{' | b'[')) {
+            if self.depth == MAX_DEPTH {
+                return None
+            }
+            self.depth += 1;
+            let ret = self.container();
+            self.depth -= 1;
+            return ret
+        }
+        match self.s.get(self.pos)? {
+            b'"' => self.string().map(Json::Str),
+            b't' => self.literal("true", Json::Bool(true)),
+            b'f' => self.literal("false", Json::Bool(false)),
+            b'n' => self.literal("null", Json::Null),
+            _ => {
+                let start = self.pos;
+                while self.s.get(self.pos).is_some_and(|b| b"+-0123456789.eE".contains(b)) {
+                    self.pos += 1
+                }
+                std::str::from_utf8(&self.s[start..self.pos]).ok()?.parse().ok().map(Json::Number)
+            }
+        }
+    }
+    /// an object or array, with pos on the opening bracket
+    fn container(&mut self) -> Option<Json> {
+        match self.s[self.pos] {
+            b'{' => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if self.eat(b'}') {
+                    return Some(Json::Object(fields))
+                }
+                loop {
+                    self.skip_ws();
+                    let key = self.string()?;
+                    if !self.eat(b':') {
+                        return None
+                    }
+                    fields.push((key, self.value()?));
+                    if !self.eat(b',') {
+                        break
+                    }
+                }
+                self.eat(b'}').then_some(Json::Object(fields))
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.eat(b']') {
+                    return Some(Json::Array(items))
+                }
+                loop {
+                    items.push(self.value()?);
+                    if !self.eat(b',') {
+                        break
+                    }
+                }
+                self.eat(b']').then_some(Json::Array(items))
+            }
+            _ => unreachable!()
+        }
+    }
+    fn string(&mut self) -> Option<String> {
+        if self.s.get(self.pos) != Some(&b'"') {
+            return None
+        }
+        self.pos += 1;
+        let mut ret = Vec::new();
+        loop {
+            let b = *self.s.get(self.pos)?;
+            self.pos += 1;
+            match b {
+                b'"' => return String::from_utf8(ret).ok(),
+                b'\\' => {
+                    let c = match self.s.get(self.pos)? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\x08',
+                        b'f' => '\x0c',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // characters outside the basic plane come as a surrogate pair
+                            if (0xd800..0xdc00).contains(&code) && self.s[self.pos + 1..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.checked_sub(0xdc00)? & 0x3ff)
+                            }
+                            char::from_u32(code)?
+                        }
+                        _ => return None
+                    };
+                    self.pos += 1;
+                    ret.extend(c.encode_utf8(&mut [0; 4]).as_bytes())
+                }
+                _ => ret.push(b)
+            }
+        }
+    }
+    /// the 4 digits after `\u`, leaving pos on the last one
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.s.get(self.pos + 1..self.pos + 5)?;
+        self.pos += 4;
+        u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
+
+/// like `{"type":"note_on","channel":1,"note":60,"velocity":100}`. pitch bend goes from -8192 to 8191, and system
+/// messages are sent as `{"type":"raw","data":[248]}`
+pub fn from_message(m: &MidiMessage) -> Json {
+    let n = |b: u8| Json::from(b as i64);
+    let kind = |idx: usize| Json::from(MESSAGE_KINDS[idx]);
+    match m {
+        MidiMessage::Channel([s, a, b]) if s & 0xf0 != 0xf0 => {
+            let channel = ("channel", n((s & 0xf) + 1));
+            match s & 0xf0 {
+                0x80 => Json::object([("type", kind(IDX_NOTE_OFF)), channel, ("note", n(*a)), ("velocity", n(*b))]),
+                0x90 => Json::object([("type", kind(IDX_NOTE_ON)), channel, ("note", n(*a)), ("velocity", n(*b))]),
+                0xa0 => Json::object([("type", kind(IDX_POLY_PRESSURE)), channel, ("note", n(*a)), ("pressure", n(*b))]),
+                0xb0 => Json::object([("type", kind(IDX_CC)), channel, ("controller", n(*a)), ("value", n(*b))]),
+                _ => {
+                    let value = ((*b as i64) << 7 | *a as i64) - 8192;
+                    Json::object([("type", kind(IDX_PITCH_BEND)), channel, ("value", Json::from(value))])
+                }
+            }
+        }
+        MidiMessage::ChannelSmall([s, a]) if s & 0xf0 == 0xc0 => Json::object([("type", kind(IDX_PROGRAM)), ("channel", n((s & 0xf) + 1)), ("program", n(*a))]),
+        MidiMessage::ChannelSmall([s, a]) if s & 0xf0 == 0xd0 => Json::object([("type", kind(IDX_CHANNEL_PRESSURE)), ("channel", n((s & 0xf) + 1)), ("pressure", n(*a))]),
+        _ => Json::object([("type", kind(IDX_RAW)), ("data", Json::Array(m.to_bytes().into_iter().map(n).collect()))])
+    }
+}
+/// the opposite of from_message. None if anything's missing or out of range
+pub fn to_message(j: &Json) -> Option<MidiMessage> {
+    let kind = j.get("type")?.as_str()?;
+    let field = |name: &str| j.get(name)?.as_int(0, 127).map(|v| v as u8);
+    let channel = || j.get("channel")?.as_int(1, 16).map(|c| c as u8);
+    Some(match MESSAGE_KINDS.iter().position(|k| *k == kind)? {
+        IDX_NOTE_ON => MidiMessage::note_on(channel()?, field("note")?, field("velocity")?),
+        IDX_NOTE_OFF => MidiMessage::note_off(channel()?, field("note")?).with_velocity(field("velocity").unwrap_or(0)),
+        IDX_POLY_PRESSURE => MidiMessage::poly_pressure(channel()?, field("note")?, field("pressure")?),
+        IDX_CC => MidiMessage::control_change(channel()?, field("controller")?, field("value")?),
+        IDX_PROGRAM => MidiMessage::program_change(channel()?, field("program")?),
+        IDX_CHANNEL_PRESSURE => MidiMessage::channel_pressure(channel()?, field("pressure")?),
+        IDX_PITCH_BEND => MidiMessage::pitch_bend(channel()?, (j.get("value")?.as_int(-8192, 8191)? + 8192) as u16),
+        IDX_RAW => {
+            let data = j.get("data")?.as_array()?.iter().map(|b| b.as_int(0, 255).map(|b| b as u8)).collect::<Option<Vec<_>>>()?;
+            // has to be exactly one complete message
+            let mut parser = StreamParser::default();
+            let (last, rest) = data.split_last()?;
+            if !parser.feed(rest).is_empty() {
+                return None
+            }
+            parser.push(*last)?
+        }
+        _ => unreachable!()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_print() {
+        let j = Json::parse(r#" {"a": [1, -2.5e1, true, null], "b\né🎹": {}, "c": []} "#).unwrap();
+        assert_eq!(j.get("a").unwrap().as_array().unwrap()[1], Json::Number(-25.0));
+        assert_eq!(j.to_string(), "{\"a\":[1,-25,true,null],\"b\\né🎹\":{},\"c\":[]}");
+        assert_eq!(Json::parse(&j.to_string()), Some(j));
+        for bad in ["", "{", "[1,]", "{\"a\" 1}", "tru", "\"abc", "1 2"] {
+            assert_eq!(Json::parse(bad), None, "{}", bad)
+        }
+        // nested too deep to be anything we'd want, and would run out of stack if followed all the way down
+        assert_eq!(Json::parse(&"[".repeat(500_000)), None);
+        let nested = |n| format!("{}{}", "[".repeat(n), "]".repeat(n));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_some());
+        assert_eq!(Json::parse(&nested(MAX_DEPTH + 1)), None);
+    }
+
+    #[test]
+    fn messages() {
+        let msgs = [
+            MidiMessage::note_on(2, 60, 100), MidiMessage::note_off(2, 60), MidiMessage::poly_pressure(1, 3, 4),
+            MidiMessage::control_change(16, 74, 0), MidiMessage::program_change(1, 5), MidiMessage::channel_pressure(1, 9),
+            MidiMessage::pitch_bend(1, 0), MidiMessage::SystemRealtime(0xf8), MidiMessage::SystemCommon(vec![0xf0, 1, 0xf7])
+        ];
+        for m in msgs {
+            assert_eq!(to_message(&from_message(&m)), Some(m))
+        }
+        assert_eq!(from_message(&MidiMessage::pitch_bend(1, 0)).to_string(), r#"{"type":"pitch_bend","channel":1,"value":-8192}"#);
+        let parse = |s| to_message(&Json::parse(s).unwrap());
+        assert_eq!(parse(r#"{"type":"note_off","channel":1,"note":60}"#), Some(MidiMessage::note_off(1, 60)));
+        assert_eq!(parse(r#"{"type":"cc","channel":17,"controller":1,"value":1}"#), None);
+        assert_eq!(parse(r#"{"type":"raw","data":[144,60]}"#), None);
+    }
+}
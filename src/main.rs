@@ -2,14 +2,31 @@ use std::collections::HashMap;
 use utils::*;
 use shell::*;
 use std::sync::{Arc, Mutex};
-use shell_words::split;
+use shell_words::{split, join, quote};
 use consts::*;
 use lazy_static::{lazy_static, initialize};
 use std::time::Instant;
+use json::Json;
+use options::Options;
 
+#[macro_use]
+mod shell;
 mod utils;
 mod processors;
-mod shell;
+mod smf;
+mod params;
+mod ump;
+mod rtp;
+mod osc;
+mod json;
+mod ws;
+mod text;
+mod stream;
+mod options;
+#[cfg(unix)]
+mod control;
+#[cfg(unix)]
+mod signals;
 #[allow(dead_code)]
 mod consts;
 
@@ -19,29 +36,100 @@ lazy_static! {
 
 fn main() {
     initialize(&INIT_TIME);
-    let mm = MidiManager::new();
-    mm.run()
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}\n{}", e, options::USAGE);
+            std::process::exit(2)
+        }
+    };
+    if options.help {
+        outln!("{}", options::USAGE);
+        return
+    }
+    if let Some(name) = &options.client_name {
+        set_client_name(name)
+    }
+    if let Some(path) = &options.log {
+        if let Err(e) = set_log(path) {
+            eprintln!("failed to open log {}: {}", path, e);
+            std::process::exit(1)
+        }
+    }
+    #[cfg(unix)]
+    signals::install(options.headless);
+
+    let mut mm = MidiManager::new(!options.headless);
+    if let Some(path) = options.patch {
+        let patch = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("failed to read patch {}: {}", path, e);
+            std::process::exit(1)
+        });
+        mm.load_patch(&patch);
+        mm.patch = Some(path)
+    }
+    if let Some(path) = options.control {
+        if let Err(e) = mm.control(&[path]) {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    }
+    std::process::exit(mm.run())
 }
 
+/// what a command gives back: data for control clients (the shell just gets what was printed), or what went wrong
+type CommandResult = std::result::Result<Json, String>;
+
 struct MidiManager {
     map: HashMap<Id, Box<dyn MidiIO>>,
     id_ctr: Id,
     returned_ids: Vec<Id>,
+    exiting: bool,
+    /// what mmm exits with. set by `exit CODE`
+    exit_code: i32,
+    #[cfg(unix)]
+    control: Option<control::ControlServer>,
+    /// the control client whose command is being run, if it isn't the shell's
+    requester: Option<u64>,
+    /// set when `control` closes or replaces the server, since the rest of a batch of requests came from the old one
+    control_replaced: bool,
+    /// input and output port names as of the last check, for port events
+    ports: (Vec<String>, Vec<String>),
+    ports_checked: Instant,
+    /// the file given with --patch, for reloading
+    patch: Option<String>,
     msgr: Arc<Mutex<Messenger>>,
-    shell_thread: std::thread::JoinHandle<()>
+    /// None when headless
+    shell_thread: Option<std::thread::JoinHandle<()>>
 }
 impl MidiManager {
-    pub fn new() -> MidiManager {
-        let (msgr, shell_thread) = Shell::new();
+    pub fn new(shell: bool) -> MidiManager {
+        let (msgr, shell_thread) = if shell {
+            let (msgr, thread) = Shell::spawn();
+            (msgr, Some(thread))
+        }
+        else {
+            (Arc::new(Mutex::new(Messenger::new())), None)
+        };
         MidiManager {
             map: HashMap::new(),
             id_ctr: 0,
             returned_ids: Vec::new(),
+            exiting: false,
+            exit_code: 0,
+            #[cfg(unix)]
+            control: None,
+            requester: None,
+            control_replaced: false,
+            ports: (Vec::new(), Vec::new()),
+            ports_checked: Instant::now(),
+            patch: None,
             msgr,
             shell_thread
         }
     }
-    pub fn run(mut self) {
+    /// returns the exit code
+    pub fn run(mut self) -> i32 {
         loop {
             self.update_map();
             let mut msgr = self.msgr.lock().unwrap();
@@ -52,10 +140,142 @@ impl MidiManager {
                     break
                 }
             }
+            if self.poll_control() {
+                break
+            }
+            if self.poll_signals() {
+                break
+            }
+        }
+        self.shutdown();
+        self.exit_code
+    }
+
+    /// stops taking commands, lets go of anything held, and tears every processor down
+    fn shutdown(&mut self) {
+        #[cfg(unix)]
+        {
+            self.control = None;
+        }
+        let mut msgr = self.msgr.lock().unwrap();
+        msgr.exiting = true;
+        // a shell waiting on a command that will never be run can stop waiting
+        msgr.shell_wait = false;
+        drop(msgr);
+
+        for id in self.release_order() {
+            let vp = self.map.get_mut(&id).unwrap();
+            if vp.can_read() {
+                let mut msgs = vp.release();
+                // anything passed on from further up. sources are left alone, since they'd only bring in something new
+                if vp.can_write() {
+                    msgs.extend(vp.read())
+                }
+                if !msgs.is_empty() {
+                    let outputs = vp.list_outputs().to_vec();
+                    self.route(&outputs, &msgs)
+                }
+            }
+        }
+        let mut ids: Vec<Id> = self.map.keys().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            self.map.remove(&id).unwrap().delete()
+        }
+
+        if let Some(thread) = self.shell_thread.take() {
+            // the shell only checks in between lines, so if it's waiting on one, it's left behind
+            for _ in 0..10 {
+                if thread.is_finished() {
+                    break
+                }
+                sleep_ms(10)
+            }
+            if thread.is_finished() {
+                thread.join().unwrap()
+            }
+            else {
+                restore_terminal()
+            }
+        }
+    }
+    /// every processor before the ones it outputs to, so what's let go further up gets passed along further down.
+    /// where processors are in a loop, the lowest id goes first
+    fn release_order(&self) -> Vec<Id> {
+        let mut ids: Vec<Id> = self.map.keys().copied().collect();
+        ids.sort_unstable();
+        let mut inputs: HashMap<Id, usize> = ids.iter().map(|id| (*id, 0)).collect();
+        let outputs = |id: &Id| if self.map[id].can_read() { self.map[id].list_outputs() } else { &[] };
+        for id in &ids {
+            for o in outputs(id) {
+                if let Some(n) = inputs.get_mut(o) {
+                    *n += 1
+                }
+            }
+        }
+        let mut ret = Vec::new();
+        while !ids.is_empty() {
+            let id = ids.remove(ids.iter().position(|id| inputs[id] == 0).unwrap_or(0));
+            for o in outputs(&id) {
+                if let Some(n) = inputs.get_mut(o) {
+                    *n = n.saturating_sub(1)
+                }
+            }
+            ret.push(id)
         }
-        self.shell_thread.join().unwrap();
+        ret
     }
 
+    /// runs each line of a patch file, carrying on past any that fail
+    fn load_patch(&mut self, patch: &str) {
+        for (n, line) in patch.lines().enumerate() {
+            let result = if line.trim_start().starts_with('.') {
+                Err(String::from("metacommands aren't supported in patch files"))
+            }
+            else {
+                self.run_command(line)
+            };
+            if let Err(e) = result {
+                outln!("line {}: {}", n + 1, e)
+            }
+        }
+    }
+    /// starts again from the patch file, if there is one. nothing is removed if the file can't be read
+    fn reload(&mut self) {
+        let Some(path) = self.patch.clone() else {
+            outln!("no patch to reload");
+            return
+        };
+        let patch = match std::fs::read_to_string(&path) {
+            Ok(p) => p,
+            Err(e) => {
+                outln!("failed to read patch {}: {}", path, e);
+                return
+            }
+        };
+        outln!("reloading {}", path);
+        let mut ids: Vec<Id> = self.map.keys().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let _ = self.remove(&[id.to_string()]);
+        }
+        self.load_patch(&patch)
+    }
+    /// true if it's time to exit
+    #[cfg(unix)]
+    fn poll_signals(&mut self) -> bool {
+        if signals::reload_requested() {
+            self.reload()
+        }
+        if signals::terminate_requested() {
+            outln!("exiting");
+            self.exiting = true;
+        }
+        self.exiting
+    }
+    #[cfg(not(unix))]
+    fn poll_signals(&mut self) -> bool { false }
+
     fn next_id(&mut self) -> Id {
         if let Some(i) = self.returned_ids.pop() {
             i
@@ -72,181 +292,478 @@ impl MidiManager {
     
     fn update_map(&mut self) {
         //sleep_ms(1);
-        let all_ids: Vec<Id> = self.map.keys().map(|id| *id).collect();
+        let all_ids: Vec<Id> = self.map.keys().copied().collect();
         for id in all_ids {
             let vp = self.map.get_mut(&id).unwrap();
             if vp.can_read() {
                 let msgs = vp.read();
                 if !msgs.is_empty() {
                     let outputs = vp.list_outputs().to_vec();
-                    for o in outputs {
-                        if let Some(dest) = self.map.get_mut(&o) {
-                            dest.write(&msgs)
-                        }
-                    }
+                    self.tap(id, "out", &msgs);
+                    self.route(&outputs, &msgs)
                 }
             }
         }
     }
-    fn do_command(&mut self, command: &str) -> bool {
-        let mut exiting = false;
-        if let Ok(parts) = split(command) {
-            use commands::*;
-            if let Some(idx) = shortened_keyword_match(&parts[0], COMMANDS) {
-                match idx {
-                    IDX_EXIT => exiting = true,
-
-                    IDX_LIST | IDX_LS => self.list(),
-                    IDX_RENAME => self.rename(&parts[1..]),
-
-                    IDX_INIT | IDX_NEW => self.new_vp(&parts[1..]),
-                    IDX_REMOVE => self.remove(&parts[1..]),
-
-                    IDX_CONNECT => self.connect(&parts[1..], false),
-                    IDX_DISCONNECT => self.connect(&parts[1..], true),
-                    IDX_CFG => self.cfg(&parts[1..]),
-
-                    IDX_INPUTS => list_inputs(),
-                    IDX_OUTPUTS => self.outputs(&parts[1..]),
-                    _ => unreachable!()
-                }
+    fn route(&mut self, outputs: &[Id], msgs: &[MidiMessage]) {
+        for o in outputs {
+            if let Some(dest) = self.map.get_mut(o) {
+                dest.write(msgs);
+                self.tap(*o, "in", msgs)
             }
-            else {
-                println!("command not found! valid commands are:");
-                for c in COMMANDS {
-                    println!("\t{}", c)
-                }
+        }
+    }
+
+    /// tells subscribed control clients about something. the event is only made if someone wants it
+    #[cfg(unix)]
+    fn event(&mut self, kind: usize, processor: Option<Id>, event: impl FnOnce() -> Json) {
+        if let Some(c) = self.control.as_mut() {
+            if c.wants(kind, processor) {
+                c.send_event(kind, processor, &event())
             }
         }
+    }
+    #[cfg(not(unix))]
+    fn event(&mut self, _kind: usize, _processor: Option<Id>, _event: impl FnOnce() -> Json) { }
+    /// messages going in to or out of a processor, for anyone tapping it
+    fn tap(&mut self, id: Id, direction: &str, msgs: &[MidiMessage]) {
+        for m in msgs {
+            self.event(event_kinds::IDX_MESSAGES, Some(id), || Json::object([
+                ("event", Json::from("message")),
+                ("id", Json::from(id as i64)),
+                ("direction", Json::from(direction)),
+                ("message", json::from_message(m))
+            ]))
+        }
+    }
+    /// ports only get looked at while someone's subscribed to them, and not too often, since it's not cheap
+    fn check_ports(&mut self) {
+        #[cfg(unix)]
+        let wanted = self.control.as_ref().is_some_and(|c| c.wants(event_kinds::IDX_PORTS, None));
+        #[cfg(not(unix))]
+        let wanted = false;
+        if !wanted || self.ports_checked.elapsed().as_millis() < 1000 {
+            return
+        }
+        self.ports_checked = Instant::now();
+        let ports = port_names();
+        let mut events = Vec::new();
+        for (direction, old, new) in [("input", &self.ports.0, &ports.0), ("output", &self.ports.1, &ports.1)] {
+            for p in new.iter().filter(|p| !old.contains(p)) {
+                events.push(("port_appeared", direction, p.clone()))
+            }
+            for p in old.iter().filter(|p| !new.contains(p)) {
+                events.push(("port_disappeared", direction, p.clone()))
+            }
+        }
+        self.ports = ports;
+        for (event, direction, name) in events {
+            self.event(event_kinds::IDX_PORTS, None, || Json::object([
+                ("event", Json::from(event)),
+                ("direction", Json::from(direction)),
+                ("name", Json::from(name))
+            ]))
+        }
+    }
+    /// runs a command from the shell, then lets the shell carry on
+    fn do_command(&mut self, command: &str) -> bool {
+        if let Err(e) = self.run_command(command) {
+            outln!("{}", e)
+        }
         let mut msgr = self.msgr.lock().unwrap();
-        msgr.exiting = exiting;
+        msgr.exiting = self.exiting;
         msgr.shell_wait = false;
-        exiting
+        self.exiting
+    }
+    /// answers anything that's come in on the control socket. true if one of the commands was exit
+    #[cfg(unix)]
+    fn poll_control(&mut self) -> bool {
+        self.check_ports();
+        let requests = match self.control.as_mut() {
+            Some(c) => c.poll(),
+            None => return false
+        };
+        self.control_replaced = false;
+        for r in requests {
+            self.requester = Some(r.client);
+            let (result, printed) = match &r.command {
+                Some(command) => capture(|| self.run_command(command)),
+                None => (Err(String::from("invalid request")), String::new())
+            };
+            self.requester = None;
+            if self.control_replaced {
+                // nobody left to answer, and the client ids now belong to the new server
+                return self.exiting
+            }
+            let mut response = Vec::new();
+            if let Some(id) = r.id {
+                response.push(("id", id))
+            }
+            match result {
+                Ok(data) => {
+                    response.push(("success", Json::from(true)));
+                    response.push(("data", data))
+                }
+                Err(e) => {
+                    response.push(("success", Json::from(false)));
+                    response.push(("error", Json::from(e)))
+                }
+            }
+            response.push(("output", Json::from(printed.lines().map(Json::from).collect::<Vec<_>>())));
+            if let Some(c) = self.control.as_mut() {
+                c.respond(r.client, &Json::object(response))
+            }
+            if self.exiting {
+                return true
+            }
+        }
+        false
     }
+    #[cfg(not(unix))]
+    fn poll_control(&mut self) -> bool { false }
 
-    fn cfg(&mut self, args: &[String]) {
-        if args.len() < 1 {
-            println!("cfg command requires at least 1 argument")
+    fn run_command(&mut self, command: &str) -> CommandResult {
+        let parts = split(command).map_err(|e| format!("failed to parse command: {}", e))?;
+        if parts.is_empty() {
+            return Ok(Json::Null)
         }
-        else {
-            if let Some(id) = self.find_by_id_or_name(&args[0]) {
-                let vp = self.map.get_mut(&id).unwrap();
-                vp.cfg(args)
+        log(&format!("> {}\n", command));
+        use commands::*;
+        match shortened_keyword_match(&parts[0], COMMANDS) {
+            Some(IDX_EXIT) => {
+                if let Some(code) = parts.get(1) {
+                    self.exit_code = code.parse().map_err(|_| format!("invalid exit code {}", code))?
+                }
+                self.exiting = true;
+                Ok(Json::Null)
+            }
+
+            Some(IDX_LIST | IDX_LS) => Ok(self.list()),
+            Some(IDX_RENAME) => self.rename(&parts[1..]),
+
+            Some(IDX_INIT | IDX_NEW) => self.new_vp(&parts[1..]),
+            Some(IDX_REMOVE) => self.remove(&parts[1..]),
+
+            Some(IDX_CONNECT) => self.connect(&parts[1..], false),
+            Some(IDX_DISCONNECT) => self.connect(&parts[1..], true),
+            Some(IDX_CFG) => self.cfg(&parts[1..]),
+
+            Some(IDX_INPUTS) => list_inputs(),
+            Some(IDX_OUTPUTS) => self.outputs(&parts[1..]),
+            Some(IDX_SAVE) => self.save(&parts[1..]),
+            Some(IDX_CONTROL) => self.control(&parts[1..]),
+            Some(IDX_SUBSCRIBE) => self.subscribe(&parts[1..], false),
+            Some(IDX_UNSUBSCRIBE) => self.subscribe(&parts[1..], true),
+            Some(_) => unreachable!(),
+            None => {
+                let mut e = String::from("command not found! valid commands are:");
+                for c in COMMANDS {
+                    e += &format!("\n\t{}", c)
+                }
+                Err(e)
             }
         }
     }
-    fn remove(&mut self, args: &[String]) {
+
+    fn cfg(&mut self, args: &[String]) -> CommandResult {
+        if args.is_empty() {
+            return Err(String::from("cfg command requires at least 1 argument"))
+        }
+        let id = self.find_by_id_or_name(&args[0])?;
+        let vp = self.map.get_mut(&id).unwrap();
+        vp.cfg(&args[1..]);
+        Ok(Json::Null)
+    }
+    fn remove(&mut self, args: &[String]) -> CommandResult {
         if args.len() != 1 {
-            println!("remove command requires 1 argument")
+            return Err(String::from("remove command requires 1 argument"))
         }
-        else {
-            if let Some(id) = self.find_by_id_or_name(&args[0]) {
-                self.map.remove(&id);
-                for (_, vp) in self.map.iter_mut() {
-                    vp.rem_output(id)
-                }
+        let id = self.find_by_id_or_name(&args[0])?;
+        let mut vp = self.map.remove(&id).unwrap();
+        if vp.can_read() {
+            let msgs = vp.release();
+            if !msgs.is_empty() {
+                self.route(vp.list_outputs(), &msgs)
             }
         }
+        // its connections go with it, both ways
+        let mut dropped: Vec<(Id, Id)> = Vec::new();
+        if vp.can_read() {
+            dropped.extend(vp.list_outputs().iter().map(|dst| (id, *dst)))
+        }
+        for (src, other) in self.map.iter_mut() {
+            if other.can_read() && other.list_outputs().contains(&id) {
+                other.rem_output(id);
+                dropped.push((*src, id))
+            }
+        }
+        for (src, dst) in dropped {
+            self.connection_event(src, dst, false)
+        }
+        self.event(event_kinds::IDX_PROCESSORS, None, || Json::object([
+            ("event", Json::from("processor_removed")),
+            ("id", Json::from(id as i64)),
+            ("name", Json::from(vp.get_name()))
+        ]));
+        #[cfg(unix)]
+        if let Some(c) = self.control.as_mut() {
+            c.untap(id)
+        }
+        vp.delete();
+        Ok(Json::Null)
     }
-    fn outputs(&mut self, args: &[String]) {
+    fn outputs(&mut self, args: &[String]) -> CommandResult {
         if args.len() != 1 {
-            println!("outputs command requires 1 argument")
+            return Err(String::from("outputs command requires 1 argument"))
         }
-        else {
-            if let Some(id) = self.find_by_id_or_name(&args[0]) {
-                let vp = self.map.get(&id).unwrap();
-                if vp.can_read() {
-                    for out in vp.list_outputs() {
-                        println!("{}", out)
-                    }
-                }
+        let id = self.find_by_id_or_name(&args[0])?;
+        let vp = self.map.get(&id).unwrap();
+        let mut ret = Vec::new();
+        if vp.can_read() {
+            for out in vp.list_outputs() {
+                outln!("{}", out);
+                ret.push(Json::from(*out as i64))
             }
         }
+        Ok(Json::from(ret))
     }
-    fn connect(&mut self, args: &[String], disconnect: bool) {
+    fn connect(&mut self, args: &[String], disconnect: bool) -> CommandResult {
         if args.len() != 2 {
-            println!("(dis)connect command requires 2 arguments")
+            return Err(String::from("(dis)connect command requires 2 arguments"))
+        }
+        let id_src = self.find_by_id_or_name(&args[0])?;
+        let id_dst = self.find_by_id_or_name(&args[1])?;
+        if !self.map.get(&id_dst).unwrap().can_write() {
+            return Err(String::from("destination processor does not support writing"))
+        }
+        let src = self.map.get_mut(&id_src).unwrap();
+        if !src.can_read() {
+            return Err(String::from("source processor does not support reading"))
+        }
+        if src.list_outputs().contains(&id_dst) != disconnect {
+            return Ok(Json::Null)
+        }
+        if disconnect {
+            src.rem_output(id_dst)
         }
         else {
-            if let (Some(id_src), Some(id_dst)) = (self.find_by_id_or_name(&args[0]), self.find_by_id_or_name(&args[1])) {
-                if self.map.get(&id_dst).unwrap().can_write() {
-                    let src = self.map.get_mut(&id_src).unwrap();
-                    if src.can_read() {
-                        if disconnect {
-                            src.rem_output(id_dst)
-                        }
-                        else {
-                            src.add_output(id_dst)
-                        }
-                    }
-                    else { println!("source processor does not support reading") }
-                }
-                else { println!("destination processor does not support writing") }
-            }
-            else {
-                println!("one or more processors not found")
-            }
+            src.add_output(id_dst)
         }
+        self.connection_event(id_src, id_dst, !disconnect);
+        Ok(Json::Null)
     }
-    fn new_vp(&mut self, args: &[String]) {
-        if let Some(idx) = shortened_keyword_match(&args[0], consts::processors::PROCESSORS) {
-            match consts::processor_ctors::PROCESSOR_CTORS[idx](args[1].clone(), &args[2..]) {
-                Ok(vp) => {
-                    let id = self.next_id();
-                    self.map.insert(id, vp);
-                }
-                Err(e) => println!("failed to create processor: {:?}", e)
-            }
+    fn connection_event(&mut self, src: Id, dst: Id, connected: bool) {
+        self.event(event_kinds::IDX_CONNECTIONS, None, || Json::object([
+            ("event", Json::from("connection_changed")),
+            ("src", Json::from(src as i64)),
+            ("dst", Json::from(dst as i64)),
+            ("connected", Json::from(connected))
+        ]))
+    }
+    fn new_vp(&mut self, args: &[String]) -> CommandResult {
+        if args.len() < 2 {
+            return Err(String::from("new command requires at least 2 arguments"))
         }
-        else {
-            println!("no match for {}", args[0])
+        let idx = shortened_keyword_match(&args[0], consts::processors::PROCESSORS).ok_or(format!("no match for {}", args[0]))?;
+        match consts::processor_ctors::PROCESSOR_CTORS[idx](args[1].clone(), &args[2..]) {
+            Ok(vp) => {
+                let id = self.next_id();
+                let (name, display_name) = (vp.get_name(), vp.get_display_name());
+                self.map.insert(id, vp);
+                self.event(event_kinds::IDX_PROCESSORS, None, || Json::object([
+                    ("event", Json::from("processor_created")),
+                    ("id", Json::from(id as i64)),
+                    ("name", Json::from(name)),
+                    ("display_name", Json::from(display_name))
+                ]));
+                Ok(Json::object([("id", Json::from(id as i64))]))
+            }
+            Err(e) => Err(format!("failed to create processor: {:?}", e))
         }
     }
-    fn list(&self) {
+    fn list(&self) -> Json {
+        let mut ret = Vec::new();
         for (id, vp) in &self.map {
-            println!("{}: {}", id, vp.get_display_name())
+            outln!("{}: {}", id, vp.get_display_name());
+            let outputs = if vp.can_read() { vp.list_outputs().iter().map(|o| Json::from(*o as i64)).collect() } else { Vec::new() };
+            ret.push(Json::object([
+                ("id", Json::from(*id as i64)),
+                ("name", Json::from(vp.get_name())),
+                ("display_name", Json::from(vp.get_display_name())),
+                ("outputs", Json::from(outputs))
+            ]))
         }
+        Json::from(ret)
     }
-    fn find_by_id_or_name(&self, needle: &str) -> Option<Id> {
+    fn find_by_id_or_name(&self, needle: &str) -> std::result::Result<Id, String> {
         if let Ok(id) = needle.parse() {
             if self.map.contains_key(&id) {
-                return Some(id);
+                return Ok(id);
             }
         }
         else {
             let (ids, names): (Vec<Id>, Vec<String>) = self.map.iter().map(|(id, vp)| (id, vp.get_name())).unzip();
             if let Some(idx) = shortened_keyword_match(needle, names) {
-                return Some(ids[idx])
+                return Ok(ids[idx])
+            }
+        }
+        Err(format!("could not find processor {}", needle))
+    }
+    /// writes a file of commands that recreate the current setup when run with .load
+    fn save(&self, args: &[String]) -> CommandResult {
+        if args.len() != 1 {
+            return Err(String::from("save command requires 1 argument"))
+        }
+        let mut ids: Vec<Id> = self.map.keys().copied().collect();
+        ids.sort_unstable();
+        let mut lines = Vec::new();
+        for id in &ids {
+            let vp = &self.map[id];
+            let mut init = vp.init_args();
+            init.insert(1, vp.get_name());
+            lines.push(format!("new {}", join(init)))
+        }
+        for id in &ids {
+            let vp = &self.map[id];
+            for l in vp.cfg_lines() {
+                lines.push(format!("cfg {} {}", quote(&vp.get_name()), join(l)))
             }
         }
-        println!("could not find processor {}", needle);
-        None
+        for id in &ids {
+            let vp = &self.map[id];
+            if vp.can_read() {
+                for o in vp.list_outputs() {
+                    if let Some(dst) = self.map.get(o) {
+                        lines.push(format!("connect {} {}", quote(&vp.get_name()), quote(&dst.get_name())))
+                    }
+                }
+            }
+        }
+        lines.push(String::new());
+        std::fs::write(&args[0], lines.join("\n")).map_err(|e| format!("failed to save: {}", e))?;
+        Ok(Json::Null)
     }
-    fn rename(&mut self, args: &[String]) {
+    fn rename(&mut self, args: &[String]) -> CommandResult {
         if args.len() != 2 {
-            println!("rename command requires 2 arguments")
+            return Err(String::from("rename command requires 2 arguments"))
         }
-        else {
-            if let Some(id) = self.find_by_id_or_name(&args[0]) {
-                let vp = self.map.get_mut(&id).unwrap();
-                vp.set_name(&args[1])
+        let id = self.find_by_id_or_name(&args[0])?;
+        let vp = self.map.get_mut(&id).unwrap();
+        let old = vp.get_name();
+        vp.set_name(&args[1]);
+        self.event(event_kinds::IDX_PROCESSORS, None, || Json::object([
+            ("event", Json::from("processor_renamed")),
+            ("id", Json::from(id as i64)),
+            ("old_name", Json::from(old)),
+            ("name", Json::from(args[1].as_str()))
+        ]));
+        Ok(Json::Null)
+    }
+    /// starts listening on a unix socket, or stops with `off`. with no arguments, says what it's doing
+    #[cfg(unix)]
+    fn control(&mut self, args: &[String]) -> CommandResult {
+        match args {
+            [] => match &self.control {
+                Some(c) => {
+                    outln!("listening on {}, {} clients connected", c.path(), c.client_count());
+                    Ok(Json::object([("path", Json::from(c.path())), ("clients", Json::from(c.client_count() as i64))]))
+                }
+                None => {
+                    outln!("not listening");
+                    Ok(Json::Null)
+                }
             }
+            [off] if off == "off" => {
+                self.control = None;
+                self.control_replaced = true;
+                Ok(Json::Null)
+            }
+            [path] => {
+                // dropping the old one first, in case it's the same path
+                self.control = None;
+                self.control_replaced = true;
+                let c = control::ControlServer::bind(path).map_err(|e| format!("failed to listen on {}: {}", path, e))?;
+                self.control = Some(c);
+                Ok(Json::Null)
+            }
+            _ => Err(String::from("control command takes at most 1 argument"))
         }
     }
+    #[cfg(not(unix))]
+    fn control(&mut self, _args: &[String]) -> CommandResult {
+        Err(String::from("control sockets aren't supported on this platform"))
+    }
+    /// `subscribe [processors] [connections] [ports] [messages NAME_OR_ID]...`, for the control client asking. with no
+    /// arguments, subscribes to everything but messages, or unsubscribes from everything
+    #[cfg(unix)]
+    fn subscribe(&mut self, args: &[String], unsubscribe: bool) -> CommandResult {
+        use event_kinds::*;
+        let client = self.requester.ok_or(String::from("subscriptions are only for control clients"))?;
+        let mut events = Vec::new();
+        let mut taps = Vec::new();
+        let mut args = args.iter();
+        while let Some(a) = args.next() {
+            match shortened_keyword_match(a, EVENT_KINDS) {
+                Some(IDX_MESSAGES) => {
+                    let p = args.next().ok_or(String::from("messages requires a processor"))?;
+                    taps.push(self.find_by_id_or_name(p)?)
+                }
+                Some(e) => events.push(e),
+                None => {
+                    let mut e = format!("no event kind {}! valid kinds are:", a);
+                    for k in EVENT_KINDS {
+                        e += &format!("\n\t{}", k)
+                    }
+                    return Err(e)
+                }
+            }
+        }
+        if !unsubscribe && events.is_empty() && taps.is_empty() {
+            events = vec![IDX_PROCESSORS, IDX_CONNECTIONS, IDX_PORTS]
+        }
+        // ports are compared with how they were when the first subscriber came along
+        let watching_ports = self.control.as_ref().is_some_and(|c| c.wants(IDX_PORTS, None));
+        if !unsubscribe && !watching_ports && events.contains(&IDX_PORTS) {
+            self.ports = port_names();
+            self.ports_checked = Instant::now()
+        }
+        let c = self.control.as_mut().ok_or(String::from("not listening for control clients"))?;
+        if unsubscribe {
+            c.unsubscribe(client, &events, &taps)
+        }
+        else {
+            c.subscribe(client, &events, &taps)
+        }
+        Ok(Json::Null)
+    }
+    #[cfg(not(unix))]
+    fn subscribe(&mut self, _args: &[String], _unsubscribe: bool) -> CommandResult {
+        Err(String::from("subscriptions are only for control clients"))
+    }
+}
 
-
+fn list_inputs() -> CommandResult {
+    let input = midir::MidiInput::new("mmm").map_err(|_| String::from("failed to create midi input"))?;
+    let ports = input.ports();
+    let mut ret = Vec::new();
+    for (idx, p) in ports.iter().enumerate() {
+        let name = input.port_name(p).unwrap_or(String::from("failed to retrieve port name"));
+        outln!("{}: {}", idx, name);
+        ret.push(Json::object([("index", Json::from(idx as i64)), ("name", Json::from(name))]))
+    }
+    Ok(Json::from(ret))
 }
 
-fn list_inputs() {
-    if let Ok(input) = midir::MidiInput::new("mmm") {
-        let ports = input.ports();
-    
-        for (idx, p) in ports.iter().enumerate() {
-            let name = input.port_name(&p).unwrap_or(String::from("failed to retrieve port name"));
-            println!("{}: {}", idx, name)
-        }
+/// every input and output port there is
+fn port_names() -> (Vec<String>, Vec<String>) {
+    let mut ret = (Vec::new(), Vec::new());
+    if let Ok(input) = midir::MidiInput::new(&client_name()) {
+        ret.0 = input.ports().iter().filter_map(|p| input.port_name(p).ok()).collect()
     }
-    else { println!("failed to create midi input") }
+    if let Ok(output) = midir::MidiOutput::new(&client_name()) {
+        ret.1 = output.ports().iter().filter_map(|p| output.port_name(p).ok()).collect()
+    }
+    ret
 }
 
 pub trait MidiIO {
@@ -262,9 +779,114 @@ pub trait MidiIO {
     fn set_name(&mut self, name: &str);
 
     fn cfg(&mut self, command: &[String]);
+    /// the arguments to `new` that would recreate this processor, minus the name
+    fn init_args(&self) -> Vec<String>;
+    /// cfg commands that bring a freshly created processor in line with this one
+    fn cfg_lines(&self) -> Vec<Vec<String>> { Vec::new() }
+    /// called just before the processor is removed. anything returned is sent to its outputs, so held notes can be let go
+    fn release(&mut self) -> Vec<MidiMessage> { Vec::new() }
     
     fn write(&mut self, messages: &[MidiMessage]);
     fn read(&mut self) -> Vec<MidiMessage>;
 
-    fn delete(self);
+    /// teardown, once the processor has been released, whether it's being removed or mmm is exiting
+    fn delete(self: Box<Self>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_lets_go_of_held_notes() {
+        let path = std::env::temp_dir().join(format!("mmm-shutdown-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut mm = MidiManager::new(false);
+        // created furthest down first, so going by id would tear the chain down in the wrong order
+        // and humanize holding on to what it's been sent for a good while
+        for cmd in [
+            &format!("new pipeout out {}", path), "new channelfilter filter 1", "new humanize late 60000 0 1", "new latch hold latch",
+            "connect hold late", "connect late filter", "connect filter out"
+        ] {
+            mm.run_command(cmd).unwrap();
+        }
+        assert_eq!(mm.release_order(), vec![3, 2, 1, 0]);
+
+        let hold = mm.find_by_id_or_name("hold").unwrap();
+        mm.map.get_mut(&hold).unwrap().write(&[MidiMessage::note_on(1, 60, 100), MidiMessage::note_off(1, 60)]);
+        mm.update_map();
+        mm.update_map();
+        assert!(mm.run_command("exit 3").is_ok());
+        assert_eq!(mm.run(), 3);
+
+        let written = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(written, "note_on ch=1 n=60 v=100\nnote_off ch=1 n=60 v=0\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removing_drops_connections() {
+        use std::io::{BufRead, BufReader, Write};
+        let path = std::env::temp_dir().join(format!("mmm-remove-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut mm = MidiManager::new(false);
+        for cmd in [&format!("control {}", path), "new channelfilter a 1", "new channelfilter b 1", "new channelfilter c 1", "connect a b", "connect b c"] {
+            mm.run_command(cmd).unwrap();
+        }
+        let mut client = std::os::unix::net::UnixStream::connect(path).unwrap();
+        client.write_all(b"subscribe connections processors\n").unwrap();
+        for _ in 0..100 {
+            mm.poll_control();
+            if mm.control.as_ref().unwrap().wants(event_kinds::IDX_CONNECTIONS, None) {
+                break
+            }
+            sleep_ms(10)
+        }
+        mm.run_command("remove b").unwrap();
+
+        let mut lines = BufReader::new(client).lines().skip(1).map(|l| l.unwrap());
+        assert_eq!(lines.next().unwrap(), r#"{"event":"connection_changed","src":1,"dst":2,"connected":false}"#);
+        assert_eq!(lines.next().unwrap(), r#"{"event":"connection_changed","src":0,"dst":1,"connected":false}"#);
+        assert_eq!(lines.next().unwrap(), r#"{"event":"processor_removed","id":1,"name":"b"}"#);
+    }
+
+    #[test]
+    fn closing_control_mid_batch() {
+        use std::io::Write;
+        let path = std::env::temp_dir().join(format!("mmm-close-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut mm = MidiManager::new(false);
+        mm.run_command(&format!("control {}", path)).unwrap();
+        let mut client = std::os::unix::net::UnixStream::connect(path).unwrap();
+        client.write_all(b"control off\nsubscribe\n").unwrap();
+        for _ in 0..100 {
+            mm.poll_control();
+            if mm.control.is_none() {
+                break
+            }
+            sleep_ms(10)
+        }
+        assert!(mm.control.is_none());
+
+        // the rest of the batch isn't run against the new server either
+        let other = format!("{}.2", path);
+        mm.run_command(&format!("control {}", path)).unwrap();
+        let mut client = std::os::unix::net::UnixStream::connect(path).unwrap();
+        client.write_all(format!("control {}\nsubscribe\n", other).as_bytes()).unwrap();
+        for _ in 0..100 {
+            mm.poll_control();
+            if mm.control.as_ref().unwrap().path() == other {
+                break
+            }
+            sleep_ms(10)
+        }
+        let _new_client = std::os::unix::net::UnixStream::connect(&other).unwrap();
+        mm.poll_control();
+        assert!(!mm.control.as_ref().unwrap().wants(event_kinds::IDX_PROCESSORS, None));
+
+        mm.run_command("control off").unwrap();
+        mm.requester = Some(0);
+        assert!(mm.subscribe(&[], false).is_err());
+    }
 }
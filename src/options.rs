@@ -0,0 +1,70 @@
+pub const USAGE: &str = "usage: mmm [--patch FILE] [--headless] [--client-name NAME] [--log FILE] [--control PATH]
+
+    --patch FILE        run the commands in FILE at startup, and again on SIGHUP
+    --headless          no prompt. drive it with --control, --patch and signals instead
+    --client-name NAME  the name mmm's ports are listed under, instead of MidiMappingManager
+    --log FILE          append everything printed to FILE, with timestamps
+    --control PATH      listen for commands on a unix socket at PATH, same as `control PATH`
+    --help              print this and exit";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub patch: Option<String>,
+    pub headless: bool,
+    pub client_name: Option<String>,
+    pub log: Option<String>,
+    pub control: Option<String>,
+    pub help: bool,
+}
+impl Options {
+    /// args without the program name. `--opt=VALUE` works as well as `--opt VALUE`
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+        let mut ret = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (opt, inline) = match arg.split_once('=') {
+                Some((o, v)) => (String::from(o), Some(String::from(v))),
+                None => (arg, None)
+            };
+            let flag = |inline: Option<String>| match inline {
+                Some(_) => Err(format!("{} doesn't take a value", opt)),
+                None => Ok(true)
+            };
+            let mut value = |inline: Option<String>| inline.or_else(|| args.next()).ok_or(format!("{} requires a value", opt));
+            match opt.as_str() {
+                "--patch" => ret.patch = Some(value(inline)?),
+                "--client-name" => ret.client_name = Some(value(inline)?),
+                "--log" => ret.log = Some(value(inline)?),
+                "--control" => ret.control = Some(value(inline)?),
+                "--headless" => ret.headless = flag(inline)?,
+                "--help" | "-h" => ret.help = flag(inline)?,
+                _ => return Err(format!("unknown option {}", opt))
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|a| String::from(*a)))
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(parse(&[]), Ok(Options::default()));
+        assert_eq!(parse(&["--headless", "--patch", "rack.mmm", "--client-name=rack", "--control", "/run/mmm.sock"]), Ok(Options {
+            patch: Some(String::from("rack.mmm")),
+            headless: true,
+            client_name: Some(String::from("rack")),
+            control: Some(String::from("/run/mmm.sock")),
+            ..Options::default()
+        }));
+        assert_eq!(parse(&["--log"]), Err(String::from("--log requires a value")));
+        assert_eq!(parse(&["--headless=yes"]), Err(String::from("--headless doesn't take a value")));
+        assert_eq!(parse(&["rack.mmm"]), Err(String::from("unknown option rack.mmm")));
+    }
+}
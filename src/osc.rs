@@ -0,0 +1,421 @@
+use crate::utils::*;
+use crate::consts::osc_targets::*;
+use std::fs::read_to_string;
+
+const BUNDLE_TAG: &[u8] = b"#bundle\0";
+/// longer patterns never match anything, so a sender can't keep us busy with them
+const MAX_PATTERN_LEN: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Blob(Vec<u8>),
+    Bool(bool),
+    Long(i64),
+    Double(f64),
+}
+impl Arg {
+    /// None for anything that isn't a number. true and false count as 1 and 0
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Arg::Int(i) => Some(*i as f64),
+            Arg::Float(f) => Some(*f as f64),
+            Arg::Long(l) => Some(*l as f64),
+            Arg::Double(d) => Some(*d),
+            Arg::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            _ => None
+        }
+    }
+    fn tag(&self) -> char {
+        match self {
+            Arg::Int(_) => 'i',
+            Arg::Float(_) => 'f',
+            Arg::Str(_) => 's',
+            Arg::Blob(_) => 'b',
+            Arg::Bool(true) => 'T',
+            Arg::Bool(false) => 'F',
+            Arg::Long(_) => 'h',
+            Arg::Double(_) => 'd',
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub addr: String,
+    pub args: Vec<Arg>,
+}
+impl Message {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::new();
+        push_str(&mut ret, &self.addr);
+        let tags: String = std::iter::once(',').chain(self.args.iter().map(Arg::tag)).collect();
+        push_str(&mut ret, &tags);
+        for arg in &self.args {
+            match arg {
+                Arg::Int(i) => ret.extend(i.to_be_bytes()),
+                Arg::Float(f) => ret.extend(f.to_be_bytes()),
+                Arg::Str(s) => push_str(&mut ret, s),
+                Arg::Blob(b) => {
+                    ret.extend((b.len() as i32).to_be_bytes());
+                    ret.extend(b);
+                    pad(&mut ret)
+                }
+                Arg::Bool(_) => {}
+                Arg::Long(l) => ret.extend(l.to_be_bytes()),
+                Arg::Double(d) => ret.extend(d.to_be_bytes()),
+            }
+        }
+        ret
+    }
+    fn parse(bytes: &[u8]) -> Option<Message> {
+        let mut pos = 0;
+        let addr = read_str(bytes, &mut pos)?;
+        if !addr.starts_with('/') {
+            return None
+        }
+        // very old senders leave out the type tags altogether
+        if pos >= bytes.len() {
+            return Some(Message { addr, args: Vec::new() })
+        }
+        let tags = read_str(bytes, &mut pos)?;
+        let mut args = Vec::new();
+        for tag in tags.strip_prefix(',')?.chars() {
+            let arg = match tag {
+                'i' => Arg::Int(i32::from_be_bytes(read_bytes(bytes, &mut pos, 4)?.try_into().unwrap())),
+                'f' => Arg::Float(f32::from_be_bytes(read_bytes(bytes, &mut pos, 4)?.try_into().unwrap())),
+                's' | 'S' => Arg::Str(read_str(bytes, &mut pos)?),
+                'b' => {
+                    let len = i32::from_be_bytes(read_bytes(bytes, &mut pos, 4)?.try_into().unwrap());
+                    let blob = read_bytes(bytes, &mut pos, usize::try_from(len).ok()?)?.to_vec();
+                    pos = pos.next_multiple_of(4);
+                    Arg::Blob(blob)
+                }
+                'T' => Arg::Bool(true),
+                'F' => Arg::Bool(false),
+                'h' => Arg::Long(i64::from_be_bytes(read_bytes(bytes, &mut pos, 8)?.try_into().unwrap())),
+                'd' => Arg::Double(f64::from_be_bytes(read_bytes(bytes, &mut pos, 8)?.try_into().unwrap())),
+                // nil and infinitum have no data, so they can be skipped
+                'N' | 'I' => continue,
+                // anything else has data of a size we don't know, so there's no way past it
+                _ => return None
+            };
+            args.push(arg)
+        }
+        Some(Message { addr, args })
+    }
+}
+
+/// all the messages in a packet, including ones nested in bundles. time tags are ignored, so everything in a bundle
+/// happens straight away
+pub fn parse_packet(bytes: &[u8]) -> Vec<Message> {
+    if !bytes.starts_with(BUNDLE_TAG) {
+        return Message::parse(bytes).into_iter().collect()
+    }
+    let mut ret = Vec::new();
+    // skipping the time tag
+    let mut pos = 16;
+    while let Some(len) = read_bytes(bytes, &mut pos, 4) {
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        match read_bytes(bytes, &mut pos, len) {
+            Some(element) => ret.extend(parse_packet(element)),
+            None => break
+        }
+    }
+    ret
+}
+
+/// matches an address against a pattern, which can have `?` (any character), `*` (any number of characters), `[...]`
+/// (any character in the list, which can have ranges and start with `!` to mean anything not in it) and `{a,b}` (any of
+/// the strings in the list). none of them match across a `/`
+pub fn pattern_matches(pattern: &str, addr: &str) -> bool {
+    if pattern.len() > MAX_PATTERN_LEN {
+        return false
+    }
+    let mut p = pattern.split('/');
+    let mut a = addr.split('/');
+    loop {
+        match (p.next(), a.next()) {
+            (None, None) => return true,
+            (Some(p), Some(a)) if segment_matches(p.as_bytes(), a.as_bytes()) => {}
+            _ => return false
+        }
+    }
+}
+/// works out every position in the address that each part of the pattern could get to, rather than trying each way
+/// in turn, since patterns can come from anyone sending to us and backtracking could take forever
+fn segment_matches(p: &[u8], a: &[u8]) -> bool {
+    let mut reached = vec![false; a.len() + 1];
+    reached[0] = true;
+    let mut i = 0;
+    while i < p.len() {
+        let mut next = vec![false; a.len() + 1];
+        match p[i] {
+            b'*' => {
+                let mut any = false;
+                for (n, r) in next.iter_mut().zip(&reached) {
+                    any |= *r;
+                    *n = any
+                }
+            }
+            b'[' => {
+                let end = match p[i..].iter().position(|c| *c == b']') {
+                    Some(end) => i + end,
+                    None => return false
+                };
+                let (negate, set) = match p[i + 1..end].strip_prefix(b"!") {
+                    Some(set) => (true, set),
+                    None => (false, &p[i + 1..end])
+                };
+                for (j, c) in a.iter().enumerate() {
+                    next[j + 1] = reached[j] && in_set(set, *c) != negate
+                }
+                i = end
+            }
+            b'{' => {
+                let end = match p[i..].iter().position(|c| *c == b'}') {
+                    Some(end) => i + end,
+                    None => return false
+                };
+                for alt in p[i + 1..end].split(|c| *c == b',') {
+                    for j in (0..=a.len()).filter(|j| reached[*j] && a[*j..].starts_with(alt)) {
+                        next[j + alt.len()] = true
+                    }
+                }
+                i = end
+            }
+            c => {
+                for j in 0..a.len() {
+                    next[j + 1] = reached[j] && (c == b'?' || c == a[j])
+                }
+            }
+        }
+        reached = next;
+        i += 1
+    }
+    reached[a.len()]
+}
+/// a list of characters and ranges, like `a-z0-9_`
+fn in_set(set: &[u8], c: u8) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == b'-' {
+            if (set[i]..=set[i + 2]).contains(&c) {
+                return true
+            }
+            i += 3
+        }
+        else {
+            if set[i] == c {
+                return true
+            }
+            i += 1
+        }
+    }
+    false
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Cc(u8, u8), // channel, controller
+    Note(u8, u8), // channel, note
+    PitchBend(u8),
+    Aftertouch(u8),
+    Program(u8),
+}
+impl Target {
+    fn max(&self) -> u16 {
+        match self {
+            Target::PitchBend(_) => 16383,
+            _ => 127
+        }
+    }
+    fn message(self, value: u16) -> MidiMessage {
+        match self {
+            Target::Cc(c, cc) => MidiMessage::control_change(c, cc, value as u8),
+            Target::Note(c, n) if value == 0 => MidiMessage::note_off(c, n),
+            Target::Note(c, n) => MidiMessage::note_on(c, n, value as u8),
+            Target::PitchBend(c) => MidiMessage::pitch_bend(c, value),
+            Target::Aftertouch(c) => MidiMessage::channel_pressure(c, value as u8),
+            Target::Program(c) => MidiMessage::program_change(c, value as u8),
+        }
+    }
+    /// the value, if the message is for this target. note offs have a value of 0
+    fn value_of(self, m: &MidiMessage) -> Option<u16> {
+        let (status, bytes) = match m {
+            MidiMessage::Channel(b) => (b[0], &b[1..]),
+            MidiMessage::ChannelSmall(b) => (b[0], &b[1..]),
+            _ => return None
+        };
+        let channel = (status & 0xf) + 1;
+        match (self, status & 0xf0) {
+            (Target::Cc(c, cc), 0xb0) if c == channel && bytes[0] == cc => Some(bytes[1] as u16),
+            (Target::Note(c, n), 0x80) if c == channel && bytes[0] == n => Some(0),
+            (Target::Note(c, n), 0x90) if c == channel && bytes[0] == n => Some(bytes[1] as u16),
+            (Target::PitchBend(c), 0xe0) if c == channel => Some((bytes[1] as u16) << 7 | bytes[0] as u16),
+            (Target::Aftertouch(c), 0xd0) if c == channel => Some(bytes[0] as u16),
+            (Target::Program(c), 0xc0) if c == channel => Some(bytes[0] as u16),
+            _ => None
+        }
+    }
+}
+
+/// one line of a mapping file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    pub address: String,
+    pub float: bool, // 0 to 1 as a float, rather than the midi value as an int
+    pub target: Target,
+}
+impl Mapping {
+    /// from the first argument, which can be any kind of number whichever type the mapping has
+    pub fn to_midi(&self, msg: &Message) -> Option<MidiMessage> {
+        let x = msg.args.first()?.as_f64()?;
+        let max = self.target.max() as f64;
+        let value = if self.float { x.clamp(0.0, 1.0) * max } else { x.clamp(0.0, max) };
+        Some(self.target.message(value.round() as u16))
+    }
+    pub fn to_osc(&self, m: &MidiMessage) -> Option<Message> {
+        let value = self.target.value_of(m)?;
+        let arg = if self.float {
+            Arg::Float(value as f32 / self.target.max() as f32)
+        }
+        else {
+            Arg::Int(value as i32)
+        };
+        Some(Message { addr: self.address.clone(), args: vec![arg] })
+    }
+}
+
+pub fn load_mappings(file: &str) -> Result<Vec<Mapping>> {
+    parse_mappings(&read_to_string(file)?)
+}
+/// each line is `ADDRESS TYPE TARGET CHANNEL [NUMBER]`, where type is `f` or `i` and target is one of `cc`, `note` (both
+/// with a number), `pitchbend`, `aftertouch` or `program`. empty lines and lines starting with # are ignored
+pub fn parse_mappings(s: &str) -> Result<Vec<Mapping>> {
+    let mut ret = Vec::new();
+    for (idx, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        ret.push(parse_line(line).ok_or(MMMErr::SyntaxError(idx + 1))?)
+    }
+    Ok(ret)
+}
+fn parse_line(line: &str) -> Option<Mapping> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let address = parts.first().filter(|a| a.starts_with('/'))?.to_string();
+    let float = match *parts.get(1)? {
+        "f" => true,
+        "i" => false,
+        _ => return None
+    };
+    let channel: u8 = parts.get(3)?.parse().ok().filter(|c| (1..=16).contains(c))?;
+    let number = || parts.get(4).and_then(|n| n.parse().ok()).filter(|n: &u8| *n < 128);
+    let (target, len) = match shortened_keyword_match(parts.get(2)?, OSC_TARGETS)? {
+        IDX_CC => (Target::Cc(channel, number()?), 5),
+        IDX_NOTE => (Target::Note(channel, number().or_else(|| parse_note(parts.get(4)?))?), 5),
+        IDX_PITCHBEND => (Target::PitchBend(channel), 4),
+        IDX_AFTERTOUCH => (Target::Aftertouch(channel), 4),
+        IDX_PROGRAM => (Target::Program(channel), 4),
+        _ => unreachable!()
+    };
+    if parts.len() != len {
+        return None
+    }
+    Some(Mapping { address, float, target })
+}
+
+fn push_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend(s.as_bytes());
+    buf.push(0);
+    pad(buf)
+}
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0)
+}
+fn read_bytes<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let ret = bytes.get(*pos..pos.checked_add(len)?)?;
+    *pos += len;
+    Some(ret)
+}
+/// strings are null terminated and padded to a multiple of 4 bytes
+fn read_str(bytes: &[u8], pos: &mut usize) -> Option<String> {
+    let len = bytes.get(*pos..)?.iter().position(|b| *b == 0)?;
+    let s = std::str::from_utf8(&bytes[*pos..*pos + len]).ok()?.to_string();
+    *pos = (*pos + len + 1).next_multiple_of(4);
+    Some(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_and_bundles() {
+        let m = Message {
+            addr: String::from("/a/bc"),
+            args: vec![Arg::Int(-3), Arg::Float(0.5), Arg::Str(String::from("four")), Arg::Blob(vec![1, 2]), Arg::Bool(true), Arg::Double(1.5)]
+        };
+        let bytes = m.to_bytes();
+        assert_eq!(&bytes[..16], b"/a/bc\0\0\0,ifsbTd\0");
+        assert_eq!(bytes.len() % 4, 0);
+        assert_eq!(parse_packet(&bytes), vec![m.clone()]);
+
+        let mut bundle = BUNDLE_TAG.to_vec();
+        bundle.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        bundle.extend((bytes.len() as u32).to_be_bytes());
+        bundle.extend(&bytes);
+        let mut inner = bundle.clone();
+        bundle.extend((inner.len() as u32).to_be_bytes());
+        bundle.append(&mut inner);
+        assert_eq!(parse_packet(&bundle), vec![m.clone(), m.clone()]);
+
+        assert!(parse_packet(b"junk").is_empty());
+        assert!(parse_packet(&bytes[..bytes.len() - 4]).is_empty());
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(pattern_matches("/synth/1/cutoff", "/synth/1/cutoff"));
+        assert!(pattern_matches("/synth/*/cutoff", "/synth/12/cutoff"));
+        assert!(!pattern_matches("/synth/*", "/synth/1/cutoff"));
+        assert!(pattern_matches("/synth/?/c*f", "/synth/1/cutoff"));
+        assert!(pattern_matches("/synth/[1-3]/cutoff", "/synth/2/cutoff"));
+        assert!(!pattern_matches("/synth/[!1-3]/cutoff", "/synth/2/cutoff"));
+        assert!(pattern_matches("/synth/1/{res,cutoff}", "/synth/1/cutoff"));
+        assert!(!pattern_matches("/synth/1/{res,cut}", "/synth/1/cutoff"));
+        assert!(pattern_matches("/*/*", "/synth/1"));
+        assert!(!pattern_matches("/synth", "/synth/1"));
+        assert!(!pattern_matches("/synth/[1-3", "/synth/2"));
+
+        // would take forever if every way of matching the stars was tried
+        let stars = format!("/{}b", "*".repeat(1000));
+        assert!(!pattern_matches(&stars, &format!("/{}", "a".repeat(1000))));
+        assert!(pattern_matches(&stars, &format!("/{}b", "a".repeat(1000))));
+        assert!(!pattern_matches(&format!("/{}", "*".repeat(2000)), "/a"));
+    }
+
+    #[test]
+    fn mappings() {
+        let m = parse_mappings("# comment\n/synth/1/cutoff f cc 1 74\n\n/pad/3 i note 10 C4\n/bend f pitchbend 2").unwrap();
+        assert_eq!(m[0].target, Target::Cc(1, 74));
+        assert_eq!(m[1].target, Target::Note(10, 60));
+
+        let osc = |addr: &str, arg| Message { addr: String::from(addr), args: vec![arg] };
+        assert_eq!(m[0].to_midi(&osc("/synth/1/cutoff", Arg::Float(1.0))), Some(MidiMessage::control_change(1, 74, 127)));
+        assert_eq!(m[0].to_midi(&osc("/synth/1/cutoff", Arg::Int(3))), Some(MidiMessage::control_change(1, 74, 127)));
+        assert_eq!(m[1].to_midi(&osc("/pad/3", Arg::Int(0))), Some(MidiMessage::note_off(10, 60)));
+        assert_eq!(m[2].to_midi(&osc("/bend", Arg::Float(0.5))), Some(MidiMessage::pitch_bend(2, 8192)));
+
+        assert_eq!(m[0].to_osc(&MidiMessage::control_change(1, 74, 127)), Some(osc("/synth/1/cutoff", Arg::Float(1.0))));
+        assert_eq!(m[0].to_osc(&MidiMessage::control_change(2, 74, 127)), None);
+        assert_eq!(m[1].to_osc(&MidiMessage::note_on(10, 60, 90)), Some(osc("/pad/3", Arg::Int(90))));
+
+        assert!(matches!(parse_mappings("/a f cc 1 74\n/b f cc 17 74"), Err(MMMErr::SyntaxError(2))));
+        assert!(matches!(parse_mappings("/a f pitchbend 1 3"), Err(MMMErr::SyntaxError(1))));
+    }
+}
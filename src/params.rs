@@ -0,0 +1,240 @@
+use crate::utils::*;
+use crate::consts::param_kinds::*;
+use std::time::Duration;
+
+const DATA_ENTRY: u8 = 6;
+const NRPN_MSB: u8 = 99;
+const NRPN_LSB: u8 = 98;
+const RPN_MSB: u8 = 101;
+const RPN_LSB: u8 = 100;
+/// how long to wait for an lsb before passing on the msb by itself
+const LSB_WAIT: Duration = Duration::from_millis(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamKind {
+    Cc,
+    Cc14, // only controllers 0 to 31, with the lsb on 32 to 63
+    Rpn,
+    Nrpn
+}
+impl ParamKind {
+    pub fn from_kwd(s: &str) -> Option<ParamKind> {
+        Some(match shortened_keyword_match(s, PARAM_KINDS)? {
+            IDX_CC => ParamKind::Cc,
+            IDX_CC14 => ParamKind::Cc14,
+            IDX_RPN => ParamKind::Rpn,
+            IDX_NRPN => ParamKind::Nrpn,
+            _ => unreachable!()
+        })
+    }
+    pub fn kwd(&self) -> &'static str {
+        PARAM_KINDS[match self {
+            ParamKind::Cc => IDX_CC,
+            ParamKind::Cc14 => IDX_CC14,
+            ParamKind::Rpn => IDX_RPN,
+            ParamKind::Nrpn => IDX_NRPN
+        }]
+    }
+    /// exclusive
+    pub fn max_number(&self) -> u16 {
+        match self {
+            ParamKind::Cc => 128,
+            ParamKind::Cc14 => 32,
+            _ => 16384
+        }
+    }
+}
+
+/// a single logical parameter change, however many messages it took
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Param {
+    pub channel: u8,
+    pub kind: ParamKind,
+    pub number: u16,
+    pub value: u16, // always 14 bit, so a 7 bit value is in the top 7 bits
+}
+impl Param {
+    pub fn to_messages(&self) -> Vec<MidiMessage> {
+        let (msb, lsb) = ((self.value >> 7) as u8 & 0x7f, self.value as u8 & 0x7f);
+        let cc = |controller: u8, value: u8| MidiMessage::control_change(self.channel, controller, value);
+        let number = self.number as u8;
+        match self.kind {
+            ParamKind::Cc => vec![cc(number, msb)],
+            ParamKind::Cc14 => vec![cc(number, msb), cc(number + 32, lsb)],
+            ParamKind::Rpn | ParamKind::Nrpn => {
+                let (sel_msb, sel_lsb) = if self.kind == ParamKind::Rpn { (RPN_MSB, RPN_LSB) } else { (NRPN_MSB, NRPN_LSB) };
+                vec![
+                    cc(sel_msb, (self.number >> 7) as u8 & 0x7f), cc(sel_lsb, number & 0x7f),
+                    cc(DATA_ENTRY, msb), cc(DATA_ENTRY + 32, lsb)
+                ]
+            }
+        }
+    }
+}
+
+pub enum Event {
+    Param(Param),
+    Message(MidiMessage)
+}
+
+#[derive(Clone, Copy, Default)]
+struct ChannelState {
+    msb: [Option<u8>; 32],
+    wide: u32, // controllers we've seen an lsb for
+    held: Option<u8>, // a controller with an msb that hasn't been passed on yet, in case its lsb is next
+    held_since: Duration,
+    select: [Option<u8>; 2], // (msb, lsb) of the selected rpn or nrpn
+    nrpn: bool,
+}
+
+/// turns a stream of messages into parameter changes, putting 14 bit cc pairs and rpn/nrpn sequences back together.
+/// controllers 0 to 31 are treated as 7 bit until an lsb turns up for them. an msb is held on to until the next message
+/// on its channel, or until it's waited LSB_WAIT, so a pair comes out as one change
+pub struct ParamAssembler {
+    channels: [ChannelState; 16],
+}
+impl ParamAssembler {
+    pub fn new() -> ParamAssembler {
+        ParamAssembler {
+            channels: [ChannelState::default(); 16]
+        }
+    }
+
+    pub fn feed(&mut self, now: Duration, m: &MidiMessage) -> Vec<Event> {
+        let c = match m.channel() {
+            Some(c) => c,
+            None => return vec![Event::Message(m.clone())]
+        };
+        let mut ret = Vec::new();
+        let state = &mut self.channels[c as usize - 1];
+        if let Some(held) = state.held.take() {
+            match m.as_cc() {
+                Some((cc, v)) if cc == held + 32 => {
+                    state.wide |= 1 << held;
+                    return vec![make_param(c, state, held, v)]
+                }
+                _ => ret.push(make_param(c, state, held, 0))
+            }
+        }
+        let (cc, v) = match m.as_cc() {
+            Some(cc) => cc,
+            None => {
+                ret.push(Event::Message(m.clone()));
+                return ret
+            }
+        };
+        match cc {
+            0..=31 => {
+                state.msb[cc as usize] = Some(v);
+                state.held = Some(cc);
+                state.held_since = now
+            }
+            32..=63 => {
+                // just the fine part changing
+                let msb = cc - 32;
+                state.wide |= 1 << msb;
+                ret.push(make_param(c, state, msb, v))
+            }
+            NRPN_MSB | NRPN_LSB | RPN_MSB | RPN_LSB => {
+                // a new parameter, so any data for the old one is done with
+                state.msb[DATA_ENTRY as usize] = None;
+                let nrpn = matches!(cc, NRPN_MSB | NRPN_LSB);
+                if nrpn != state.nrpn {
+                    state.select = [None; 2];
+                    state.nrpn = nrpn
+                }
+                state.select[if matches!(cc, NRPN_MSB | RPN_MSB) { 0 } else { 1 }] = Some(v);
+                if !nrpn && state.select == [Some(127); 2] {
+                    // rpn null
+                    state.select = [None; 2]
+                }
+            }
+            _ => ret.push(Event::Param(Param { channel: c, kind: ParamKind::Cc, number: cc as u16, value: (v as u16) << 7 }))
+        }
+        ret
+    }
+    /// passes on any msbs that have given up waiting for an lsb
+    pub fn poll(&mut self, now: Duration) -> Vec<Event> {
+        self.release_held(|state| state.held_since + LSB_WAIT <= now)
+    }
+    /// passes on any msbs still waiting for an lsb
+    pub fn flush(&mut self) -> Vec<Event> {
+        self.release_held(|_| true)
+    }
+    fn release_held(&mut self, due: impl Fn(&ChannelState) -> bool) -> Vec<Event> {
+        let mut ret = Vec::new();
+        for (i, state) in self.channels.iter_mut().enumerate() {
+            if due(state) {
+                if let Some(cc) = state.held.take() {
+                    ret.push(make_param(i as u8 + 1, state, cc, 0))
+                }
+            }
+        }
+        ret
+    }
+}
+
+fn make_param(channel: u8, state: &ChannelState, cc: u8, lsb: u8) -> Event {
+    let value = (state.msb[cc as usize].unwrap_or(0) as u16) << 7 | lsb as u16;
+    let wide = state.wide & 1 << cc != 0;
+    let (kind, number) = match state.select {
+        [Some(msb), Some(lsb)] if cc == DATA_ENTRY => {
+            (if state.nrpn { ParamKind::Nrpn } else { ParamKind::Rpn }, (msb as u16) << 7 | lsb as u16)
+        }
+        _ if wide => (ParamKind::Cc14, cc as u16),
+        _ => (ParamKind::Cc, cc as u16)
+    };
+    Event::Param(Param { channel, kind, number, value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(a: &mut ParamAssembler, msgs: &[MidiMessage]) -> Vec<Param> {
+        msgs.iter().flat_map(|m| a.feed(Duration::ZERO, m)).filter_map(|e| match e {
+            Event::Param(p) => Some(p),
+            Event::Message(_) => None
+        }).collect()
+    }
+    fn p(kind: ParamKind, number: u16, value: u16) -> Param {
+        Param { channel: 1, kind, number, value }
+    }
+
+    #[test]
+    fn fourteen_bit_cc() {
+        let mut a = ParamAssembler::new();
+        let cc = |c, v| MidiMessage::control_change(1, c, v);
+        assert_eq!(params(&mut a, &[cc(1, 10), cc(33, 5), cc(1, 11), cc(33, 6), cc(7, 100)]), vec![
+            p(ParamKind::Cc14, 1, 10 << 7 | 5), p(ParamKind::Cc14, 1, 11 << 7 | 6)
+        ]);
+        // the last msb waits to see if there's an lsb coming
+        assert!(a.poll(Duration::from_millis(4)).is_empty());
+        assert_eq!(a.poll(Duration::from_millis(5)).len(), 1);
+        // just the lsb changing
+        assert_eq!(params(&mut a, &[cc(33, 7)]), vec![p(ParamKind::Cc14, 1, 11 << 7 | 7)]);
+        // just the msb changing, which stops waiting as soon as something else comes along
+        assert_eq!(params(&mut a, &[cc(1, 12), MidiMessage::note_on(1, 60, 100)]), vec![p(ParamKind::Cc14, 1, 12 << 7)]);
+        a.feed(Duration::ZERO, &cc(1, 13));
+        assert_eq!(a.flush().len(), 1);
+        assert!(a.flush().is_empty());
+    }
+
+    #[test]
+    fn rpn_and_nrpn() {
+        let mut a = ParamAssembler::new();
+        let cc = |c, v| MidiMessage::control_change(1, c, v);
+        assert_eq!(params(&mut a, &[cc(101, 0), cc(100, 0), cc(6, 12), cc(38, 0), cc(101, 127), cc(100, 127), cc(6, 1), cc(38, 2)]), vec![
+            p(ParamKind::Rpn, 0, 12 << 7), p(ParamKind::Cc14, 6, 1 << 7 | 2)
+        ]);
+        let nrpn = p(ParamKind::Nrpn, 1000, 4000);
+        assert_eq!(params(&mut a, &nrpn.to_messages()), vec![nrpn]);
+    }
+
+    #[test]
+    fn serialise() {
+        let bytes: Vec<Vec<u8>> = p(ParamKind::Cc14, 2, 300).to_messages().iter().map(|m| m.to_bytes()).collect();
+        assert_eq!(bytes, vec![vec![0xb0, 2, 2], vec![0xb0, 34, 44]]);
+        assert_eq!(p(ParamKind::Rpn, 0, 0).to_messages().len(), 4);
+    }
+}
@@ -33,14 +33,14 @@ impl ChannelFilter {
 
     pub fn change_channel(&mut self, args: &[String]) {
         if args.is_empty() {
-            println!("channel number required")
+            outln!("channel number required")
         }
         else {
             if let Ok(channel) = args[0].parse() {
                 self.channel = channel
             }
             else {
-                println!("channel number failed to parse")
+                outln!("channel number failed to parse")
             }
         }
     }
@@ -62,31 +62,32 @@ impl MidiIO for ChannelFilter {
     }
 
     fn cfg(&mut self, command: &[String]) {
-        if !command.is_empty() {
-            println!("channelfilter on channel {}", self.channel)
+        if command.is_empty() {
+            outln!("channelfilter on channel {}", self.channel)
         }
         else {
             match shortened_keyword_match(&command[0], CHANNELFILTER_CMDS) {
                 Some(IDX_CHANNEL) => self.change_channel(&command[1..]),
                 _ => {
-                    println!("command not found! valid commands are:");
+                    outln!("command not found! valid commands are:");
                     for cmd in CHANNELFILTER_CMDS {
-                        println!("\t{}", cmd)
+                        outln!("\t{}", cmd)
                     }
                 }
             }
         }
     }
+    fn init_args(&self) -> Vec<String> { vec![String::from("channelfilter"), self.channel.to_string()] }
 
     fn write(&mut self, messages: &[MidiMessage]) {
         self.buf.extend(messages.iter().skip_while(|m| {
             m.channel().map(|c| c != self.channel).unwrap_or(false)
-        }).map(|m| m.clone()))
+        }).cloned())
     }
     fn read(&mut self) -> Vec<MidiMessage> {
         let replacement = Vec::new();
         std::mem::replace(&mut self.buf, replacement)
     }
     
-    fn delete(self) { }
+    fn delete(self: Box<Self>) { }
 }
@@ -7,6 +7,7 @@ type MessageBuf = Arc<Mutex<Vec<MidiMessage>>>;
 pub struct MidiIn {
     _connection: MidiInputConnection<MessageBuf>,
     buf: MessageBuf,
+    idx: usize,
     name: String,
     port_name: String,
     outputs: Vec<Id>
@@ -15,7 +16,7 @@ impl MidiIn {
     fn new(idx: usize, name: String) -> Result<MidiIn> {
         let buf = Arc::new(Mutex::new(Vec::new()));
 
-        let input = MidiInput::new(CLIENT_NAME)?;
+        let input = MidiInput::new(&client_name())?;
         let ports = input.ports();
         let port = &ports[idx];
         let port_name = input.port_name(port)?;
@@ -23,11 +24,11 @@ impl MidiIn {
         let _connection = input.connect(
             &ports[idx],
             &name,
-            |ts, bytes, buf| process_msg(ts, bytes, buf),
+            process_msg,
             Arc::clone(&buf)
         )?;
         Ok(MidiIn {
-            _connection, buf, name, port_name,
+            _connection, buf, idx, name, port_name,
             outputs: Vec::new()
         })
     }
@@ -58,7 +59,8 @@ impl MidiIO for MidiIn {
         self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
     }
 
-    fn cfg(&mut self, _command: &[String]) { println!("n/a") }
+    fn cfg(&mut self, _command: &[String]) { outln!("n/a") }
+    fn init_args(&self) -> Vec<String> { vec![String::from("input"), self.idx.to_string()] }
 
     fn write(&mut self, _messages: &[MidiMessage]) { unreachable!() }
     fn read(&mut self) -> Vec<MidiMessage> {
@@ -67,7 +69,7 @@ impl MidiIO for MidiIn {
         replace(&mut *buf, replacement)
     }
     
-    fn delete(self) {
+    fn delete(self: Box<Self>) {
         self._connection.close();
     }
 }
@@ -85,7 +87,7 @@ pub struct MidiOut {
 impl MidiOut {
     fn new(name: String) -> Result<MidiOut> {
 
-        let output = MidiOutput::new(CLIENT_NAME)?;
+        let output = MidiOutput::new(&client_name())?;
         let port = output.create_virtual(&name)?;
 
         Ok(MidiOut {
@@ -100,7 +102,7 @@ impl MidiIO for MidiOut {
     fn can_read(&self) -> bool { false }
     fn can_write(&self) -> bool { true }
 
-    fn get_name(&self) -> String { format!("{}", self.name)}
+    fn get_name(&self) -> String { self.name.clone() }
     fn get_display_name(&self) -> String { format!("{} (output)", self.name)}
     fn set_name(&mut self, name: &str) { self.name = name.into() }
 
@@ -108,7 +110,8 @@ impl MidiIO for MidiOut {
     fn add_output(&mut self, _id: Id) { unreachable!() }
     fn rem_output(&mut self, _id: Id) { unreachable!() }
 
-    fn cfg(&mut self, _command: &[String]) { println!("n/a") }
+    fn cfg(&mut self, _command: &[String]) { outln!("n/a") }
+    fn init_args(&self) -> Vec<String> { vec![String::from("output")] }
 
     fn write(&mut self, messages: &[MidiMessage]) {
         for m in messages {
@@ -117,5 +120,8 @@ impl MidiIO for MidiOut {
     }
     fn read(&mut self) -> Vec<MidiMessage> { unreachable!() }
 
-    fn delete(self) {}
+    fn delete(self: Box<Self>) {
+        // takes the virtual port down with it
+        self.port.close();
+    }
 }
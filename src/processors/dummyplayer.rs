@@ -34,14 +34,14 @@ impl DummyPlayer {
 
     pub fn change_channel(&mut self, args: &[String]) {
         if args.is_empty() {
-            println!("channel number required")
+            outln!("channel number required")
         }
         else {
             if let Ok(channel) = args[0].parse() {
                 self.channel = channel
             }
             else {
-                println!("channel number failed to parse")
+                outln!("channel number failed to parse")
             }
         }
     }
@@ -63,21 +63,22 @@ impl MidiIO for DummyPlayer {
     }
 
     fn cfg(&mut self, command: &[String]) {
-        if !command.is_empty() {
-            println!("dummyplayer on channel {}", self.channel)
+        if command.is_empty() {
+            outln!("dummyplayer on channel {}", self.channel)
         }
         else {
             match shortened_keyword_match(&command[0], CHANNELFILTER_CMDS) {
                 Some(IDX_CHANNEL) => self.change_channel(&command[1..]),
                 _ => {
-                    println!("command not found! valid commands are:");
+                    outln!("command not found! valid commands are:");
                     for cmd in CHANNELFILTER_CMDS {
-                        println!("\t{}", cmd)
+                        outln!("\t{}", cmd)
                     }
                 }
             }
         }
     }    
+    fn init_args(&self) -> Vec<String> { vec![String::from("dummyplayer"), self.channel.to_string()] }
 
     fn write(&mut self, _messages: &[MidiMessage]) { unreachable!() }
     fn read(&mut self) -> Vec<MidiMessage> {
@@ -92,5 +93,5 @@ impl MidiIO for DummyPlayer {
         ret
     }
     
-    fn delete(self) { }
+    fn delete(self: Box<Self>) { }
 }
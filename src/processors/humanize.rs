@@ -0,0 +1,222 @@
+use crate::utils::*;
+use crate::consts::humanize_cmds::*;
+use crate::MidiIO;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// a minute is already far more than anyone would want, and keeps due times well away from overflowing
+const MAX_TIMING: u64 = 60_000;
+const MAX_VELOCITY: u64 = 127;
+
+pub struct Humanize {
+    timing: u64, // maximum delay in ms
+    velocity: u64,
+    name: String,
+    rng: Rng,
+    seed: Option<u64>, // None if seeded from the time, which isn't worth saving
+    queue: Vec<(Duration, MidiMessage)>, // sorted by due time
+    last_due: HashMap<(u8, u8), Duration>, // (channel, note) -> when the last event for that note goes out
+    outputs: Vec<Id>,
+}
+impl Humanize {
+    fn new(timing: u64, velocity: u64, seed: Option<u64>, name: String) -> Humanize {
+        Humanize {
+            timing, velocity, seed, name,
+            rng: seed.map(Rng::new).unwrap_or_else(Rng::from_time),
+            queue: Vec::new(),
+            last_due: HashMap::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !(2..=3).contains(&args.len()) {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            let timing = args[0].parse()?;
+            let velocity = args[1].parse()?;
+            if timing > MAX_TIMING || velocity > MAX_VELOCITY {
+                return Err(MMMErr::ArgError)
+            }
+            let seed = args.get(2).map(|s| s.parse()).transpose()?;
+            Ok(Box::new(Self::new(timing, velocity, seed, name)) as Box<dyn MidiIO>)
+        }
+    }
+
+    fn process(&mut self, now: Duration, messages: &[MidiMessage]) {
+        for m in messages {
+            if let (Some(channel), Some(note)) = (m.channel(), m.note()) {
+                let mut due = now + Duration::from_millis(self.rng.up_to(self.timing));
+                // never let a note event overtake an earlier one for the same note
+                if let Some(last) = self.last_due.get(&(channel, note)) {
+                    due = due.max(*last)
+                }
+                self.last_due.insert((channel, note), due);
+
+                let m = if m.is_note_on() {
+                    let v = m.velocity().unwrap() as i64 + self.rng.jitter(self.velocity);
+                    m.with_velocity(v.clamp(1, 127) as u8)
+                }
+                else {
+                    m.clone()
+                };
+                self.schedule(due, m)
+            }
+            else {
+                self.schedule(now, m.clone())
+            }
+        }
+    }
+    fn schedule(&mut self, due: Duration, m: MidiMessage) {
+        // goes after anything due at the same time, so order is kept
+        let idx = self.queue.partition_point(|(t, _)| *t <= due);
+        self.queue.insert(idx, (due, m))
+    }
+    fn take_due(&mut self, now: Duration) -> Vec<MidiMessage> {
+        let idx = self.queue.partition_point(|(t, _)| *t <= now);
+        self.queue.drain(..idx).map(|(_, m)| m).collect()
+    }
+
+    fn set_value(args: &[String], val: &mut u64, max: u64) {
+        if args.is_empty() {
+            outln!("value required")
+        }
+        else {
+            match args[0].parse() {
+                Ok(v) if v <= max => *val = v,
+                Ok(_) => outln!("value must be from 0 to {}", max),
+                Err(_) => outln!("value failed to parse")
+            }
+        }
+    }
+}
+impl MidiIO for Humanize {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (humanize)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("humanize with timing 0-{}ms, velocity +-{}", self.timing, self.velocity)
+        }
+        else {
+            match shortened_keyword_match(&command[0], HUMANIZE_CMDS) {
+                Some(IDX_TIMING) => Self::set_value(&command[1..], &mut self.timing, MAX_TIMING),
+                Some(IDX_VELOCITY) => Self::set_value(&command[1..], &mut self.velocity, MAX_VELOCITY),
+                Some(IDX_SEED) => match command.get(1).map(|s| s.parse()) {
+                    Some(Ok(seed)) => {
+                        self.rng = Rng::new(seed);
+                        self.seed = Some(seed)
+                    }
+                    _ => outln!("seed required")
+                }
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in HUMANIZE_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        let mut ret = vec![String::from("humanize"), self.timing.to_string(), self.velocity.to_string()];
+        ret.extend(self.seed.map(|s| s.to_string()));
+        ret
+    }
+
+    /// everything still waiting, in order, apart from notes that haven't started yet and wouldn't be ended
+    fn release(&mut self) -> Vec<MidiMessage> {
+        let queue: Vec<MidiMessage> = self.queue.drain(..).map(|(_, m)| m).collect();
+        self.last_due.clear();
+        queue.iter().enumerate().filter(|(idx, m)| {
+            !m.is_note_on() || queue[idx + 1..].iter().any(|o| o.is_note_off() && o.channel() == m.channel() && o.note() == m.note())
+        }).map(|(_, m)| m.clone()).collect()
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        self.process(timestamp(), messages)
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        self.take_due(timestamp())
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(seed: u64) -> Vec<(Duration, Vec<u8>)> {
+        let mut h = Humanize::new(50, 20, Some(seed), String::from("h"));
+        let mut input = Vec::new();
+        for n in 0..20 {
+            input.push(MidiMessage::from_slice(0, &[0x90, 60 + n % 3, 100]).unwrap());
+            input.push(MidiMessage::from_slice(0, &[0x80, 60 + n % 3, 0]).unwrap());
+        }
+        h.process(Duration::ZERO, &input);
+
+        let mut out = Vec::new();
+        for ms in 0..=100 {
+            let now = Duration::from_millis(ms);
+            out.extend(h.take_due(now).iter().map(|m| (now, m.to_bytes())))
+        }
+        out
+    }
+
+    #[test]
+    fn same_seed_same_result() {
+        assert_eq!(run(42), run(42));
+        assert_ne!(run(42), run(43));
+    }
+
+    #[test]
+    fn note_off_never_before_note_on() {
+        for seed in 0..50 {
+            let out = run(seed);
+            assert_eq!(out.len(), 40);
+            for note in 60..63 {
+                let statuses: Vec<u8> = out.iter().filter(|(_, b)| b[1] == note).map(|(_, b)| b[0]).collect();
+                for pair in statuses.chunks(2) {
+                    assert_eq!(pair, &[0x90, 0x80]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn settings() {
+        let args = |a: &[&str]| a.iter().map(|s| String::from(*s)).collect::<Vec<_>>();
+        assert!(Humanize::new_args(String::from("h"), &args(&["18446744073709551615", "0"])).is_err());
+        assert!(Humanize::new_args(String::from("h"), &args(&["10", "128"])).is_err());
+
+        let mut h = Humanize::new(10, 5, None, String::from("h"));
+        h.cfg(&args(&["timing", "18446744073709551615"]));
+        assert_eq!(h.timing, 10);
+        assert_eq!(h.init_args(), args(&["humanize", "10", "5"]));
+        h.cfg(&args(&["seed", "7"]));
+        assert_eq!(h.init_args(), args(&["humanize", "10", "5", "7"]));
+    }
+
+    #[test]
+    fn release_sends_whats_waiting() {
+        let mut h = Humanize::new(1000, 0, Some(1), String::from("h"));
+        h.process(Duration::ZERO, &[MidiMessage::note_on(1, 60, 100), MidiMessage::note_on(1, 62, 100)]);
+        h.process(Duration::ZERO, &[MidiMessage::note_off(1, 60)]);
+        // 62 never got going, so it's left out rather than left hanging
+        assert_eq!(h.release(), vec![MidiMessage::note_on(1, 60, 100), MidiMessage::note_off(1, 60)]);
+        assert!(h.queue.is_empty());
+    }
+}
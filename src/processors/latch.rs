@@ -0,0 +1,270 @@
+use crate::utils::*;
+use crate::consts::latch_cmds::*;
+use crate::consts::latch_modes::*;
+use crate::MidiIO;
+
+const SUSTAIN_CC: u8 = 64;
+
+pub struct Latch {
+    mode: usize,
+    toggle_cc: Option<u8>,
+    enabled: bool, // only used in latch mode
+    pedal_down: [bool; 16], // by channel, only used in sustain mode
+    pressed: Vec<(u8, u8)>, // (channel, note) for keys that are physically down
+    held: Vec<(u8, u8)>, // (channel, note) for notes we've swallowed the note off for
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl Latch {
+    fn new(mode: usize, toggle_cc: Option<u8>, name: String) -> Latch {
+        Latch {
+            mode, toggle_cc, name,
+            enabled: true,
+            pedal_down: [false; 16],
+            pressed: Vec::new(),
+            held: Vec::new(),
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !(1..=2).contains(&args.len()) {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            let mode = shortened_keyword_match(&args[0], LATCH_MODES).ok_or(MMMErr::ArgError)?;
+            let toggle_cc = match args.get(1) {
+                Some(s) => Some(s.parse()?),
+                None => None
+            };
+            Ok(Box::new(Self::new(mode, toggle_cc, name)) as Box<dyn MidiIO>)
+        }
+    }
+
+    fn release_held(&mut self) {
+        for (c, n) in self.held.drain(..) {
+            self.buf.push(MidiMessage::note_off(c, n))
+        }
+    }
+    /// for a sustain pedal coming up, which only lets go of notes on its own channel
+    fn release_channel(&mut self, channel: u8) {
+        let buf = &mut self.buf;
+        self.held.retain(|(c, n)| {
+            if *c == channel {
+                buf.push(MidiMessage::note_off(*c, *n))
+            }
+            *c != channel
+        })
+    }
+    /// stop holding a single note so it can be played again
+    fn retrigger(&mut self, key: (u8, u8)) {
+        if let Some(idx) = self.held.iter().position(|k| *k == key) {
+            self.held.remove(idx);
+            self.buf.push(MidiMessage::note_off(key.0, key.1))
+        }
+    }
+
+    fn process_latch(&mut self, m: &MidiMessage) {
+        if let (Some(c), Some(n)) = (m.channel(), m.note()) {
+            let key = (c, n);
+            if m.is_note_on() {
+                if self.enabled && self.pressed.is_empty() {
+                    // a new chord, so the old one goes
+                    self.release_held()
+                }
+                self.retrigger(key);
+                push_if_not_present(key, &mut self.pressed);
+                self.buf.push(m.clone())
+            }
+            else {
+                self.pressed.retain(|k| *k != key);
+                if self.enabled {
+                    push_if_not_present(key, &mut self.held)
+                }
+                else {
+                    self.buf.push(m.clone())
+                }
+            }
+        }
+        else if let (Some((cc, v)), Some(toggle)) = (m.as_cc(), self.toggle_cc) {
+            if cc == toggle {
+                if v >= 64 {
+                    self.enabled = !self.enabled;
+                    self.release_held()
+                }
+            }
+            else {
+                self.buf.push(m.clone())
+            }
+        }
+        else {
+            self.buf.push(m.clone())
+        }
+    }
+    fn process_sustain(&mut self, m: &MidiMessage) {
+        if let (Some(c), Some(n)) = (m.channel(), m.note()) {
+            let key = (c, n);
+            if m.is_note_on() {
+                self.retrigger(key);
+                self.buf.push(m.clone())
+            }
+            else if self.pedal_down[c as usize - 1] {
+                push_if_not_present(key, &mut self.held)
+            }
+            else {
+                self.buf.push(m.clone())
+            }
+        }
+        else if let (Some((SUSTAIN_CC, v)), Some(c)) = (m.as_cc(), m.channel()) {
+            self.pedal_down[c as usize - 1] = v >= 64;
+            if v < 64 {
+                self.release_channel(c)
+            }
+        }
+        else {
+            self.buf.push(m.clone())
+        }
+    }
+
+    fn set_mode(&mut self, args: &[String]) {
+        match args.first().map(|s| shortened_keyword_match(s, LATCH_MODES)) {
+            Some(Some(mode)) => {
+                self.release_held();
+                self.mode = mode;
+                self.enabled = true;
+                self.pedal_down = [false; 16];
+            }
+            _ => {
+                outln!("mode required! valid modes are:");
+                for m in LATCH_MODES {
+                    outln!("\t{}", m)
+                }
+            }
+        }
+    }
+    fn set_toggle(&mut self, args: &[String]) {
+        match args.first().map(|s| s.parse()) {
+            Some(Ok(cc)) => self.toggle_cc = Some(cc),
+            Some(Err(_)) => outln!("cc number failed to parse"),
+            None => self.toggle_cc = None
+        }
+    }
+}
+impl MidiIO for Latch {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (latch)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            out!("latch in {} mode, holding {} notes", LATCH_MODES[self.mode], self.held.len());
+            match self.toggle_cc {
+                Some(cc) => outln!(", toggled by cc {}", cc),
+                None => outln!()
+            }
+        }
+        else {
+            match shortened_keyword_match(&command[0], LATCH_CMDS) {
+                Some(IDX_MODE) => self.set_mode(&command[1..]),
+                Some(IDX_TOGGLE) => self.set_toggle(&command[1..]),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in LATCH_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        let mut ret = vec![String::from("latch"), String::from(LATCH_MODES[self.mode])];
+        ret.extend(self.toggle_cc.map(|cc| cc.to_string()));
+        ret
+    }
+    fn release(&mut self) -> Vec<MidiMessage> {
+        self.release_held();
+        self.read()
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        for m in messages {
+            match self.mode {
+                IDX_LATCH => self.process_latch(m),
+                IDX_SUSTAIN => self.process_sustain(m),
+                _ => unreachable!()
+            }
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(msgs: Vec<MidiMessage>) -> Vec<Vec<u8>> {
+        msgs.iter().map(|m| m.to_bytes()).collect()
+    }
+
+    #[test]
+    fn latch_holds_until_next_chord() {
+        let mut l = Latch::new(IDX_LATCH, None, String::from("l"));
+        l.write(&[MidiMessage::note_on(1, 60, 100), MidiMessage::note_on(1, 64, 100)]);
+        l.write(&[MidiMessage::note_off(1, 60), MidiMessage::note_off(1, 64)]);
+        assert_eq!(l.read().len(), 2);
+        l.write(&[MidiMessage::note_on(1, 62, 100)]);
+        assert_eq!(bytes(l.read()), vec![vec![0x80, 60, 0], vec![0x80, 64, 0], vec![0x90, 62, 100]]);
+        l.write(&[MidiMessage::note_off(1, 62)]);
+        assert_eq!(bytes(l.release()), vec![vec![0x80, 62, 0]]);
+    }
+
+    #[test]
+    fn sustain_holds_note_offs() {
+        let mut l = Latch::new(IDX_SUSTAIN, None, String::from("l"));
+        l.write(&[MidiMessage::control_change(1, 64, 127), MidiMessage::note_on(1, 60, 100), MidiMessage::note_off(1, 60)]);
+        assert_eq!(bytes(l.read()), vec![vec![0x90, 60, 100]]);
+        l.write(&[MidiMessage::control_change(1, 64, 0)]);
+        assert_eq!(bytes(l.read()), vec![vec![0x80, 60, 0]]);
+    }
+
+    #[test]
+    fn sustain_is_per_channel() {
+        let mut l = Latch::new(IDX_SUSTAIN, None, String::from("l"));
+        l.write(&[MidiMessage::control_change(1, 64, 127), MidiMessage::note_on(1, 60, 100), MidiMessage::note_on(2, 60, 100)]);
+        l.read();
+        // channel 2's pedal isn't down, so its note goes straight out
+        l.write(&[MidiMessage::note_off(1, 60), MidiMessage::note_off(2, 60)]);
+        assert_eq!(bytes(l.read()), vec![vec![0x81, 60, 0]]);
+        // and it coming up doesn't let go of channel 1
+        l.write(&[MidiMessage::control_change(2, 64, 127), MidiMessage::control_change(2, 64, 0)]);
+        assert!(l.read().is_empty());
+        l.write(&[MidiMessage::control_change(1, 64, 0)]);
+        assert_eq!(bytes(l.read()), vec![vec![0x80, 60, 0]]);
+    }
+
+    #[test]
+    fn mode_change_releases() {
+        let mut l = Latch::new(IDX_LATCH, None, String::from("l"));
+        l.write(&[MidiMessage::note_on(1, 60, 100), MidiMessage::note_off(1, 60)]);
+        l.read();
+        l.cfg(&[String::from("mode"), String::from("sustain")]);
+        assert_eq!(bytes(l.read()), vec![vec![0x80, 60, 0]]);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::utils::*;
+use crate::consts::lfo_cmds::*;
+use crate::consts::lfo_shapes::*;
+use crate::consts::lfo_targets::*;
+use crate::MidiIO;
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+/// midi clocks per beat
+const CLOCKS_PER_BEAT: f64 = 24.0;
+
+pub struct Lfo {
+    shape: usize,
+    target: usize,
+    cc: u8, // only used when the target is a cc
+    channel: u8,
+    depth: f64, // percent of the full range
+    offset: f64, // percent of the full range
+    rate: f64, // in hz, only used when not synced
+    sync: Option<f64>, // beats per cycle
+    max_rate: f64, // messages per second
+    phase: f64, // in cycles since the start, so the fractional part is the actual phase
+    sample: f64, // for sample and hold
+    clocks: u64,
+    last_clock: Duration,
+    clock_interval: Option<Duration>,
+    last: Duration,
+    last_sent: Option<(Duration, u16)>,
+    rng: Rng,
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl Lfo {
+    fn new(channel: u8, target: usize, cc: u8, name: String) -> Lfo {
+        Lfo {
+            channel, target, cc, name,
+            shape: IDX_SINE,
+            depth: 100.0,
+            offset: 50.0,
+            rate: 1.0,
+            sync: None,
+            max_rate: 50.0,
+            phase: 0.0,
+            sample: 0.0,
+            clocks: 0,
+            last_clock: Duration::ZERO,
+            clock_interval: None,
+            last: timestamp(),
+            last_sent: None,
+            rng: Rng::from_time(),
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !(2..=3).contains(&args.len()) {
+            return Err(MMMErr::ArgError)
+        }
+        let c: u8 = args[0].parse()?;
+        let target = shortened_keyword_match(&args[1], LFO_TARGETS).ok_or(MMMErr::ArgError)?;
+        let cc: u8 = match args.get(2) {
+            Some(s) => s.parse()?,
+            None if target == IDX_CC => return Err(MMMErr::ArgError),
+            None => 0
+        };
+        if (1..=16).contains(&c) && cc < 128 {
+            Ok(Box::new(Self::new(c, target, cc, name)) as Box<dyn MidiIO>)
+        }
+        else {
+            Err(MMMErr::ArgError)
+        }
+    }
+
+    fn update(&mut self, now: Duration) {
+        let cycle = self.phase.floor();
+        match self.sync {
+            None => self.phase += (now - self.last).as_secs_f64() * self.rate,
+            Some(beats) => {
+                // between clocks, guess how far along we are, but never past the next one
+                let frac = match self.clock_interval {
+                    Some(i) => ((now - self.last_clock).as_secs_f64() / i.as_secs_f64()).min(1.0),
+                    None => 0.0
+                };
+                self.phase = (self.clocks as f64 + frac) / (beats * CLOCKS_PER_BEAT)
+            }
+        }
+        self.last = now;
+        if self.phase.floor() != cycle {
+            self.sample = (self.rng.up_to(20000) as f64 - 10000.0) / 10000.0
+        }
+
+        if let Some((t, _)) = self.last_sent {
+            if (now - t).as_secs_f64() < 1.0 / self.max_rate {
+                return
+            }
+        }
+        let v = match self.shape {
+            IDX_RANDOM => self.sample,
+            s => shape_value(s, self.phase.fract())
+        };
+        let centre = self.offset / 100.0;
+        let amount = (centre + v * self.depth / 200.0).clamp(0.0, 1.0);
+        let value = match self.target {
+            IDX_PITCHBEND => (amount * 16383.0).round() as u16,
+            _ => (amount * 127.0).round() as u16
+        };
+        if self.last_sent.map(|(_, v)| v != value).unwrap_or(true) {
+            self.buf.push(match self.target {
+                IDX_CC => MidiMessage::control_change(self.channel, self.cc, value as u8),
+                IDX_PITCHBEND => MidiMessage::pitch_bend(self.channel, value),
+                IDX_AFTERTOUCH => MidiMessage::channel_pressure(self.channel, value as u8),
+                _ => unreachable!()
+            });
+            self.last_sent = Some((now, value))
+        }
+    }
+    fn clock(&mut self, now: Duration, m: &MidiMessage) {
+        match m {
+            MidiMessage::SystemRealtime(0xf8) => {
+                self.clocks += 1;
+                self.clock_interval = Some(now - self.last_clock);
+                self.last_clock = now
+            }
+            MidiMessage::SystemRealtime(0xfa) => {
+                self.clocks = 0;
+                self.last_clock = now
+            }
+            _ => {}
+        }
+    }
+
+    fn set_shape(&mut self, args: &[String]) {
+        match args.first().and_then(|s| shortened_keyword_match(s, LFO_SHAPES)) {
+            Some(s) => self.shape = s,
+            None => {
+                outln!("shape required! valid shapes are:");
+                for s in LFO_SHAPES {
+                    outln!("\t{}", s)
+                }
+            }
+        }
+    }
+    fn set_target(&mut self, args: &[String]) {
+        match args.first().and_then(|s| shortened_keyword_match(s, LFO_TARGETS)) {
+            Some(IDX_CC) => match args.get(1).map(|s| s.parse::<u8>()) {
+                Some(Ok(cc)) if cc < 128 => {
+                    self.target = IDX_CC;
+                    self.cc = cc
+                }
+                _ => outln!("cc number required")
+            }
+            Some(t) => self.target = t,
+            None => {
+                outln!("target required! valid targets are:");
+                for t in LFO_TARGETS {
+                    outln!("\t{}", t)
+                }
+            }
+        }
+        self.last_sent = None
+    }
+    fn set_value<T: std::str::FromStr>(args: &[String], valid: impl Fn(&T) -> bool, val: &mut T) {
+        match args.first().map(|s| s.parse::<T>()) {
+            Some(Ok(v)) if valid(&v) => *val = v,
+            _ => outln!("bad or missing value")
+        }
+    }
+}
+impl MidiIO for Lfo {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (lfo)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            let target = match self.target {
+                IDX_CC => format!("cc {}", self.cc),
+                t => String::from(LFO_TARGETS[t])
+            };
+            let rate = match self.sync {
+                Some(beats) => format!("every {} beats", beats),
+                None => format!("at {}hz", self.rate)
+            };
+            outln!("lfo sending {} {} on channel {} {}, depth {}%, offset {}%, at most {} messages per second",
+                LFO_SHAPES[self.shape], target, self.channel, rate, self.depth, self.offset, self.max_rate)
+        }
+        else {
+            match shortened_keyword_match(&command[0], LFO_CMDS) {
+                Some(IDX_SHAPE) => self.set_shape(&command[1..]),
+                Some(IDX_TARGET) => self.set_target(&command[1..]),
+                Some(IDX_CHANNEL) => Self::set_value(&command[1..], |c| (1..=16).contains(c), &mut self.channel),
+                Some(IDX_RATE) => {
+                    let mut rate = self.rate;
+                    Self::set_value(&command[1..], |r| *r > 0.0, &mut rate);
+                    self.rate = rate;
+                    self.sync = None
+                }
+                Some(IDX_SYNC) => {
+                    let mut beats = self.sync.unwrap_or(1.0);
+                    Self::set_value(&command[1..], |b| *b > 0.0, &mut beats);
+                    self.sync = Some(beats)
+                }
+                Some(IDX_DEPTH) => Self::set_value(&command[1..], |d| (0.0..=100.0).contains(d), &mut self.depth),
+                Some(IDX_OFFSET) => Self::set_value(&command[1..], |o| (0.0..=100.0).contains(o), &mut self.offset),
+                Some(IDX_MAXRATE) => Self::set_value(&command[1..], |r| *r > 0.0, &mut self.max_rate),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in LFO_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        let mut ret = vec![String::from("lfo"), self.channel.to_string(), String::from(LFO_TARGETS[self.target])];
+        if self.target == IDX_CC {
+            ret.push(self.cc.to_string())
+        }
+        ret
+    }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        let mut ret = vec![
+            vec![String::from("shape"), String::from(LFO_SHAPES[self.shape])],
+            vec![String::from("depth"), self.depth.to_string()],
+            vec![String::from("offset"), self.offset.to_string()],
+            vec![String::from("maxrate"), self.max_rate.to_string()],
+        ];
+        match self.sync {
+            Some(beats) => ret.push(vec![String::from("sync"), beats.to_string()]),
+            None => ret.push(vec![String::from("rate"), self.rate.to_string()])
+        }
+        ret
+    }
+
+    /// only clock and start are listened to, and only when synced
+    fn write(&mut self, messages: &[MidiMessage]) {
+        if self.sync.is_some() {
+            let now = timestamp();
+            for m in messages {
+                self.clock(now, m)
+            }
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        self.update(timestamp());
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+/// phase is 0 to 1, output is -1 to 1
+fn shape_value(shape: usize, phase: f64) -> f64 {
+    match shape {
+        IDX_SINE => (phase * TAU).sin(),
+        IDX_TRIANGLE => 1.0 - 4.0 * (phase - 0.5).abs(),
+        IDX_SAW => phase * 2.0 - 1.0,
+        IDX_SQUARE => if phase < 0.5 { 1.0 } else { -1.0 },
+        _ => unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(shape_value(IDX_SINE, 0.25), 1.0));
+        assert!(close(shape_value(IDX_TRIANGLE, 0.0), -1.0));
+        assert!(close(shape_value(IDX_TRIANGLE, 0.5), 1.0));
+        assert!(close(shape_value(IDX_SAW, 0.75), 0.5));
+        assert!(close(shape_value(IDX_SQUARE, 0.6), -1.0));
+    }
+
+    #[test]
+    fn output_is_rate_limited() {
+        let mut l = Lfo::new(1, IDX_PITCHBEND, 0, String::from("l"));
+        l.last = Duration::ZERO;
+        l.rate = 5.0;
+        for ms in 0..1000 {
+            l.update(Duration::from_millis(ms))
+        }
+        assert!(l.buf.len() <= 50);
+        assert!(l.buf.len() > 40);
+        // first value is the middle of a sine
+        assert_eq!(l.buf[0].to_bytes(), vec![0xe0, 0x00, 0x40]);
+    }
+
+    #[test]
+    fn follows_clock() {
+        let mut l = Lfo::new(1, IDX_CC, 74, String::from("l"));
+        l.shape = IDX_SAW;
+        l.sync = Some(1.0);
+        l.max_rate = 1000.0;
+        for i in 0..12 {
+            l.clock(Duration::from_millis(i * 20), &MidiMessage::SystemRealtime(0xf8));
+        }
+        l.update(Duration::from_millis(220));
+        // half way through the cycle, so half way up the saw
+        assert_eq!(l.buf.last().unwrap().to_bytes(), vec![0xb0, 74, 64]);
+    }
+}
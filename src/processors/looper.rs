@@ -0,0 +1,415 @@
+use crate::utils::*;
+use crate::consts::looper_cmds::*;
+use crate::MidiIO;
+use std::time::Duration;
+
+/// positions are measured in midi clocks, so 24 to a beat
+const CLOCKS_PER_BEAT: f64 = 24.0;
+
+type Layer = Vec<(f64, MidiMessage)>;
+
+pub struct Looper {
+    layers: Vec<Layer>,
+    take: Layer, // recorded since the last time round, not part of the top layer yet so it doesn't play straight back
+    held: Vec<(u8, u8)>, // (channel, note) for note ons in the current take without a note off
+    recording: bool,
+    record_end: Option<f64>, // where a single pass recording stops. None when overdubbing
+    muted: bool,
+    length: f64, // in clocks
+    tempo: f64, // in bpm, only used when not synced
+    sync: bool,
+    running: bool, // only used when synced
+    abs: f64, // clocks since the start. never goes backwards, except when external clock restarts
+    clocks: u64, // clock messages since external start
+    last_clock: Duration,
+    clock_interval: Option<Duration>,
+    last: Duration,
+    sounding: Vec<(u8, u8)>, // (channel, note) for notes started by playback
+    mappings: Vec<(u8, usize)>, // (cc, index into LOOPER_CMDS)
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl Looper {
+    fn new(beats: f64, tempo: f64, name: String) -> Looper {
+        Looper {
+            tempo, name,
+            layers: Vec::new(),
+            take: Vec::new(),
+            held: Vec::new(),
+            recording: false,
+            record_end: None,
+            muted: false,
+            length: beats * CLOCKS_PER_BEAT,
+            sync: false,
+            running: false,
+            abs: 0.0,
+            clocks: 0,
+            last_clock: Duration::ZERO,
+            clock_interval: None,
+            last: timestamp(),
+            sounding: Vec::new(),
+            mappings: Vec::new(),
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if args.len() > 2 {
+            return Err(MMMErr::ArgError)
+        }
+        let beats = args.first().map(|s| s.parse::<f64>()).transpose().map_err(|_| MMMErr::ArgError)?.unwrap_or(4.0);
+        let tempo = args.get(1).map(|s| s.parse::<f64>()).transpose().map_err(|_| MMMErr::ArgError)?.unwrap_or(120.0);
+        if beats <= 0.0 || tempo <= 0.0 {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            Ok(Box::new(Self::new(beats, tempo, name)) as Box<dyn MidiIO>)
+        }
+    }
+
+    /// move the playhead up to now, playing anything passed on the way
+    fn update(&mut self, now: Duration) {
+        let target = if self.sync {
+            if !self.running {
+                self.last = now;
+                return
+            }
+            // between clocks, guess how far along we are, but never past the next one
+            let frac = match self.clock_interval {
+                Some(i) => ((now - self.last_clock).as_secs_f64() / i.as_secs_f64()).min(1.0),
+                None => 0.0
+            };
+            self.clocks as f64 + frac
+        }
+        else {
+            self.abs + (now - self.last).as_secs_f64() * self.tempo / 60.0 * CLOCKS_PER_BEAT
+        };
+        self.last = now;
+        self.advance_to(target.max(self.abs))
+    }
+    fn advance_to(&mut self, new: f64) {
+        let len = self.length;
+        let (cycle_a, cycle_b) = ((self.abs / len).floor(), (new / len).floor());
+        let (a, b) = (self.abs - cycle_a * len, new - cycle_b * len);
+        if cycle_a == cycle_b {
+            self.play(a, b)
+        }
+        else {
+            self.play(a, len);
+            if self.recording {
+                self.merge_take()
+            }
+            self.play(0.0, b)
+        }
+        self.abs = new;
+        if self.record_end.map(|end| new >= end).unwrap_or(false) {
+            self.finish_take()
+        }
+    }
+    fn play(&mut self, from: f64, to: f64) {
+        if self.muted {
+            return
+        }
+        let mut events: Vec<&(f64, MidiMessage)> = self.layers.iter().flatten().filter(|(p, _)| from <= *p && *p < to).collect();
+        events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        for (_, m) in events {
+            if let (Some(c), Some(n)) = (m.channel(), m.note()) {
+                if m.is_note_on() {
+                    push_if_not_present((c, n), &mut self.sounding)
+                }
+                else {
+                    self.sounding.retain(|k| *k != (c, n))
+                }
+            }
+            self.buf.push(m.clone())
+        }
+    }
+    fn notes_off(&mut self) {
+        for (c, n) in self.sounding.drain(..) {
+            self.buf.push(MidiMessage::note_off(c, n))
+        }
+    }
+
+    fn merge_take(&mut self) {
+        let layer = self.layers.last_mut().unwrap();
+        layer.append(&mut self.take);
+        layer.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+    }
+    fn start_take(&mut self, single_pass: bool) {
+        self.layers.push(Vec::new());
+        self.take.clear();
+        self.held.clear();
+        self.recording = true;
+        self.record_end = if single_pass { Some(self.abs + self.length) } else { None }
+    }
+    fn finish_take(&mut self) {
+        if self.recording {
+            self.merge_take();
+            // notes still down get let go just before the end of the loop, so they don't hang forever
+            let end = self.length - 0.001;
+            let layer = self.layers.last_mut().unwrap();
+            for (c, n) in self.held.drain(..) {
+                layer.push((end, MidiMessage::note_off(c, n)))
+            }
+            self.recording = false;
+            self.record_end = None
+        }
+    }
+    fn record(&mut self, m: &MidiMessage) {
+        if let (Some(c), Some(n)) = (m.channel(), m.note()) {
+            if m.is_note_on() {
+                push_if_not_present((c, n), &mut self.held)
+            }
+            else {
+                self.held.retain(|k| *k != (c, n))
+            }
+        }
+        let pos = self.abs % self.length;
+        self.take.push((pos, m.clone()))
+    }
+
+    fn action(&mut self, action: usize) {
+        match action {
+            IDX_RECORD | IDX_OVERDUB => {
+                if self.recording {
+                    self.finish_take()
+                }
+                else {
+                    self.start_take(action == IDX_RECORD)
+                }
+            }
+            IDX_UNDO => {
+                // while recording, the layer being recorded is the one that goes
+                self.take.clear();
+                self.held.clear();
+                self.recording = false;
+                self.record_end = None;
+                self.layers.pop();
+                self.notes_off()
+            }
+            IDX_CLEAR => {
+                self.take.clear();
+                self.held.clear();
+                self.recording = false;
+                self.record_end = None;
+                self.layers.clear();
+                self.notes_off()
+            }
+            IDX_MUTE => {
+                self.muted = !self.muted;
+                self.notes_off()
+            }
+            _ => unreachable!()
+        }
+    }
+    fn clock(&mut self, now: Duration, m: &MidiMessage) {
+        match m {
+            MidiMessage::SystemRealtime(0xf8) if self.running => {
+                self.clocks += 1;
+                self.clock_interval = Some(now - self.last_clock);
+                self.last_clock = now
+            }
+            MidiMessage::SystemRealtime(0xfa) => {
+                self.finish_take();
+                self.notes_off();
+                self.clocks = 0;
+                self.abs = 0.0;
+                self.last_clock = now;
+                self.running = true
+            }
+            MidiMessage::SystemRealtime(0xfb) => {
+                self.last_clock = now;
+                self.running = true
+            }
+            MidiMessage::SystemRealtime(0xfc) => {
+                self.running = false;
+                self.notes_off()
+            }
+            _ => {}
+        }
+    }
+    fn input(&mut self, now: Duration, messages: &[MidiMessage]) {
+        self.update(now);
+        for m in messages {
+            if self.sync {
+                self.clock(now, m);
+                self.update(now)
+            }
+            if let Some((cc, v)) = m.as_cc() {
+                if let Some((_, action)) = self.mappings.iter().find(|(c, _)| *c == cc) {
+                    if v >= 64 {
+                        self.action(*action)
+                    }
+                    continue
+                }
+            }
+            if self.recording && !matches!(m, MidiMessage::SystemRealtime(_)) {
+                self.record(m)
+            }
+            self.buf.push(m.clone())
+        }
+    }
+
+    fn map(&mut self, args: &[String]) {
+        let action = args.first().and_then(|a| shortened_keyword_match(a, LOOPER_CMDS));
+        match action {
+            Some(a @ (IDX_RECORD | IDX_OVERDUB | IDX_UNDO | IDX_CLEAR | IDX_MUTE)) => {
+                self.mappings.retain(|(_, act)| *act != a);
+                match args.get(1).map(|s| s.parse()) {
+                    Some(Ok(cc)) => {
+                        self.mappings.retain(|(c, _)| *c != cc);
+                        self.mappings.push((cc, a))
+                    }
+                    Some(Err(_)) => outln!("cc number failed to parse"),
+                    None => {}
+                }
+            }
+            _ => outln!("only record, overdub, undo, clear and mute can be mapped")
+        }
+    }
+    fn set_positive(args: &[String], val: &mut f64, scale: f64) {
+        match args.first().map(|s| s.parse::<f64>()) {
+            Some(Ok(v)) if v > 0.0 => *val = v * scale,
+            _ => outln!("positive number required")
+        }
+    }
+}
+impl MidiIO for Looper {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (looper)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            let timing = if self.sync { String::from("synced to clock") } else { format!("at {}bpm", self.tempo) };
+            out!("looper with {} layers, {} beats long, {}", self.layers.len(), self.length / CLOCKS_PER_BEAT, timing);
+            if self.recording {
+                out!(", recording")
+            }
+            if self.muted {
+                out!(", muted")
+            }
+            outln!();
+            for (cc, action) in &self.mappings {
+                outln!("\tcc {} -> {}", cc, LOOPER_CMDS[*action])
+            }
+        }
+        else {
+            self.update(timestamp());
+            match shortened_keyword_match(&command[0], LOOPER_CMDS) {
+                Some(a @ (IDX_RECORD | IDX_OVERDUB | IDX_UNDO | IDX_CLEAR | IDX_MUTE)) => self.action(a),
+                Some(IDX_LENGTH) => Self::set_positive(&command[1..], &mut self.length, CLOCKS_PER_BEAT),
+                Some(IDX_TEMPO) => Self::set_positive(&command[1..], &mut self.tempo, 1.0),
+                Some(IDX_SYNC) => {
+                    self.sync = !self.sync;
+                    self.running = false;
+                    self.notes_off()
+                }
+                Some(IDX_MAP) => self.map(&command[1..]),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in LOOPER_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        vec![String::from("looper"), (self.length / CLOCKS_PER_BEAT).to_string(), self.tempo.to_string()]
+    }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        let mut ret: Vec<Vec<String>> = self.mappings.iter().map(|(cc, action)| {
+            vec![String::from("map"), String::from(LOOPER_CMDS[*action]), cc.to_string()]
+        }).collect();
+        if self.sync {
+            ret.push(vec![String::from("sync")])
+        }
+        ret
+    }
+    fn release(&mut self) -> Vec<MidiMessage> {
+        self.notes_off();
+        std::mem::take(&mut self.buf)
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        self.input(timestamp(), messages)
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        self.update(timestamp());
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+    fn bytes(l: &mut Looper) -> Vec<Vec<u8>> {
+        l.buf.drain(..).map(|m| m.to_bytes()).collect()
+    }
+
+    #[test]
+    fn records_one_pass_then_loops() {
+        // 4 beats at 120bpm is 2 seconds
+        let mut l = Looper::new(4.0, 120.0, String::from("l"));
+        l.last = Duration::ZERO;
+        l.action(IDX_RECORD);
+        l.input(ms(500), &[MidiMessage::note_on(1, 60, 100)]);
+        l.input(ms(1000), &[MidiMessage::note_off(1, 60)]);
+        l.update(ms(1999));
+        // only the passed through input so far
+        assert_eq!(bytes(&mut l).len(), 2);
+        l.update(ms(2100));
+        assert!(!l.recording);
+        l.update(ms(2400));
+        assert!(bytes(&mut l).is_empty());
+        l.update(ms(2600));
+        assert_eq!(bytes(&mut l), vec![vec![0x90, 60, 100]]);
+        l.update(ms(3100));
+        assert_eq!(bytes(&mut l), vec![vec![0x80, 60, 0]]);
+        l.update(ms(4600));
+        assert_eq!(bytes(&mut l), vec![vec![0x90, 60, 100]]);
+        l.action(IDX_UNDO);
+        assert_eq!(bytes(&mut l), vec![vec![0x80, 60, 0]]);
+        assert!(l.layers.is_empty());
+    }
+
+    #[test]
+    fn stays_locked_to_clock() {
+        let mut l = Looper::new(1.0, 120.0, String::from("l"));
+        l.sync = true;
+        l.last = Duration::ZERO;
+        l.input(ms(0), &[MidiMessage::SystemRealtime(0xfa)]);
+        l.action(IDX_RECORD);
+        l.input(ms(0), &[MidiMessage::note_on(1, 60, 100)]);
+        // a wildly wrong clock rate still only moves the loop on by one clock per tick
+        for i in 1..=24 {
+            l.input(ms(i * 7), &[MidiMessage::SystemRealtime(0xf8)]);
+        }
+        assert!(!l.recording);
+        assert_eq!(l.abs, 24.0);
+        bytes(&mut l);
+        l.update(ms(1000));
+        assert_eq!(l.abs, 25.0);
+        assert_eq!(bytes(&mut l), vec![vec![0x90, 60, 100]]);
+    }
+}
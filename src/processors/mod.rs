@@ -2,3 +2,22 @@ pub mod connection;
 pub mod channelfilter;
 pub mod channelmerge;
 pub mod dummyplayer;
+pub mod humanize;
+pub mod latch;
+pub mod progmap;
+pub mod monitor;
+pub mod record;
+pub mod player;
+pub mod looper;
+pub mod seq;
+pub mod lfo;
+pub mod thin;
+pub mod rotate;
+pub mod mpedown;
+pub mod paramconv;
+pub mod net;
+pub mod rtpmidi;
+pub mod osc;
+pub mod tcp;
+pub mod pipe;
+pub mod serial;
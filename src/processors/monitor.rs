@@ -0,0 +1,273 @@
+use crate::utils::*;
+use crate::consts::monitor_cmds::*;
+use crate::consts::monitor_outputs::*;
+use crate::consts::monitor_types::*;
+use crate::shell::print_line;
+use crate::MidiIO;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+enum Output {
+    Shell,
+    File(String, File),
+    Off
+}
+
+pub struct Monitor {
+    output: Output,
+    shown: Vec<bool>, // indexed by MONITOR_TYPES
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl Monitor {
+    fn new(output: Output, name: String) -> Monitor {
+        let mut shown = vec![true; MONITOR_TYPES.len()];
+        // clock alone is 24 messages per beat, which drowns out everything else
+        shown[IDX_REALTIME] = false;
+        Monitor {
+            output, shown, name,
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        let output = if args.is_empty() { Output::Shell } else { open_output(args)? };
+        Ok(Box::new(Self::new(output, name)) as Box<dyn MidiIO>)
+    }
+
+    fn set_output(&mut self, args: &[String]) {
+        match open_output(args) {
+            Ok(o) => self.output = o,
+            Err(MMMErr::ArgError) => {
+                outln!("output required! valid outputs are:");
+                for o in MONITOR_OUTPUTS {
+                    outln!("\t{}", o)
+                }
+            }
+            Err(e) => outln!("failed to open log file: {}", e)
+        }
+    }
+    fn set_shown(&mut self, args: &[String], show: bool) {
+        if args.is_empty() {
+            outln!("message type required! valid types are:");
+            for t in MONITOR_TYPES {
+                outln!("\t{}", t)
+            }
+        }
+        for a in args {
+            match shortened_keyword_match(a, MONITOR_TYPES) {
+                Some(idx) => self.shown[idx] = show,
+                None => outln!("no match for {}", a)
+            }
+        }
+    }
+
+    fn log(&mut self, m: &MidiMessage) {
+        if !self.shown[kind(m)] {
+            return
+        }
+        let line = format!("{:>10.3} {}: {}", timestamp().as_secs_f64(), self.name, describe(m));
+        match &mut self.output {
+            Output::Shell => print_line(line),
+            Output::File(_, f) => { let _ = writeln!(f, "{}", line); }
+            Output::Off => {}
+        }
+    }
+}
+impl MidiIO for Monitor {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (monitor)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            let output = match &self.output {
+                Output::Shell => String::from("the shell"),
+                Output::File(path, _) => path.clone(),
+                Output::Off => String::from("nowhere")
+            };
+            let shown: Vec<&str> = MONITOR_TYPES.iter().zip(&self.shown).filter(|(_, s)| **s).map(|(t, _)| *t).collect();
+            outln!("monitor logging {} to {}", shown.join(", "), output)
+        }
+        else {
+            match shortened_keyword_match(&command[0], MONITOR_CMDS) {
+                Some(IDX_OUTPUT) => self.set_output(&command[1..]),
+                Some(IDX_SHOW) => self.set_shown(&command[1..], true),
+                Some(IDX_HIDE) => self.set_shown(&command[1..], false),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in MONITOR_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        let mut ret = vec![String::from("monitor")];
+        match &self.output {
+            Output::Shell => ret.push(String::from("shell")),
+            Output::File(path, _) => ret.extend([String::from("file"), path.clone()]),
+            Output::Off => ret.push(String::from("off"))
+        }
+        ret
+    }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        let mut show = vec![String::from("show")];
+        let mut hide = vec![String::from("hide")];
+        for (t, shown) in MONITOR_TYPES.iter().zip(&self.shown) {
+            if *shown { &mut show } else { &mut hide }.push(String::from(*t))
+        }
+        vec![show, hide].into_iter().filter(|l| l.len() > 1).collect()
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        for m in messages {
+            self.log(m)
+        }
+        self.buf.extend_from_slice(messages)
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+fn open_output(args: &[String]) -> Result<Output> {
+    match args.first().and_then(|a| shortened_keyword_match(a, MONITOR_OUTPUTS)) {
+        Some(IDX_SHELL) => Ok(Output::Shell),
+        Some(IDX_OFF) => Ok(Output::Off),
+        Some(IDX_FILE) => {
+            let path = args.get(1).ok_or(MMMErr::ArgError)?;
+            let f = OpenOptions::new().create(true).append(true).open(path)?;
+            Ok(Output::File(path.clone(), f))
+        }
+        _ => Err(MMMErr::ArgError)
+    }
+}
+
+/// index into MONITOR_TYPES
+fn kind(m: &MidiMessage) -> usize {
+    match m {
+        MidiMessage::Channel([s, ..]) | MidiMessage::ChannelSmall([s, ..]) => match s & 0xf0 {
+            0x80 | 0x90 => IDX_NOTE,
+            0xa0 => IDX_POLYTOUCH,
+            0xb0 => IDX_CC,
+            0xc0 => IDX_PROGRAM,
+            0xd0 => IDX_CHANTOUCH,
+            _ => IDX_PITCHBEND
+        }
+        MidiMessage::SystemCommon(_) => IDX_COMMON,
+        MidiMessage::SystemRealtime(_) => IDX_REALTIME
+    }
+}
+
+fn describe(m: &MidiMessage) -> String {
+    match m {
+        MidiMessage::Channel([s, a, b]) => {
+            let c = (s & 0xf) + 1;
+            match s & 0xf0 {
+                0x80 => format!("ch {} note off {} ({}) vel {}", c, note_name(*a), a, b),
+                0x90 if *b == 0 => format!("ch {} note off {} ({})", c, note_name(*a), a),
+                0x90 => format!("ch {} note on {} ({}) vel {}", c, note_name(*a), a, b),
+                0xa0 => format!("ch {} poly aftertouch {} ({}) pressure {}", c, note_name(*a), a, b),
+                0xb0 => match cc_name(*a) {
+                    Some(name) => format!("ch {} cc {} ({}) value {}", c, a, name, b),
+                    None => format!("ch {} cc {} value {}", c, a, b)
+                }
+                0xe0 => format!("ch {} pitch bend {}", c, ((*b as i32) << 7 | *a as i32) - 8192),
+                _ => format!("ch {} unknown {:02x?}", c, [s, a, b])
+            }
+        }
+        MidiMessage::ChannelSmall([s, a]) => {
+            let c = (s & 0xf) + 1;
+            match s & 0xf0 {
+                0xc0 => format!("ch {} program change {}", c, a),
+                0xd0 => format!("ch {} channel aftertouch {}", c, a),
+                _ => format!("ch {} unknown {:02x?}", c, [s, a])
+            }
+        }
+        MidiMessage::SystemCommon(v) => match v[0] {
+            0xf0 => format!("sysex ({} bytes)", v.len()),
+            0xf1 => format!("mtc quarter frame {:02x}", v.get(1).unwrap_or(&0)),
+            0xf2 if v.len() == 3 => format!("song position {}", (v[2] as u16) << 7 | v[1] as u16),
+            0xf3 => format!("song select {}", v.get(1).unwrap_or(&0)),
+            0xf6 => String::from("tune request"),
+            _ => format!("system common {:02x?}", v)
+        }
+        MidiMessage::SystemRealtime(b) => match b {
+            0xf8 => String::from("clock"),
+            0xfa => String::from("start"),
+            0xfb => String::from("continue"),
+            0xfc => String::from("stop"),
+            0xfe => String::from("active sensing"),
+            0xff => String::from("reset"),
+            _ => format!("realtime {:02x}", b)
+        }
+    }
+}
+
+fn cc_name(cc: u8) -> Option<&'static str> {
+    Some(match cc {
+        0 => "bank select",
+        1 => "mod wheel",
+        2 => "breath",
+        4 => "foot pedal",
+        5 => "portamento time",
+        6 => "data entry",
+        7 => "volume",
+        8 => "balance",
+        10 => "pan",
+        11 => "expression",
+        32 => "bank select lsb",
+        38 => "data entry lsb",
+        64 => "sustain",
+        65 => "portamento",
+        66 => "sostenuto",
+        67 => "soft pedal",
+        71 => "resonance",
+        72 => "release",
+        73 => "attack",
+        74 => "cutoff",
+        91 => "reverb",
+        93 => "chorus",
+        98 => "nrpn lsb",
+        99 => "nrpn msb",
+        100 => "rpn lsb",
+        101 => "rpn msb",
+        120 => "all sound off",
+        121 => "reset all controllers",
+        123 => "all notes off",
+        _ => return None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptions() {
+        let d = |b: &[u8]| describe(&MidiMessage::from_slice(0, b).unwrap());
+        assert_eq!(d(&[0x90, 60, 100]), "ch 1 note on C4 (60) vel 100");
+        assert_eq!(d(&[0x9f, 61, 0]), "ch 16 note off C#4 (61)");
+        assert_eq!(d(&[0xb2, 74, 3]), "ch 3 cc 74 (cutoff) value 3");
+        assert_eq!(d(&[0xe0, 0, 0x40]), "ch 1 pitch bend 0");
+        assert_eq!(d(&[0xc4, 5]), "ch 5 program change 5");
+        assert_eq!(d(&[0xf8]), "clock");
+    }
+}
@@ -0,0 +1,253 @@
+use crate::utils::*;
+use crate::consts::mpedown_cmds::*;
+use crate::consts::mpedown_bend_modes::*;
+use crate::MidiIO;
+
+pub struct MpeDown {
+    channel: u8,
+    master: u8,
+    bend_mode: usize,
+    notes: Vec<(u8, u8)>, // (incoming channel, note), oldest first
+    bend: [u16; 16], // the latest bend on each incoming channel
+    sent_bend: u16,
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl MpeDown {
+    fn new(channel: u8, master: u8, name: String) -> MpeDown {
+        MpeDown {
+            channel, master, name,
+            bend_mode: IDX_HIGHEST,
+            notes: Vec::new(),
+            bend: [8192; 16],
+            sent_bend: 8192,
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !(1..=2).contains(&args.len()) {
+            return Err(MMMErr::ArgError)
+        }
+        let c: u8 = args[0].parse()?;
+        let master: u8 = match args.get(1) {
+            Some(s) => s.parse()?,
+            None => 1
+        };
+        if (1..=16).contains(&c) && (1..=16).contains(&master) {
+            Ok(Box::new(Self::new(c, master, name)) as Box<dyn MidiIO>)
+        }
+        else {
+            Err(MMMErr::ArgError)
+        }
+    }
+
+    fn process(&mut self, m: &MidiMessage) {
+        match m.channel() {
+            None => self.buf.push(m.clone()),
+            // zone-wide messages
+            Some(c) if c == self.master => self.buf.push(m.with_channel(self.channel)),
+            Some(c) => {
+                if m.is_note_on() {
+                    let n = m.note().unwrap();
+                    self.notes.push((c, n));
+                    // so the note starts off with the right bend
+                    self.update_bend();
+                    self.buf.push(m.with_channel(self.channel))
+                }
+                else if m.is_note_off() {
+                    let n = m.note().unwrap();
+                    if let Some(idx) = self.notes.iter().position(|k| *k == (c, n)) {
+                        self.notes.remove(idx);
+                        // two channels playing the same note share it once it's downmixed
+                        if !self.notes.iter().any(|(_, other)| *other == n) {
+                            self.buf.push(m.with_channel(self.channel))
+                        }
+                        self.update_bend()
+                    }
+                }
+                else {
+                    match m {
+                        MidiMessage::Channel([s, l, h]) if s & 0xf0 == 0xe0 => {
+                            self.bend[c as usize - 1] = (*h as u16) << 7 | *l as u16;
+                            self.update_bend()
+                        }
+                        MidiMessage::ChannelSmall([s, v]) if s & 0xf0 == 0xd0 => {
+                            for (_, n) in self.notes.iter().filter(|(nc, _)| *nc == c) {
+                                self.buf.push(MidiMessage::poly_pressure(self.channel, *n, *v))
+                            }
+                        }
+                        // anything else on a member channel only makes sense for that one note
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    /// sends the bend of the highest sounding note, if it's changed
+    fn update_bend(&mut self) {
+        if self.bend_mode != IDX_HIGHEST {
+            return
+        }
+        if let Some((c, _)) = self.notes.iter().max_by_key(|(_, n)| *n) {
+            let b = self.bend[*c as usize - 1];
+            if b != self.sent_bend {
+                self.buf.push(MidiMessage::pitch_bend(self.channel, b));
+                self.sent_bend = b
+            }
+        }
+    }
+    fn release_all(&mut self) {
+        let mut released = Vec::new();
+        for (_, n) in self.notes.drain(..) {
+            if !released.contains(&n) {
+                self.buf.push(MidiMessage::note_off(self.channel, n));
+                released.push(n)
+            }
+        }
+        if self.sent_bend != 8192 {
+            self.buf.push(MidiMessage::pitch_bend(self.channel, 8192));
+            self.sent_bend = 8192
+        }
+    }
+
+    fn parse_channel(args: &[String]) -> Option<u8> {
+        match args.first().map(|s| s.parse()) {
+            Some(Ok(c)) if (1..=16).contains(&c) => Some(c),
+            _ => {
+                outln!("channel number required");
+                None
+            }
+        }
+    }
+}
+impl MidiIO for MpeDown {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (mpedown)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("mpedown to channel {} with master channel {}, bend from {}, {} notes sounding",
+                self.channel, self.master, MPEDOWN_BEND_MODES[self.bend_mode], self.notes.len())
+        }
+        else {
+            match shortened_keyword_match(&command[0], MPEDOWN_CMDS) {
+                Some(IDX_CHANNEL) => match Self::parse_channel(&command[1..]) {
+                    Some(c) if c != self.channel => {
+                        // notes have to be let go on the channel they were started on
+                        self.release_all();
+                        self.channel = c
+                    }
+                    _ => {}
+                }
+                Some(IDX_MASTER) => if let Some(c) = Self::parse_channel(&command[1..]) {
+                    self.master = c
+                }
+                Some(IDX_BEND) => match command.get(1).and_then(|s| shortened_keyword_match(s, MPEDOWN_BEND_MODES)) {
+                    Some(mode) => {
+                        self.bend_mode = mode;
+                        self.update_bend()
+                    }
+                    None => {
+                        outln!("bend mode required! valid modes are:");
+                        for m in MPEDOWN_BEND_MODES {
+                            outln!("\t{}", m)
+                        }
+                    }
+                }
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in MPEDOWN_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        vec![String::from("mpedown"), self.channel.to_string(), self.master.to_string()]
+    }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        vec![vec![String::from("bend"), String::from(MPEDOWN_BEND_MODES[self.bend_mode])]]
+    }
+    fn release(&mut self) -> Vec<MidiMessage> {
+        self.release_all();
+        self.read()
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        for m in messages {
+            self.process(m)
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(msgs: Vec<MidiMessage>) -> Vec<Vec<u8>> {
+        msgs.iter().map(|m| m.to_bytes()).collect()
+    }
+
+    #[test]
+    fn bend_follows_highest_note() {
+        let mut d = MpeDown::new(1, 1, String::from("d"));
+        d.write(&[MidiMessage::pitch_bend(2, 9000), MidiMessage::note_on(2, 60, 100)]);
+        d.write(&[MidiMessage::pitch_bend(3, 8192), MidiMessage::note_on(3, 64, 100)]);
+        d.write(&[MidiMessage::pitch_bend(2, 10000)]);
+        d.write(&[MidiMessage::note_off(3, 64)]);
+        assert_eq!(bytes(d.read()), vec![
+            MidiMessage::pitch_bend(1, 9000).to_bytes(), vec![0x90, 60, 100],
+            MidiMessage::pitch_bend(1, 8192).to_bytes(), vec![0x90, 64, 100],
+            vec![0x80, 64, 0], MidiMessage::pitch_bend(1, 10000).to_bytes()
+        ]);
+        assert_eq!(bytes(d.release()), vec![vec![0x80, 60, 0], vec![0xe0, 0, 0x40]]);
+    }
+
+    #[test]
+    fn pressure_and_shared_notes() {
+        let mut d = MpeDown::new(5, 1, String::from("d"));
+        d.bend_mode = IDX_DROP;
+        d.write(&[MidiMessage::note_on(2, 60, 100), MidiMessage::note_on(3, 60, 100), MidiMessage::pitch_bend(3, 0)]);
+        d.write(&[MidiMessage::channel_pressure(3, 20), MidiMessage::control_change(3, 74, 1), MidiMessage::control_change(1, 64, 127)]);
+        d.write(&[MidiMessage::note_off(2, 60)]);
+        assert_eq!(bytes(d.read()), vec![vec![0x94, 60, 100], vec![0x94, 60, 100], vec![0xa4, 60, 20], vec![0xb4, 64, 127]]);
+        d.write(&[MidiMessage::note_off(3, 60)]);
+        assert_eq!(bytes(d.read()), vec![vec![0x84, 60, 0]]);
+    }
+
+    #[test]
+    fn changing_channel() {
+        let mut d = MpeDown::new(1, 1, String::from("d"));
+        d.write(&[MidiMessage::note_on(2, 60, 100)]);
+        d.read();
+        for c in ["17", "x", "1"] {
+            d.cfg(&[String::from("channel"), String::from(c)]);
+        }
+        assert!(d.read().is_empty());
+        assert_eq!(d.notes.len(), 1);
+        d.cfg(&[String::from("channel"), String::from("2")]);
+        assert_eq!(bytes(d.read()), vec![vec![0x80, 60, 0]]);
+        assert_eq!(d.channel, 2);
+    }
+}
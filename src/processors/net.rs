@@ -0,0 +1,284 @@
+use crate::utils::*;
+use crate::consts::netin_cmds;
+use crate::consts::netout_cmds;
+use crate::stream::StreamParser;
+use crate::MidiIO;
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// every datagram is this, then a u32 sequence number and a u64 timestamp in microseconds (both big endian), then
+/// one message
+const MAGIC: &[u8] = b"mm";
+const HEADER_LEN: usize = 14;
+
+struct Peer {
+    next_seq: u32,
+    transit: i64, // arrival time minus the sender's timestamp for the last packet, in microseconds
+    jitter: f64,
+}
+
+pub struct NetIn {
+    socket: UdpSocket,
+    port: u16,
+    peers: HashMap<SocketAddr, Peer>,
+    received: u64,
+    lost: u64, // packets skipped over that haven't turned up since
+    late: u64,
+    name: String,
+    outputs: Vec<Id>,
+}
+impl NetIn {
+    fn new(port: u16, name: String) -> Result<NetIn> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_nonblocking(true)?;
+        let port = socket.local_addr()?.port();
+        Ok(NetIn {
+            socket, port, name,
+            peers: HashMap::new(),
+            received: 0,
+            lost: 0,
+            late: 0,
+            outputs: Vec::new()
+        })
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if args.len() != 1 {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            let port = args[0].parse()?;
+            Self::new(port, name).map(|n| Box::new(n) as Box<dyn MidiIO>)
+        }
+    }
+
+    fn receive(&mut self, now: Duration, from: SocketAddr, bytes: &[u8]) -> Vec<MidiMessage> {
+        let (seq, sent, payload) = match decode_packet(bytes) {
+            Some(p) => p,
+            None => return Vec::new()
+        };
+        // the clocks aren't in sync, so only differences between transit times mean anything, and those survive wrapping
+        let transit = (now.as_micros() as i64).wrapping_sub(sent as i64);
+        self.received += 1;
+        match self.peers.get_mut(&from) {
+            // seq 0 means the sender has started again
+            Some(peer) if seq != 0 => {
+                // how far ahead of what was expected, allowing for the sequence number wrapping around
+                let ahead = seq.wrapping_sub(peer.next_seq) as i32;
+                if ahead >= 0 {
+                    self.lost += ahead as u64;
+                    peer.next_seq = seq.wrapping_add(1)
+                }
+                else {
+                    // it wasn't lost after all, just slow
+                    self.late += 1;
+                    self.lost = self.lost.saturating_sub(1)
+                }
+                let d = transit.wrapping_sub(peer.transit).unsigned_abs() as f64;
+                peer.jitter += (d - peer.jitter) / 16.0;
+                peer.transit = transit
+            }
+            _ => {
+                self.peers.insert(from, Peer { next_seq: seq.wrapping_add(1), transit, jitter: 0.0 });
+            }
+        }
+        StreamParser::default().feed(payload)
+    }
+}
+impl MidiIO for NetIn {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { false }
+
+    fn get_display_name(&self) -> String { format!("udp {} -> {} (netin, {} lost)", self.port, self.name, self.lost) }
+    fn get_name(&self) -> String { self.name.clone() }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("netin on udp port {}, {} packets received, {} lost, {} late", self.port, self.received, self.lost, self.late);
+            for (addr, peer) in &self.peers {
+                outln!("\t{}: jitter {:.2}ms", addr, peer.jitter / 1000.0)
+            }
+        }
+        else {
+            match shortened_keyword_match(&command[0], netin_cmds::NETIN_CMDS) {
+                Some(netin_cmds::IDX_RESET) => {
+                    self.received = 0;
+                    self.lost = 0;
+                    self.late = 0;
+                    self.peers.clear()
+                }
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in netin_cmds::NETIN_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> { vec![String::from("netin"), self.port.to_string()] }
+
+    fn write(&mut self, _messages: &[MidiMessage]) { unreachable!() }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        let mut buf = [0; 65536];
+        // non-blocking, so this stops when there's nothing left
+        while let Ok((len, from)) = self.socket.recv_from(&mut buf) {
+            ret.extend(self.receive(timestamp(), from, &buf[..len]))
+        }
+        ret
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+pub struct NetOut {
+    socket: UdpSocket,
+    addr: String,
+    seq: u32,
+    name: String,
+}
+impl NetOut {
+    fn new(addr: String, name: String) -> Result<NetOut> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(&addr)?;
+        Ok(NetOut {
+            socket, addr, name,
+            seq: 0
+        })
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if args.len() != 1 {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            Self::new(args[0].clone(), name).map(|n| Box::new(n) as Box<dyn MidiIO>)
+        }
+    }
+}
+impl MidiIO for NetOut {
+    fn can_read(&self) -> bool { false }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} -> udp {} (netout)", self.name, self.addr) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { unreachable!() }
+    fn add_output(&mut self, _id: Id) { unreachable!() }
+    fn rem_output(&mut self, _id: Id) { unreachable!() }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("netout sending to {}, {} packets sent", self.addr, self.seq)
+        }
+        else {
+            match shortened_keyword_match(&command[0], netout_cmds::NETOUT_CMDS) {
+                Some(netout_cmds::IDX_ADDRESS) => match command.get(1) {
+                    Some(addr) => match self.socket.connect(addr) {
+                        Ok(_) => {
+                            self.addr = addr.clone();
+                            // a new stream as far as the other end is concerned
+                            self.seq = 0
+                        }
+                        Err(e) => outln!("failed to connect: {}", e)
+                    }
+                    None => outln!("address required")
+                }
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in netout_cmds::NETOUT_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> { vec![String::from("netout"), self.addr.clone()] }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        for m in messages {
+            let _ = self.socket.send(&encode_packet(self.seq, timestamp(), m));
+            self.seq = self.seq.wrapping_add(1)
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> { unreachable!() }
+
+    fn delete(self: Box<Self>) { }
+}
+
+fn encode_packet(seq: u32, timestamp: Duration, m: &MidiMessage) -> Vec<u8> {
+    let mut ret = MAGIC.to_vec();
+    ret.extend(seq.to_be_bytes());
+    ret.extend((timestamp.as_micros() as u64).to_be_bytes());
+    ret.extend(m.to_bytes());
+    ret
+}
+/// (sequence number, timestamp, message bytes)
+fn decode_packet(bytes: &[u8]) -> Option<(u32, u64, &[u8])> {
+    if bytes.len() <= HEADER_LEN || !bytes.starts_with(MAGIC) {
+        return None
+    }
+    let seq = u32::from_be_bytes(bytes[2..6].try_into().unwrap());
+    let ts = u64::from_be_bytes(bytes[6..14].try_into().unwrap());
+    Some((seq, ts, &bytes[HEADER_LEN..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_loss() {
+        let mut n = NetIn::new(0, String::from("n")).unwrap();
+        let from: SocketAddr = "127.0.0.1:9999".parse().unwrap();
+        let m = MidiMessage::note_on(1, 60, 100);
+        for seq in [0, 1, 4, 5, 2] {
+            assert_eq!(n.receive(Duration::ZERO, from, &encode_packet(seq, Duration::ZERO, &m)), vec![m.clone()]);
+        }
+        assert_eq!((n.received, n.lost, n.late), (5, 1, 1));
+        assert!(n.receive(Duration::ZERO, from, b"junk").is_empty());
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut n = NetIn::new(0, String::from("n")).unwrap();
+        let from: SocketAddr = "127.0.0.1:9999".parse().unwrap();
+        let m = MidiMessage::note_on(1, 60, 100);
+        let sent = Duration::from_micros(u64::MAX);
+        for seq in [u32::MAX - 1, u32::MAX, 2, 1] {
+            n.receive(Duration::from_secs(1), from, &encode_packet(seq, sent, &m));
+        }
+        // 0 and 1 went missing, then 1 turned up
+        assert_eq!((n.received, n.lost, n.late), (4, 1, 1));
+        assert_eq!(n.peers[&from].next_seq, 3);
+        assert_eq!(n.peers[&from].jitter, 0.0)
+    }
+
+    #[test]
+    fn localhost() {
+        let mut n = NetIn::new(0, String::from("n")).unwrap();
+        let mut o = NetOut::new(format!("127.0.0.1:{}", n.port), String::from("o")).unwrap();
+        let msgs = vec![MidiMessage::note_on(1, 60, 100), MidiMessage::SystemCommon(vec![0xf0, 1, 2, 0xf7])];
+        o.write(&msgs);
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            received.extend(n.read());
+            if received.len() == msgs.len() {
+                break
+            }
+            sleep_ms(10)
+        }
+        assert_eq!(received, msgs);
+        assert_eq!(n.lost, 0)
+    }
+}
@@ -0,0 +1,235 @@
+use crate::utils::*;
+use crate::consts::oscin_cmds;
+use crate::consts::oscout_cmds;
+use crate::osc::*;
+use crate::MidiIO;
+use std::net::UdpSocket;
+
+pub struct OscIn {
+    socket: UdpSocket,
+    port: u16,
+    mappings: Vec<Mapping>,
+    file: Option<String>,
+    unmatched: u64,
+    name: String,
+    outputs: Vec<Id>,
+}
+impl OscIn {
+    fn new(port: u16, file: Option<String>, name: String) -> Result<OscIn> {
+        let mappings = match &file {
+            Some(f) => load_mappings(f)?,
+            None => Vec::new()
+        };
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_nonblocking(true)?;
+        let port = socket.local_addr()?.port();
+        Ok(OscIn {
+            socket, port, mappings, file, name,
+            unmatched: 0,
+            outputs: Vec::new()
+        })
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !(1..=2).contains(&args.len()) {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            let port = args[0].parse()?;
+            Self::new(port, args.get(1).cloned(), name).map(|o| Box::new(o) as Box<dyn MidiIO>)
+        }
+    }
+
+    fn receive(&mut self, bytes: &[u8]) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        for msg in parse_packet(bytes) {
+            let before = ret.len();
+            // osc has the sender's address as the pattern, but patterns in mappings are handy for catching a group
+            ret.extend(self.mappings.iter()
+                .filter(|m| pattern_matches(&msg.addr, &m.address) || pattern_matches(&m.address, &msg.addr))
+                .filter_map(|m| m.to_midi(&msg)));
+            if ret.len() == before {
+                self.unmatched += 1
+            }
+        }
+        ret
+    }
+}
+impl MidiIO for OscIn {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { false }
+
+    fn get_display_name(&self) -> String { format!("osc {} -> {} (oscin)", self.port, self.name) }
+    fn get_name(&self) -> String { self.name.clone() }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("oscin on udp port {} with {} mappings from {}, {} messages didn't match",
+                self.port, self.mappings.len(), self.file.as_deref().unwrap_or("nowhere"), self.unmatched)
+        }
+        else {
+            match shortened_keyword_match(&command[0], oscin_cmds::OSCIN_CMDS) {
+                Some(oscin_cmds::IDX_LOAD) => load(&command[1..], &mut self.file, &mut self.mappings),
+                Some(oscin_cmds::IDX_CLEAR) => self.mappings.clear(),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in oscin_cmds::OSCIN_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        let mut ret = vec![String::from("oscin"), self.port.to_string()];
+        ret.extend(self.file.clone());
+        ret
+    }
+
+    fn write(&mut self, _messages: &[MidiMessage]) { unreachable!() }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        let mut buf = [0; 65536];
+        while let Ok(len) = self.socket.recv(&mut buf) {
+            ret.extend(self.receive(&buf[..len]))
+        }
+        ret
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+pub struct OscOut {
+    socket: UdpSocket,
+    addr: String,
+    mappings: Vec<Mapping>,
+    file: Option<String>,
+    name: String,
+}
+impl OscOut {
+    fn new(addr: String, file: Option<String>, name: String) -> Result<OscOut> {
+        let mappings = match &file {
+            Some(f) => load_mappings(f)?,
+            None => Vec::new()
+        };
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(&addr)?;
+        Ok(OscOut { socket, addr, mappings, file, name })
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !(1..=2).contains(&args.len()) {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            Self::new(args[0].clone(), args.get(1).cloned(), name).map(|o| Box::new(o) as Box<dyn MidiIO>)
+        }
+    }
+
+    fn translate(&self, messages: &[MidiMessage]) -> Vec<Message> {
+        messages.iter().flat_map(|m| self.mappings.iter().filter_map(move |map| map.to_osc(m))).collect()
+    }
+}
+impl MidiIO for OscOut {
+    fn can_read(&self) -> bool { false }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} -> osc {} (oscout)", self.name, self.addr) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { unreachable!() }
+    fn add_output(&mut self, _id: Id) { unreachable!() }
+    fn rem_output(&mut self, _id: Id) { unreachable!() }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("oscout sending to {} with {} mappings from {}", self.addr, self.mappings.len(), self.file.as_deref().unwrap_or("nowhere"))
+        }
+        else {
+            match shortened_keyword_match(&command[0], oscout_cmds::OSCOUT_CMDS) {
+                Some(oscout_cmds::IDX_LOAD) => load(&command[1..], &mut self.file, &mut self.mappings),
+                Some(oscout_cmds::IDX_CLEAR) => self.mappings.clear(),
+                Some(oscout_cmds::IDX_ADDRESS) => match command.get(1) {
+                    Some(addr) => match self.socket.connect(addr) {
+                        Ok(_) => self.addr = addr.clone(),
+                        Err(e) => outln!("failed to connect: {}", e)
+                    }
+                    None => outln!("address required")
+                }
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in oscout_cmds::OSCOUT_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        let mut ret = vec![String::from("oscout"), self.addr.clone()];
+        ret.extend(self.file.clone());
+        ret
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        for msg in self.translate(messages) {
+            let _ = self.socket.send(&msg.to_bytes());
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> { unreachable!() }
+
+    fn delete(self: Box<Self>) { }
+}
+
+/// with no file given, reloads the last one
+fn load(args: &[String], file: &mut Option<String>, mappings: &mut Vec<Mapping>) {
+    match args.first().or(file.as_ref()).cloned() {
+        Some(f) => match load_mappings(&f) {
+            Ok(m) => {
+                *mappings = m;
+                *file = Some(f)
+            }
+            Err(e) => outln!("failed to load mappings: {}", e)
+        }
+        None => outln!("file name required")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn localhost() {
+        let mut i = OscIn::new(0, None, String::from("i")).unwrap();
+        let mut o = OscOut::new(format!("127.0.0.1:{}", i.port), None, String::from("o")).unwrap();
+        i.mappings = parse_mappings("/synth/1/cutoff f cc 1 74\n/synth/*/res i cc 2 71").unwrap();
+        o.mappings = parse_mappings("/synth/1/cutoff f cc 1 74\n/synth/3/res i cc 2 71\n/other i program 1").unwrap();
+
+        o.write(&[
+            MidiMessage::control_change(1, 74, 127), MidiMessage::note_on(1, 60, 100),
+            MidiMessage::control_change(2, 71, 5), MidiMessage::program_change(1, 4)
+        ]);
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            received.extend(i.read());
+            if i.unmatched == 1 {
+                break
+            }
+            sleep_ms(10)
+        }
+        assert_eq!(received, vec![MidiMessage::control_change(1, 74, 127), MidiMessage::control_change(2, 71, 5)]);
+
+        // patterns can come from the sender too
+        let msg = Message { addr: String::from("/synth/?/cutoff"), args: vec![Arg::Float(0.0)] };
+        assert_eq!(i.receive(&msg.to_bytes()), vec![MidiMessage::control_change(1, 74, 0)]);
+    }
+}
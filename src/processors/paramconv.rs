@@ -0,0 +1,166 @@
+use crate::utils::*;
+use crate::consts::paramconv_cmds::*;
+use crate::params::*;
+use crate::MidiIO;
+
+pub struct ParamConv {
+    assembler: ParamAssembler,
+    maps: Vec<((ParamKind, u16), (ParamKind, u16))>,
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl ParamConv {
+    fn new(name: String) -> ParamConv {
+        ParamConv {
+            name,
+            assembler: ParamAssembler::new(),
+            maps: Vec::new(),
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !args.is_empty() {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            Ok(Box::new(Self::new(name)) as Box<dyn MidiIO>)
+        }
+    }
+
+    fn process(&mut self, events: Vec<Event>) {
+        for e in events {
+            match e {
+                Event::Param(mut p) => {
+                    if let Some((_, (kind, number))) = self.maps.iter().find(|(from, _)| matches(*from, &p)) {
+                        p.kind = *kind;
+                        p.number = *number
+                    }
+                    self.buf.extend(p.to_messages())
+                }
+                Event::Message(m) => self.buf.push(m)
+            }
+        }
+    }
+
+    fn map(&mut self, args: &[String]) {
+        match (parse_param(args), parse_param(args.get(2..).unwrap_or(&[]))) {
+            (Some(from), Some(to)) => {
+                self.maps.retain(|(f, _)| *f != from);
+                self.maps.push((from, to))
+            }
+            _ => outln!("source and destination required, as KIND NUMBER KIND NUMBER")
+        }
+    }
+    fn unmap(&mut self, args: &[String]) {
+        match parse_param(args) {
+            Some(from) => self.maps.retain(|(f, _)| *f != from),
+            None => outln!("source required, as KIND NUMBER")
+        }
+    }
+}
+impl MidiIO for ParamConv {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (paramconv)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("paramconv with {} mappings", self.maps.len());
+            for ((fk, fnum), (tk, tnum)) in &self.maps {
+                outln!("\t{} {} -> {} {}", fk.kwd(), fnum, tk.kwd(), tnum)
+            }
+        }
+        else {
+            match shortened_keyword_match(&command[0], PARAMCONV_CMDS) {
+                Some(IDX_MAP) => self.map(&command[1..]),
+                Some(IDX_UNMAP) => self.unmap(&command[1..]),
+                Some(IDX_CLEAR) => self.maps.clear(),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in PARAMCONV_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> { vec![String::from("paramconv")] }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        self.maps.iter().map(|((fk, fnum), (tk, tnum))| {
+            vec![String::from("map"), String::from(fk.kwd()), fnum.to_string(), String::from(tk.kwd()), tnum.to_string()]
+        }).collect()
+    }
+    fn release(&mut self) -> Vec<MidiMessage> {
+        let events = self.assembler.flush();
+        self.process(events);
+        self.read()
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        let now = timestamp();
+        for m in messages {
+            let events = self.assembler.feed(now, m);
+            self.process(events)
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let events = self.assembler.poll(timestamp());
+        self.process(events);
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+/// cc and cc14 are the same controller, whether or not it's sending an lsb
+fn matches((kind, number): (ParamKind, u16), p: &Param) -> bool {
+    let cc = |k| matches!(k, ParamKind::Cc | ParamKind::Cc14);
+    number == p.number && (kind == p.kind || (cc(kind) && cc(p.kind)))
+}
+
+fn parse_param(args: &[String]) -> Option<(ParamKind, u16)> {
+    let kind = ParamKind::from_kwd(args.first()?)?;
+    let number: u16 = args.get(1)?.parse().ok()?;
+    if number < kind.max_number() {
+        Some((kind, number))
+    }
+    else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nrpn_to_cc14_and_back() {
+        let mut p = ParamConv::new(String::from("p"));
+        let args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        p.cfg(&args("map nrpn 300 cc14 1"));
+        p.cfg(&args("map cc 7 nrpn 4"));
+        p.write(&Param { channel: 2, kind: ParamKind::Nrpn, number: 300, value: 1000 }.to_messages());
+        p.write(&[MidiMessage::control_change(2, 7, 64), MidiMessage::note_on(2, 60, 100)]);
+        let bytes: Vec<Vec<u8>> = p.read().iter().map(|m| m.to_bytes()).collect();
+        assert_eq!(bytes, vec![
+            vec![0xb1, 1, 7], vec![0xb1, 33, 104],
+            vec![0xb1, 99, 0], vec![0xb1, 98, 4], vec![0xb1, 6, 64], vec![0xb1, 38, 0],
+            vec![0x91, 60, 100]
+        ]);
+        assert_eq!(p.cfg_lines().len(), 2);
+    }
+}
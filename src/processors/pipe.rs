@@ -0,0 +1,425 @@
+use crate::utils::*;
+use crate::consts::pipe_formats::*;
+use crate::consts::pipein_cmds;
+use crate::consts::pipeout_cmds;
+use crate::shell::print_line;
+use crate::stream::StreamParser;
+use crate::text::{from_text, to_text};
+use crate::MidiIO;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// opening a fifo and reading from it both block, so that's done on another thread
+enum Chunk {
+    Data(Vec<u8>),
+    End, // whoever was writing has finished, and anything after this is from someone new
+}
+
+pub struct PipeIn {
+    path: String,
+    format: usize, // index into PIPE_FORMATS
+    receiver: Option<Receiver<Chunk>>, // None once a file's been read to the end
+    fifo: bool,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+    parser: StreamParser,
+    line: Vec<u8>, // the text line so far
+    lines: u64,
+    name: String,
+    outputs: Vec<Id>,
+}
+impl PipeIn {
+    fn new(path: String, format: usize, name: String) -> Result<PipeIn> {
+        let (sender, receiver) = channel();
+        let fifo = is_fifo(&path);
+        // open it here when it won't block, so a missing file is an error straight away
+        let file = if path == "-" || fifo { None } else { Some(File::open(&path)?) };
+        let thread_path = path.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || read_thread(thread_path, file, fifo, thread_stop, sender));
+        Ok(PipeIn {
+            path, format, name, fifo, stop, thread,
+            receiver: Some(receiver),
+            parser: StreamParser::default(),
+            line: Vec::new(),
+            lines: 0,
+            outputs: Vec::new()
+        })
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        let format = parse_format(args)?;
+        Self::new(args[0].clone(), format, name).map(|p| Box::new(p) as Box<dyn MidiIO>)
+    }
+
+    fn receive(&mut self, chunk: Chunk) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        match chunk {
+            Chunk::Data(bytes) if self.format == IDX_RAW => ret.extend(self.parser.feed(&bytes)),
+            Chunk::Data(bytes) => {
+                for b in bytes {
+                    if b == b'\n' {
+                        ret.extend(self.text_line())
+                    }
+                    else {
+                        self.line.push(b)
+                    }
+                }
+            }
+            Chunk::End => {
+                ret.extend(self.text_line());
+                self.parser = StreamParser::default()
+            }
+        }
+        ret
+    }
+    fn text_line(&mut self) -> Option<MidiMessage> {
+        let line = String::from_utf8_lossy(&std::mem::take(&mut self.line)).into_owned();
+        let line = line.trim();
+        if line.is_empty() {
+            return None
+        }
+        self.lines += 1;
+        if line.starts_with('#') {
+            return None
+        }
+        let ret = from_text(line);
+        if ret.is_none() {
+            print_line(format!("{}: couldn't understand line {}: {}", self.name, self.lines, line))
+        }
+        ret
+    }
+}
+impl MidiIO for PipeIn {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { false }
+
+    fn get_display_name(&self) -> String { format!("{} -> {} (pipein, {})", self.path, self.name, PIPE_FORMATS[self.format]) }
+    fn get_name(&self) -> String { self.name.clone() }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            let state = if self.receiver.is_some() { "reading" } else { "finished reading" };
+            outln!("pipein {} {} as {}", state, self.path, PIPE_FORMATS[self.format])
+        }
+        else {
+            match shortened_keyword_match(&command[0], pipein_cmds::PIPEIN_CMDS) {
+                Some(pipein_cmds::IDX_FORMAT) => set_format(command.get(1), &mut self.format),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in pipein_cmds::PIPEIN_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        vec![String::from("pipein"), self.path.clone(), String::from(PIPE_FORMATS[self.format])]
+    }
+
+    fn write(&mut self, _messages: &[MidiMessage]) { unreachable!() }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        while let Some(receiver) = &self.receiver {
+            match receiver.try_recv() {
+                Ok(chunk) => ret.extend(self.receive(chunk)),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    ret.extend(self.receive(Chunk::End));
+                    self.receiver = None;
+                    print_line(format!("{}: reached the end of {}", self.name, self.path))
+                }
+            }
+        }
+        ret
+    }
+
+    /// the reader could be stuck waiting for someone to open the fifo, which would take the next writer's data with it
+    fn delete(self: Box<Self>) {
+        let PipeIn { path, fifo, stop, thread, .. } = *self;
+        stop.store(true, Ordering::Relaxed);
+        for _ in 0..10 {
+            if !fifo || thread.is_finished() {
+                break
+            }
+            wake_reader(&path);
+            sleep_ms(10)
+        }
+    }
+}
+
+pub struct PipeOut {
+    path: String,
+    format: usize,
+    sender: Sender<Vec<u8>>,
+    thread: thread::JoinHandle<()>,
+    name: String,
+}
+impl PipeOut {
+    fn new(path: String, format: usize, name: String) -> Result<PipeOut> {
+        let (sender, receiver) = channel();
+        let fifo = is_fifo(&path);
+        let file = if path == "-" || fifo { None } else { Some(File::create(&path)?) };
+        let thread_path = path.clone();
+        let thread = thread::spawn(move || write_thread(thread_path, file, receiver));
+        Ok(PipeOut { path, format, sender, thread, name })
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        let format = parse_format(args)?;
+        Self::new(args[0].clone(), format, name).map(|p| Box::new(p) as Box<dyn MidiIO>)
+    }
+}
+impl MidiIO for PipeOut {
+    fn can_read(&self) -> bool { false }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} -> {} (pipeout, {})", self.name, self.path, PIPE_FORMATS[self.format]) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { unreachable!() }
+    fn add_output(&mut self, _id: Id) { unreachable!() }
+    fn rem_output(&mut self, _id: Id) { unreachable!() }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("pipeout writing to {} as {}", self.path, PIPE_FORMATS[self.format])
+        }
+        else {
+            match shortened_keyword_match(&command[0], pipeout_cmds::PIPEOUT_CMDS) {
+                Some(pipeout_cmds::IDX_FORMAT) => set_format(command.get(1), &mut self.format),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in pipeout_cmds::PIPEOUT_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        vec![String::from("pipeout"), self.path.clone(), String::from(PIPE_FORMATS[self.format])]
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        let mut bytes = Vec::new();
+        for m in messages {
+            if self.format == IDX_RAW {
+                // no running status, since whoever opens a fifo next won't know what came before
+                bytes.extend(m.to_bytes())
+            }
+            else {
+                bytes.extend(to_text(m).as_bytes());
+                bytes.push(b'\n')
+            }
+        }
+        // the thread only goes away if the file can't be written to, and then there's nothing to be done
+        let _ = self.sender.send(bytes);
+    }
+    fn read(&mut self) -> Vec<MidiMessage> { unreachable!() }
+
+    /// lets the writer finish what it's been sent. not for long though, since a fifo might have nobody reading it
+    fn delete(self: Box<Self>) {
+        let PipeOut { sender, thread, .. } = *self;
+        drop(sender);
+        for _ in 0..10 {
+            if thread.is_finished() {
+                break
+            }
+            sleep_ms(10)
+        }
+    }
+}
+
+/// `PATH [FORMAT]`, where format defaults to text
+fn parse_format(args: &[String]) -> Result<usize> {
+    match args.len() {
+        1 => Ok(IDX_TEXT),
+        2 => shortened_keyword_match(&args[1], PIPE_FORMATS).ok_or(MMMErr::ArgError),
+        _ => Err(MMMErr::ArgError)
+    }
+}
+fn set_format(arg: Option<&String>, format: &mut usize) {
+    match arg.map(|f| shortened_keyword_match(f, PIPE_FORMATS)) {
+        Some(Some(f)) => *format = f,
+        Some(None) => outln!("unknown format"),
+        None => outln!("format required")
+    }
+}
+
+#[cfg(unix)]
+fn is_fifo(path: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path).is_ok_and(|m| m.file_type().is_fifo())
+}
+#[cfg(not(unix))]
+fn is_fifo(_path: &str) -> bool {
+    false
+}
+/// opens a fifo for writing and closes it again, so a reader waiting to open it can carry on. doesn't wait for a
+/// reader if there isn't one
+#[cfg(unix)]
+fn wake_reader(path: &str) {
+    use std::os::unix::fs::OpenOptionsExt;
+    let _ = OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(path);
+}
+#[cfg(not(unix))]
+fn wake_reader(_path: &str) {}
+
+/// fifos are opened again after each writer finishes, files are read once. `-` is stdin
+fn read_thread(path: String, mut file: Option<File>, fifo: bool, stop: Arc<AtomicBool>, sender: Sender<Chunk>) {
+    let mut buf = [0; 4096];
+    loop {
+        let mut source: Box<dyn Read> = match file.take() {
+            Some(f) => Box::new(f),
+            None if path == "-" => Box::new(std::io::stdin()),
+            // blocks until someone opens it for writing
+            None => match File::open(&path) {
+                Ok(f) => Box::new(f),
+                Err(_) => return
+            }
+        };
+        if stop.load(Ordering::Relaxed) {
+            return
+        }
+        loop {
+            match source.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => if sender.send(Chunk::Data(buf[..len].to_vec())).is_err() {
+                    return
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => break
+            }
+        }
+        if !fifo || stop.load(Ordering::Relaxed) || sender.send(Chunk::End).is_err() {
+            return
+        }
+    }
+}
+/// fifos are opened again if the reader goes away, and what couldn't be written to it is dropped. `-` is stdout
+fn write_thread(path: String, mut file: Option<File>, receiver: Receiver<Vec<u8>>) {
+    let mut sink: Option<Box<dyn Write>> = None;
+    for bytes in receiver {
+        if sink.is_none() {
+            sink = match file.take() {
+                Some(f) => Some(Box::new(f)),
+                None if path == "-" => Some(Box::new(std::io::stdout())),
+                // blocks until someone opens it for reading
+                None => match OpenOptions::new().write(true).open(&path) {
+                    Ok(f) => Some(Box::new(f)),
+                    Err(_) => return
+                }
+            }
+        }
+        let ok = sink.as_mut().is_some_and(|s| s.write_all(&bytes).and_then(|_| s.flush()).is_ok());
+        if !ok {
+            if path == "-" || !is_fifo(&path) {
+                return
+            }
+            sink = None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(p: &mut PipeIn) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        for _ in 0..100 {
+            ret.extend(p.read());
+            if p.receiver.is_none() {
+                break
+            }
+            sleep_ms(10)
+        }
+        ret
+    }
+
+    #[test]
+    fn files() {
+        let dir = std::env::temp_dir();
+        let text = dir.join(format!("mmm-pipe-{}.txt", std::process::id()));
+        let raw = dir.join(format!("mmm-pipe-{}.raw", std::process::id()));
+        let text = text.to_str().unwrap();
+        let raw = raw.to_str().unwrap();
+
+        let msgs = vec![MidiMessage::note_on(1, 60, 100), MidiMessage::SystemCommon(vec![0xf0, 1, 0xf7]), MidiMessage::pitch_bend(2, 0)];
+        for (path, format) in [(text, IDX_TEXT), (raw, IDX_RAW)] {
+            let mut out = PipeOut::new(String::from(path), format, String::from("o")).unwrap();
+            out.write(&msgs);
+            drop(out);
+            // the writer thread finishes once the sender's gone
+            let mut received = Vec::new();
+            for _ in 0..100 {
+                let mut p = PipeIn::new(String::from(path), format, String::from("i")).unwrap();
+                received = read_all(&mut p);
+                if received.len() == msgs.len() {
+                    break
+                }
+                sleep_ms(10)
+            }
+            assert_eq!(received, msgs);
+        }
+        assert_eq!(std::fs::read_to_string(text).unwrap(), "note_on ch=1 n=60 v=100\nraw f0 01 f7\npitch_bend ch=2 v=-8192\n");
+
+        // comments, a bad line, and no newline at the end
+        std::fs::write(text, "# setup\ncc ch=1 c=7 v=100\nnonsense\n\nprogram ch=1 p=3").unwrap();
+        let mut p = PipeIn::new(String::from(text), IDX_TEXT, String::from("i")).unwrap();
+        assert_eq!(read_all(&mut p), vec![MidiMessage::control_change(1, 7, 100), MidiMessage::program_change(1, 3)]);
+
+        std::fs::remove_file(text).unwrap();
+        std::fs::remove_file(raw).unwrap();
+        assert!(PipeIn::new(String::from(text), IDX_TEXT, String::from("i")).is_err())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fifo() {
+        let path = std::env::temp_dir().join(format!("mmm-pipe-{}.fifo", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert!(std::process::Command::new("mkfifo").arg(path).status().unwrap().success());
+        let mut p = PipeIn::new(String::from(path), IDX_TEXT, String::from("i")).unwrap();
+        let mut received = Vec::new();
+        let mut read_until = |p: &mut PipeIn, count| {
+            for _ in 0..100 {
+                received.extend(p.read());
+                if received.len() == count {
+                    break
+                }
+                sleep_ms(10)
+            }
+            received.clone()
+        };
+        // one writer after another, the first without a newline at the end
+        std::fs::write(path, "note_on ch=1 n=60 v=1").unwrap();
+        assert_eq!(read_until(&mut p, 1), vec![MidiMessage::note_on(1, 60, 1)]);
+        std::fs::write(path, "note_off ch=1 n=60\n").unwrap();
+        assert_eq!(read_until(&mut p, 2), vec![MidiMessage::note_on(1, 60, 1), MidiMessage::note_off(1, 60)]);
+        assert!(p.receiver.is_some());
+
+        // nothing's left behind waiting to read from it
+        Box::new(p).delete();
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            assert!(OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(path).is_err());
+        }
+        std::fs::remove_file(path).unwrap()
+    }
+}
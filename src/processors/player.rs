@@ -0,0 +1,294 @@
+use crate::utils::*;
+use crate::consts::player_cmds::*;
+use crate::smf::{Smf, read_smf};
+use crate::ump::{is_clip, read_clip};
+use crate::MidiIO;
+use std::time::Duration;
+
+pub struct Player {
+    smf: Smf,
+    file: String,
+    pos: f64, // in ticks
+    next: usize, // index of the next event to play
+    playing: bool,
+    looping: bool,
+    follow: bool, // follow external clock instead of the tempo map
+    speed: f64, // 1.0 is as written
+    last: Duration,
+    sounding: Vec<(u8, u8)>, // (channel, note)
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl Player {
+    fn new(smf: Smf, file: String, name: String) -> Player {
+        Player {
+            smf, file, name,
+            pos: 0.0,
+            next: 0,
+            playing: false,
+            looping: false,
+            follow: false,
+            speed: 1.0,
+            last: timestamp(),
+            sounding: Vec::new(),
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if args.len() != 1 {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            let smf = read_file(&args[0])?;
+            Ok(Box::new(Self::new(smf, args[0].clone(), name)) as Box<dyn MidiIO>)
+        }
+    }
+
+    fn notes_off(&mut self) {
+        for (c, n) in self.sounding.drain(..) {
+            self.buf.push(MidiMessage::note_off(c, n))
+        }
+    }
+    fn seek(&mut self, tick: f64) {
+        self.notes_off();
+        self.pos = tick;
+        self.next = self.smf.events.partition_point(|(t, _)| (*t as f64) < tick)
+    }
+    fn stop(&mut self) {
+        self.playing = false;
+        self.notes_off()
+    }
+
+    /// play everything up to the current position, dealing with the end of the file
+    fn play_to_pos(&mut self) {
+        while let Some((t, m)) = self.smf.events.get(self.next) {
+            if *t as f64 > self.pos {
+                break
+            }
+            if let (Some(c), Some(n)) = (m.channel(), m.note()) {
+                if m.is_note_on() {
+                    push_if_not_present((c, n), &mut self.sounding)
+                }
+                else {
+                    self.sounding.retain(|k| *k != (c, n))
+                }
+            }
+            self.buf.push(m.clone());
+            self.next += 1
+        }
+        if self.pos >= self.smf.length as f64 {
+            if self.looping && self.smf.length > 0 {
+                let over = self.pos - self.smf.length as f64;
+                self.seek(over);
+                self.play_to_pos()
+            }
+            else {
+                self.stop()
+            }
+        }
+    }
+    fn advance(&mut self, now: Duration) {
+        let dt = now - self.last;
+        self.last = now;
+        if self.playing && !self.follow {
+            let us_per_tick = self.smf.tempo_at(self.pos as u64) as f64 / self.smf.ppq as f64 / self.speed;
+            self.pos += dt.as_micros() as f64 / us_per_tick;
+            self.play_to_pos()
+        }
+    }
+    fn clock(&mut self, m: &MidiMessage) {
+        match m {
+            MidiMessage::SystemRealtime(0xf8) if self.playing => {
+                self.pos += self.smf.ppq as f64 / 24.0;
+                self.play_to_pos()
+            }
+            MidiMessage::SystemRealtime(0xfa) => {
+                self.seek(0.0);
+                self.playing = true;
+                self.play_to_pos()
+            }
+            MidiMessage::SystemRealtime(0xfb) => self.playing = true,
+            MidiMessage::SystemRealtime(0xfc) => self.stop(),
+            // song position is in 16th notes
+            MidiMessage::SystemCommon(v) if v.len() == 3 && v[0] == 0xf2 => {
+                let sixteenths = (v[2] as u16) << 7 | v[1] as u16;
+                self.seek(sixteenths as f64 * self.smf.ppq as f64 / 4.0)
+            }
+            _ => {}
+        }
+    }
+
+    fn cfg_seek(&mut self, args: &[String]) {
+        match args.first().map(|s| s.parse::<f64>()) {
+            Some(Ok(beat)) if beat >= 0.0 => self.seek(beat * self.smf.ppq as f64),
+            _ => outln!("position in beats required")
+        }
+    }
+    fn cfg_tempo(&mut self, args: &[String]) {
+        match args.first().map(|s| s.parse::<f64>()) {
+            Some(Ok(percent)) if percent > 0.0 => self.speed = percent / 100.0,
+            _ => outln!("tempo percentage required")
+        }
+    }
+    fn load(&mut self, args: &[String]) {
+        match args.first() {
+            Some(file) => match read_file(file) {
+                Ok(smf) => {
+                    self.stop();
+                    self.smf = smf;
+                    self.file = file.clone();
+                    self.seek(0.0)
+                }
+                Err(e) => outln!("failed to load file: {}", e)
+            }
+            None => outln!("file name required")
+        }
+    }
+}
+impl MidiIO for Player {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (player)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            let state = if self.playing { "playing" } else { "stopped" };
+            let beat = self.pos / self.smf.ppq as f64;
+            let length = self.smf.length as f64 / self.smf.ppq as f64;
+            out!("player {} {} at beat {:.2} of {:.2}, {}% speed", state, self.file, beat, length, self.speed * 100.0);
+            if self.looping {
+                out!(", looping")
+            }
+            if self.follow {
+                out!(", following clock")
+            }
+            outln!()
+        }
+        else {
+            match shortened_keyword_match(&command[0], PLAYER_CMDS) {
+                Some(IDX_PLAY) => {
+                    if self.pos >= self.smf.length as f64 {
+                        self.seek(0.0)
+                    }
+                    self.last = timestamp();
+                    self.playing = true
+                }
+                Some(IDX_STOP) => self.stop(),
+                Some(IDX_SEEK) => self.cfg_seek(&command[1..]),
+                Some(IDX_LOOP) => self.looping = !self.looping,
+                Some(IDX_TEMPO) => self.cfg_tempo(&command[1..]),
+                Some(IDX_FOLLOW) => self.follow = !self.follow,
+                Some(IDX_LOAD) => self.load(&command[1..]),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in PLAYER_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> { vec![String::from("player"), self.file.clone()] }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        let mut ret = vec![vec![String::from("tempo"), (self.speed * 100.0).to_string()]];
+        if self.looping {
+            ret.push(vec![String::from("loop")])
+        }
+        if self.follow {
+            ret.push(vec![String::from("follow")])
+        }
+        ret
+    }
+    fn release(&mut self) -> Vec<MidiMessage> {
+        self.stop();
+        self.read()
+    }
+
+    /// only clock, start, stop, continue and song position are listened to, and only when following clock
+    fn write(&mut self, messages: &[MidiMessage]) {
+        if self.follow {
+            for m in messages {
+                self.clock(m)
+            }
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        self.advance(timestamp());
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+/// standard midi files and midi 2.0 clip files
+fn read_file(path: &str) -> Result<Smf> {
+    let bytes = std::fs::read(path)?;
+    if is_clip(&bytes) { read_clip(&bytes) } else { read_smf(&bytes) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smf::write_smf;
+
+    fn player() -> Player {
+        let events = vec![
+            (Duration::ZERO, MidiMessage::note_on(1, 60, 100)),
+            (Duration::from_millis(500), MidiMessage::note_on(1, 62, 100)),
+            (Duration::from_millis(1000), MidiMessage::note_off(1, 60)),
+            (Duration::from_millis(1000), MidiMessage::note_off(1, 62)),
+        ];
+        let smf = read_smf(&write_smf(&events, 0)).unwrap();
+        let mut p = Player::new(smf, String::new(), String::from("p"));
+        p.last = Duration::ZERO;
+        p.playing = true;
+        p
+    }
+
+    #[test]
+    fn follows_tempo() {
+        let mut p = player();
+        p.advance(Duration::from_millis(400));
+        assert_eq!(p.buf.len(), 1);
+        p.speed = 0.5;
+        // at half speed, 100ms is only 50ms into the file
+        p.advance(Duration::from_millis(500));
+        assert_eq!(p.buf.len(), 1);
+        p.advance(Duration::from_millis(1000));
+        assert_eq!(p.buf.len(), 2);
+    }
+
+    #[test]
+    fn stopping_ends_notes() {
+        let mut p = player();
+        p.advance(Duration::from_millis(600));
+        p.buf.clear();
+        p.stop();
+        let offs: Vec<Vec<u8>> = p.buf.iter().map(|m| m.to_bytes()).collect();
+        assert_eq!(offs, vec![vec![0x80, 60, 0], vec![0x80, 62, 0]]);
+    }
+
+    #[test]
+    fn follows_clock() {
+        let mut p = player();
+        p.follow = true;
+        p.write(&[MidiMessage::SystemRealtime(0xfa)]);
+        assert_eq!(p.buf.len(), 1);
+        p.write(&vec![MidiMessage::SystemRealtime(0xf8); 24]);
+        assert_eq!(p.buf.len(), 2);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::utils::*;
+use crate::consts::progmap_cmds::*;
+use crate::consts::progmap_line_kwds::*;
+use crate::MidiIO;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use shell_words::split;
+
+/// program -> everything to send when it comes in
+type Mappings = HashMap<u8, Vec<MidiMessage>>;
+
+pub struct ProgMap {
+    mappings: Mappings,
+    file: Option<String>,
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl ProgMap {
+    fn new(mappings: Mappings, file: Option<String>, name: String) -> ProgMap {
+        ProgMap {
+            mappings, file, name,
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        match args.len() {
+            0 => Ok(Box::new(Self::new(HashMap::new(), None, name)) as Box<dyn MidiIO>),
+            1 => {
+                let mappings = load_mappings(&args[0])?;
+                Ok(Box::new(Self::new(mappings, Some(args[0].clone()), name)) as Box<dyn MidiIO>)
+            }
+            _ => Err(MMMErr::ArgError)
+        }
+    }
+
+    fn load(&mut self, args: &[String]) {
+        // with no file given, reload the last one
+        match args.first().or(self.file.as_ref()) {
+            Some(file) => match load_mappings(file) {
+                Ok(mappings) => {
+                    self.mappings = mappings;
+                    self.file = Some(file.clone())
+                }
+                Err(e) => outln!("failed to load mappings: {}", e)
+            }
+            None => outln!("file name required")
+        }
+    }
+}
+impl MidiIO for ProgMap {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (progmap)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("progmap with {} programs mapped from {}", self.mappings.len(), self.file.as_deref().unwrap_or("nowhere"))
+        }
+        else {
+            match shortened_keyword_match(&command[0], PROGMAP_CMDS) {
+                Some(IDX_LOAD) => self.load(&command[1..]),
+                Some(IDX_CLEAR) => self.mappings.clear(),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in PROGMAP_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        let mut ret = vec![String::from("progmap")];
+        ret.extend(self.file.clone());
+        ret
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        for m in messages {
+            match m.as_program_change().and_then(|p| self.mappings.get(&p)) {
+                Some(seq) => self.buf.extend_from_slice(seq),
+                None => self.buf.push(m.clone())
+            }
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+fn load_mappings(file: &str) -> Result<Mappings> {
+    parse_mappings(&read_to_string(file)?)
+}
+/// each line is `PROGRAM CHANNELS [bank MSB [LSB]] [program N] [cc CONTROLLER VALUE]...`, where CHANNELS is a
+/// comma separated list. bank select goes first, then the program change, then the ccs.
+/// several lines for the same program are sent in order. empty lines and lines starting with # are ignored
+fn parse_mappings(s: &str) -> Result<Mappings> {
+    let mut ret: Mappings = HashMap::new();
+    for (idx, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        let seq = parse_line(line).ok_or(MMMErr::SyntaxError(idx + 1))?;
+        ret.entry(seq.0).or_default().extend(seq.1)
+    }
+    Ok(ret)
+}
+fn parse_line(line: &str) -> Option<(u8, Vec<MidiMessage>)> {
+    let parts = split(line).ok()?;
+    let program = parse_7bit(parts.first()?)?;
+    let mut channels = Vec::new();
+    for c in parts.get(1)?.split(',') {
+        let c: u8 = c.parse().ok()?;
+        if !(1..=16).contains(&c) {
+            return None
+        }
+        channels.push(c)
+    }
+
+    let mut bank = Vec::new();
+    let mut prog = None;
+    let mut ccs = Vec::new();
+    let mut rest = parts[2..].iter();
+    while let Some(word) = rest.next() {
+        match shortened_keyword_match(word, PROGMAP_LINE_KWDS)? {
+            IDX_BANK => {
+                bank.push((0, parse_7bit(rest.next()?)?));
+                // lsb is optional, so only take the next word if it's a number
+                if let Some(lsb) = rest.as_slice().first().and_then(|s| parse_7bit(s)) {
+                    rest.next();
+                    bank.push((32, lsb))
+                }
+            }
+            IDX_PROGRAM => prog = Some(parse_7bit(rest.next()?)?),
+            IDX_CC => ccs.push((parse_7bit(rest.next()?)?, parse_7bit(rest.next()?)?)),
+            _ => unreachable!()
+        }
+    }
+
+    let mut seq = Vec::new();
+    for c in channels {
+        seq.extend(bank.iter().map(|(cc, v)| MidiMessage::control_change(c, *cc, *v)));
+        seq.extend(prog.map(|p| MidiMessage::program_change(c, p)));
+        seq.extend(ccs.iter().map(|(cc, v)| MidiMessage::control_change(c, *cc, *v)));
+    }
+    Some((program, seq))
+}
+fn parse_7bit(s: &str) -> Option<u8> {
+    s.parse().ok().filter(|v| *v < 128)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapping_file() {
+        let m = parse_mappings("# comment\n\n5 1,2 bank 3 prog 12\n5 3 prog 40 cc 74 64\n6 1 bank 1 2 cc 7 100\n").unwrap();
+        let bytes = |p| m[&p].iter().map(|m: &MidiMessage| m.to_bytes()).collect::<Vec<_>>();
+        assert_eq!(bytes(5), vec![
+            vec![0xb0, 0, 3], vec![0xc0, 12],
+            vec![0xb1, 0, 3], vec![0xc1, 12],
+            vec![0xc2, 40], vec![0xb2, 74, 64],
+        ]);
+        assert_eq!(bytes(6), vec![vec![0xb0, 0, 1], vec![0xb0, 32, 2], vec![0xb0, 7, 100]]);
+        assert!(matches!(parse_mappings("1 1\n2 17 prog 3"), Err(MMMErr::SyntaxError(2))));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::utils::*;
+use crate::consts::record_cmds::*;
+use crate::smf::write_smf;
+use crate::ump::write_clip;
+use crate::MidiIO;
+use std::time::Duration;
+
+pub struct Record {
+    events: Vec<(Duration, MidiMessage)>,
+    recording: bool,
+    started: Duration, // timestamp of the start of the take, allowing for any time already recorded
+    length: Duration, // only kept up to date while stopped
+    sounding: Vec<(u8, u8)>, // (channel, note)
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl Record {
+    fn new(name: String) -> Record {
+        Record {
+            name,
+            events: Vec::new(),
+            recording: false,
+            started: Duration::ZERO,
+            length: Duration::ZERO,
+            sounding: Vec::new(),
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !args.is_empty() {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            Ok(Box::new(Self::new(name)) as Box<dyn MidiIO>)
+        }
+    }
+
+    fn start(&mut self) {
+        if !self.recording {
+            self.started = timestamp().saturating_sub(self.length);
+            self.recording = true
+        }
+    }
+    fn stop(&mut self) {
+        if self.recording {
+            self.length = timestamp() - self.started;
+            // don't leave notes hanging in the recording
+            for (c, n) in self.sounding.drain(..) {
+                self.events.push((self.length, MidiMessage::note_off(c, n)))
+            }
+            self.recording = false
+        }
+    }
+    /// the take as it would be if stopped at now, leaving the recording itself alone
+    fn snapshot(&self, now: Duration) -> Vec<(Duration, MidiMessage)> {
+        let mut ret = self.events.clone();
+        if self.recording {
+            let length = now.saturating_sub(self.started);
+            ret.extend(self.sounding.iter().map(|(c, n)| (length, MidiMessage::note_off(*c, *n))))
+        }
+        ret
+    }
+    fn clear(&mut self) {
+        self.events.clear();
+        self.sounding.clear();
+        self.length = Duration::ZERO;
+        self.started = timestamp()
+    }
+    fn save(&mut self, args: &[String]) {
+        if args.is_empty() {
+            outln!("file name required")
+        }
+        else {
+            // None for a midi 2.0 clip file
+            let format = match args.get(1).map(|s| s.as_str()) {
+                None => Some(0),
+                Some("ump") => None,
+                Some(s) => match s.parse() {
+                    Ok(f @ 0..=1) => Some(f),
+                    _ => {
+                        outln!("format must be 0, 1 or ump");
+                        return
+                    }
+                }
+            };
+            let events = self.snapshot(timestamp());
+            let bytes = match format {
+                Some(f) => write_smf(&events, f),
+                None => write_clip(&events)
+            };
+            if let Err(e) = std::fs::write(&args[0], bytes) {
+                outln!("failed to save: {}", e)
+            }
+        }
+    }
+}
+impl MidiIO for Record {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (record)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            let state = if self.recording { "recording" } else { "stopped" };
+            outln!("record {} with {} events", state, self.events.len())
+        }
+        else {
+            match shortened_keyword_match(&command[0], RECORD_CMDS) {
+                Some(IDX_START) => self.start(),
+                Some(IDX_STOP) => self.stop(),
+                Some(IDX_CLEAR) => self.clear(),
+                Some(IDX_SAVE) => self.save(&command[1..]),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in RECORD_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> { vec![String::from("record")] }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        if self.recording {
+            let t = timestamp() - self.started;
+            for m in messages {
+                if let (Some(c), Some(n)) = (m.channel(), m.note()) {
+                    if m.is_note_on() {
+                        push_if_not_present((c, n), &mut self.sounding)
+                    }
+                    else {
+                        self.sounding.retain(|k| *k != (c, n))
+                    }
+                }
+                self.events.push((t, m.clone()))
+            }
+        }
+        self.buf.extend_from_slice(messages)
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smf::read_smf;
+
+    #[test]
+    fn save_while_a_note_is_held() {
+        let path = std::env::temp_dir().join(format!("mmm-record-{}.mid", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut r = Record::new(String::from("r"));
+        r.cfg(&[String::from("start")]);
+        r.write(&[MidiMessage::note_on(1, 60, 100)]);
+        r.cfg(&[String::from("save"), String::from(path)]);
+
+        // the file has the note closed off
+        let saved: Vec<MidiMessage> = read_smf(&std::fs::read(path).unwrap()).unwrap().events.into_iter().map(|(_, m)| m).collect();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(saved, vec![MidiMessage::note_on(1, 60, 100), MidiMessage::note_off(1, 60)]);
+
+        // but the take carries on with it still held, so its real note off pairs up
+        assert!(r.recording);
+        assert_eq!(r.sounding, vec![(1, 60)]);
+        r.write(&[MidiMessage::note_off(1, 60)]);
+        r.cfg(&[String::from("stop")]);
+        let taken: Vec<&MidiMessage> = r.events.iter().map(|(_, m)| m).collect();
+        assert_eq!(taken, vec![&MidiMessage::note_on(1, 60, 100), &MidiMessage::note_off(1, 60)]);
+    }
+}
@@ -0,0 +1,346 @@
+use crate::utils::*;
+use crate::consts::rotate_cmds::*;
+use crate::consts::rotate_policies::*;
+use crate::consts::rotate_steal::*;
+use crate::MidiIO;
+
+const TIMBRE_CC: u8 = 74;
+
+struct Voice {
+    in_channel: u8,
+    note: u8,
+    channel: u8,
+}
+
+pub struct Rotate {
+    master: u8, // 0 for none
+    members: Vec<u8>,
+    policy: usize,
+    steal: usize,
+    voices: Vec<Voice>, // oldest first
+    released: Vec<u64>, // when each member channel was last freed up, indexed like members
+    counter: u64,
+    next: usize, // index into members, for round robin
+    // the latest per-channel expression for each incoming channel, so new voices start off in the right place
+    bend: [u16; 16],
+    pressure: [Option<u8>; 16],
+    timbre: [Option<u8>; 16],
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl Rotate {
+    fn new(master: u8, members: Vec<u8>, policy: usize, name: String) -> Rotate {
+        Rotate {
+            master, policy, name,
+            released: vec![0; members.len()],
+            members,
+            steal: IDX_OLDEST,
+            voices: Vec::new(),
+            counter: 0,
+            next: 0,
+            bend: [8192; 16],
+            pressure: [None; 16],
+            timbre: [None; 16],
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !(2..=3).contains(&args.len()) {
+            return Err(MMMErr::ArgError)
+        }
+        let master: u8 = args[0].parse()?;
+        let size: u8 = args[1].parse()?;
+        let members = zone_members(master, size).ok_or(MMMErr::ArgError)?;
+        let policy = match args.get(2) {
+            Some(s) => shortened_keyword_match(s, ROTATE_POLICIES).ok_or(MMMErr::ArgError)?,
+            None => IDX_ROUNDROBIN
+        };
+        Ok(Box::new(Self::new(master, members, policy, name)) as Box<dyn MidiIO>)
+    }
+
+    fn process(&mut self, m: &MidiMessage) {
+        let c = match m.channel() {
+            Some(c) => c,
+            None => {
+                self.buf.push(m.clone());
+                return
+            }
+        };
+        if m.is_note_on() {
+            self.note_on(c, m)
+        }
+        else if m.is_note_off() {
+            let n = m.note().unwrap();
+            if let Some(idx) = self.voices.iter().position(|v| v.in_channel == c && v.note == n) {
+                self.buf.push(m.with_channel(self.voices[idx].channel));
+                self.free(idx)
+            }
+            // otherwise it was stolen or dropped, and the note off has already gone
+        }
+        else {
+            match m {
+                MidiMessage::Channel([s, n, v]) if s & 0xf0 == 0xa0 => {
+                    // poly aftertouch becomes pressure on the note's own channel
+                    if let Some(voice) = self.voices.iter().find(|voice| voice.in_channel == c && voice.note == *n) {
+                        self.buf.push(MidiMessage::channel_pressure(voice.channel, *v))
+                    }
+                }
+                MidiMessage::Channel([s, l, h]) if s & 0xf0 == 0xe0 => {
+                    self.bend[c as usize - 1] = (*h as u16) << 7 | *l as u16;
+                    self.send_to_voices(c, m)
+                }
+                MidiMessage::ChannelSmall([s, v]) if s & 0xf0 == 0xd0 => {
+                    self.pressure[c as usize - 1] = Some(*v);
+                    self.send_to_voices(c, m)
+                }
+                MidiMessage::Channel([s, TIMBRE_CC, v]) if s & 0xf0 == 0xb0 => {
+                    self.timbre[c as usize - 1] = Some(*v);
+                    self.send_to_voices(c, m)
+                }
+                _ => {
+                    // anything else affects the whole zone
+                    if self.master == 0 {
+                        for ch in &self.members {
+                            self.buf.push(m.with_channel(*ch))
+                        }
+                    }
+                    else {
+                        self.buf.push(m.with_channel(self.master))
+                    }
+                }
+            }
+        }
+    }
+    fn note_on(&mut self, c: u8, m: &MidiMessage) {
+        let n = m.note().unwrap();
+        // a note that's played again without being released keeps to one voice
+        if let Some(idx) = self.voices.iter().position(|v| v.in_channel == c && v.note == n) {
+            self.buf.push(MidiMessage::note_off(self.voices[idx].channel, n));
+            self.free(idx)
+        }
+        let channel = match self.free_channel() {
+            Some(ch) => ch,
+            None => {
+                let idx = match self.steal {
+                    IDX_OLDEST => 0,
+                    IDX_NEWEST => self.voices.len() - 1,
+                    _ => return
+                };
+                let ch = self.voices[idx].channel;
+                self.buf.push(MidiMessage::note_off(ch, self.voices[idx].note));
+                self.free(idx);
+                ch
+            }
+        };
+
+        let i = c as usize - 1;
+        self.buf.push(MidiMessage::pitch_bend(channel, self.bend[i]));
+        if let Some(p) = self.pressure[i] {
+            self.buf.push(MidiMessage::channel_pressure(channel, p))
+        }
+        if let Some(t) = self.timbre[i] {
+            self.buf.push(MidiMessage::control_change(channel, TIMBRE_CC, t))
+        }
+        self.buf.push(m.with_channel(channel));
+        self.voices.push(Voice { in_channel: c, note: n, channel })
+    }
+    fn free_channel(&mut self) -> Option<u8> {
+        let free: Vec<usize> = (0..self.members.len())
+            .filter(|idx| !self.voices.iter().any(|v| v.channel == self.members[*idx]))
+            .collect();
+        let idx = match self.policy {
+            IDX_ROUNDROBIN => {
+                let len = self.members.len();
+                let idx = (0..len).map(|i| (self.next + i) % len).find(|idx| free.contains(idx))?;
+                self.next = idx + 1;
+                idx
+            }
+            IDX_LRU => *free.iter().min_by_key(|idx| self.released[**idx])?,
+            IDX_LOWEST => *free.iter().min_by_key(|idx| self.members[**idx])?,
+            _ => unreachable!()
+        };
+        Some(self.members[idx])
+    }
+    /// forgets a voice, without sending anything
+    fn free(&mut self, voice: usize) {
+        let v = self.voices.remove(voice);
+        if let Some(idx) = self.members.iter().position(|ch| *ch == v.channel) {
+            self.counter += 1;
+            self.released[idx] = self.counter
+        }
+    }
+    fn send_to_voices(&mut self, in_channel: u8, m: &MidiMessage) {
+        for v in self.voices.iter().filter(|v| v.in_channel == in_channel) {
+            self.buf.push(m.with_channel(v.channel))
+        }
+    }
+    fn release_all(&mut self) {
+        for v in self.voices.drain(..) {
+            self.buf.push(MidiMessage::note_off(v.channel, v.note))
+        }
+    }
+
+    fn set_zone(&mut self, master: Option<u8>, size: Option<u8>) {
+        let master = master.unwrap_or(self.master);
+        let size = size.unwrap_or(self.members.len() as u8);
+        match zone_members(master, size) {
+            Some(members) => {
+                self.release_all();
+                self.master = master;
+                self.released = vec![0; members.len()];
+                self.members = members;
+                self.next = 0
+            }
+            None => outln!("zone doesn't fit in 16 channels")
+        }
+    }
+    fn set_keyword(args: &[String], kwds: &[&str], val: &mut usize) {
+        match args.first().and_then(|s| shortened_keyword_match(s, kwds)) {
+            Some(idx) => *val = idx,
+            None => {
+                outln!("value required! valid values are:");
+                for k in kwds {
+                    outln!("\t{}", k)
+                }
+            }
+        }
+    }
+}
+impl MidiIO for Rotate {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (rotate)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            let members: Vec<String> = self.members.iter().map(|c| c.to_string()).collect();
+            let master = if self.master == 0 { String::from("no master channel") } else { format!("master channel {}", self.master) };
+            outln!("rotate over channels {} with {}, {}, stealing {}, {} voices sounding",
+                members.join(", "), master, ROTATE_POLICIES[self.policy], ROTATE_STEAL[self.steal], self.voices.len())
+        }
+        else {
+            match shortened_keyword_match(&command[0], ROTATE_CMDS) {
+                Some(IDX_MASTER) => match command.get(1).map(|s| s.parse()) {
+                    Some(Ok(m)) => self.set_zone(Some(m), None),
+                    _ => outln!("master channel required (0 for none)")
+                }
+                Some(IDX_SIZE) => match command.get(1).map(|s| s.parse()) {
+                    Some(Ok(s)) => self.set_zone(None, Some(s)),
+                    _ => outln!("zone size required")
+                }
+                Some(IDX_POLICY) => Self::set_keyword(&command[1..], ROTATE_POLICIES, &mut self.policy),
+                Some(IDX_STEAL) => Self::set_keyword(&command[1..], ROTATE_STEAL, &mut self.steal),
+                Some(IDX_RESET) => self.release_all(),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in ROTATE_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        vec![String::from("rotate"), self.master.to_string(), self.members.len().to_string(), String::from(ROTATE_POLICIES[self.policy])]
+    }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        vec![vec![String::from("steal"), String::from(ROTATE_STEAL[self.steal])]]
+    }
+    fn release(&mut self) -> Vec<MidiMessage> {
+        self.release_all();
+        self.read()
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        for m in messages {
+            self.process(m)
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+/// the channels notes get spread over. with master 16 they count down from 15, like an mpe upper zone
+fn zone_members(master: u8, size: u8) -> Option<Vec<u8>> {
+    match master {
+        _ if size == 0 => None,
+        0 if size <= 16 => Some((1..=size).collect()),
+        16 if size <= 15 => Some((16 - size..16).rev().collect()),
+        1..=15 if master + size <= 16 => Some((master + 1..=master + size).collect()),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// just the notes, as (channel, note, on)
+    fn notes(msgs: Vec<MidiMessage>) -> Vec<(u8, u8, bool)> {
+        msgs.iter().filter_map(|m| Some((m.channel()?, m.note()?, m.is_note_on()))).collect()
+    }
+
+    #[test]
+    fn zones() {
+        assert_eq!(zone_members(1, 3), Some(vec![2, 3, 4]));
+        assert_eq!(zone_members(16, 2), Some(vec![15, 14]));
+        assert_eq!(zone_members(0, 2), Some(vec![1, 2]));
+        assert_eq!(zone_members(10, 7), None);
+    }
+
+    #[test]
+    fn round_robin_steals_oldest() {
+        let mut r = Rotate::new(1, vec![2, 3, 4], IDX_ROUNDROBIN, String::from("r"));
+        for n in 60..64 {
+            r.write(&[MidiMessage::note_on(1, n, 100)])
+        }
+        assert_eq!(notes(r.read()), vec![(2, 60, true), (3, 61, true), (4, 62, true), (2, 60, false), (2, 63, true)]);
+        r.write(&[MidiMessage::note_off(1, 60), MidiMessage::note_off(1, 61)]);
+        assert_eq!(notes(r.read()), vec![(3, 61, false)]);
+    }
+
+    #[test]
+    fn lru_and_lowest() {
+        let mut r = Rotate::new(1, vec![2, 3, 4], IDX_LRU, String::from("r"));
+        r.write(&[MidiMessage::note_on(1, 60, 100), MidiMessage::note_on(1, 61, 100)]);
+        r.write(&[MidiMessage::note_off(1, 60), MidiMessage::note_off(1, 61)]);
+        r.write(&[MidiMessage::note_on(1, 62, 100), MidiMessage::note_on(1, 63, 100)]);
+        assert_eq!(notes(r.read())[4..], [(4, 62, true), (2, 63, true)]);
+
+        let mut r = Rotate::new(1, vec![2, 3, 4], IDX_LOWEST, String::from("r"));
+        r.write(&[MidiMessage::note_on(1, 60, 100), MidiMessage::note_off(1, 60), MidiMessage::note_on(1, 61, 100)]);
+        assert_eq!(notes(r.read()), vec![(2, 60, true), (2, 60, false), (2, 61, true)]);
+    }
+
+    #[test]
+    fn expression_follows_notes() {
+        let mut r = Rotate::new(1, vec![2, 3], IDX_ROUNDROBIN, String::from("r"));
+        r.write(&[MidiMessage::note_on(1, 60, 100), MidiMessage::pitch_bend(1, 0), MidiMessage::note_on(1, 64, 100)]);
+        r.write(&[MidiMessage::Channel([0xa0, 64, 50]), MidiMessage::control_change(1, 7, 100)]);
+        let bytes: Vec<Vec<u8>> = r.read().iter().map(|m| m.to_bytes()).collect();
+        assert_eq!(bytes, vec![
+            vec![0xe1, 0, 0x40], vec![0x91, 60, 100],
+            vec![0xe1, 0, 0],
+            vec![0xe2, 0, 0], vec![0x92, 64, 100],
+            vec![0xd2, 50],
+            vec![0xb0, 7, 100]
+        ]);
+    }
+}
@@ -0,0 +1,526 @@
+use crate::utils::*;
+use crate::consts::rtpmidi_cmds::*;
+use crate::rtp::*;
+use crate::shell::print_line;
+use crate::MidiIO;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+const INVITE_INTERVAL: Duration = Duration::from_secs(1);
+const INVITE_TRIES: u32 = 12;
+const CLOCK_INTERVAL: Duration = Duration::from_secs(10);
+const FEEDBACK_INTERVAL: Duration = Duration::from_secs(1);
+/// most of a command list can be 4095 bytes, so leave room for that and the journal
+const MAX_LIST_LEN: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Control, // waiting for the control port invitation to be accepted
+    Data, // same for the data port
+    Connected
+}
+
+struct Session {
+    ssrc: Option<u32>, // theirs, once we know it
+    name: String,
+    control: SocketAddr,
+    data: SocketAddr,
+    token: u32,
+    state: State,
+    initiator: bool,
+    tries: u32,
+    last_sent: Duration, // last invitation or clock sync
+    next_seq: Option<u16>,
+    feedback: Option<u16>, // sequence number to tell them about
+    last_feedback: Duration,
+    acked: Option<u16>, // the last of our packets they've said they received
+    received: MidiState,
+    lost: u64,
+    latency: Option<Duration>,
+}
+impl Session {
+    /// None if the control port is the last one, so there's no data port after it
+    fn new(control: SocketAddr, token: u32, initiator: bool) -> Option<Session> {
+        let mut data = control;
+        data.set_port(control.port().checked_add(1)?);
+        Some(Session {
+            control, data, token, initiator,
+            ssrc: None,
+            name: control.to_string(),
+            state: if initiator { State::Control } else { State::Data },
+            tries: 0,
+            last_sent: timestamp(),
+            next_seq: None,
+            feedback: None,
+            last_feedback: Duration::ZERO,
+            acked: None,
+            received: MidiState::new(),
+            lost: 0,
+            latency: None
+        })
+    }
+}
+
+pub struct RtpMidi {
+    control: UdpSocket,
+    data: UdpSocket,
+    port: u16,
+    ssrc: u32,
+    rng: Rng,
+    sessions: Vec<Session>,
+    invites: Vec<String>, // addresses we've been asked to invite, for saving
+    seq: u16,
+    sent: MidiState,
+    checkpoint: u16, // the first packet the journal goes back to
+    name: String,
+    outputs: Vec<Id>,
+}
+impl RtpMidi {
+    fn new(port: u16, name: String) -> Result<RtpMidi> {
+        let (control, data) = bind_pair(port)?;
+        control.set_nonblocking(true)?;
+        data.set_nonblocking(true)?;
+        let port = control.local_addr()?.port();
+        let mut rng = Rng::from_time();
+        Ok(RtpMidi {
+            control, data, port, name,
+            ssrc: rng.next_u64() as u32,
+            rng,
+            sessions: Vec::new(),
+            invites: Vec::new(),
+            seq: 0,
+            sent: MidiState::new(),
+            checkpoint: 0,
+            outputs: Vec::new()
+        })
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !(1..=2).contains(&args.len()) {
+            return Err(MMMErr::ArgError)
+        }
+        let mut r = Self::new(args[0].parse()?, name)?;
+        if let Some(addr) = args.get(1) {
+            r.invite(addr)?
+        }
+        Ok(Box::new(r) as Box<dyn MidiIO>)
+    }
+
+    /// the address is of the control port
+    fn invite(&mut self, addr: &str) -> Result<()> {
+        let control = addr.to_socket_addrs()?.next().ok_or(MMMErr::ArgError)?;
+        let s = Session::new(control, self.rng.next_u64() as u32, true).ok_or(MMMErr::ArgError)?;
+        send(&self.control, s.control, &Command::Invite { token: s.token, ssrc: self.ssrc, name: self.name.clone() });
+        self.sessions.push(s);
+        push_if_not_present(String::from(addr), &mut self.invites);
+        Ok(())
+    }
+    fn bye(&mut self, name: Option<&String>) {
+        let (gone, kept) = self.sessions.drain(..).partition(|s| name.map(|n| *n == s.name).unwrap_or(true));
+        self.sessions = kept;
+        for s in gone {
+            send(&self.control, s.control, &Command::Bye { token: s.token, ssrc: self.ssrc })
+        }
+    }
+
+    fn handle_command(&mut self, now: Duration, from: SocketAddr, cmd: Command, on_data: bool) {
+        let socket = if on_data { &self.data } else { &self.control };
+        match cmd {
+            Command::Invite { token, ssrc, name } => {
+                if !on_data && from.port() == u16::MAX {
+                    // no room for a data port after it
+                    send(socket, from, &Command::Reject { token, ssrc: self.ssrc });
+                    return
+                }
+                send(socket, from, &Command::Accept { token, ssrc: self.ssrc, name: self.name.clone() });
+                if on_data {
+                    match self.sessions.iter_mut().find(|s| s.ssrc == Some(ssrc)) {
+                        Some(s) => {
+                            s.data = from;
+                            s.state = State::Connected;
+                            print_line(format!("{}: {} connected", self.name, s.name))
+                        }
+                        None => {
+                            // never saw the control invitation, but there's no harm in going along with it
+                            let mut control = from;
+                            control.set_port(from.port().saturating_sub(1));
+                            if let Some(mut s) = Session::new(control, token, false) {
+                                s.data = from;
+                                s.ssrc = Some(ssrc);
+                                s.name = name;
+                                s.state = State::Connected;
+                                self.sessions.push(s)
+                            }
+                        }
+                    }
+                }
+                else {
+                    self.sessions.retain(|s| s.ssrc != Some(ssrc));
+                    if let Some(mut s) = Session::new(from, token, false) {
+                        s.ssrc = Some(ssrc);
+                        s.name = name;
+                        self.sessions.push(s)
+                    }
+                }
+            }
+            Command::Accept { token, ssrc, name } => {
+                if let Some(s) = self.sessions.iter_mut().find(|s| s.initiator && s.token == token) {
+                    match (s.state, on_data) {
+                        (State::Control, false) => {
+                            s.ssrc = Some(ssrc);
+                            s.name = name;
+                            s.state = State::Data;
+                            s.tries = 0;
+                            s.last_sent = now;
+                            send(&self.data, s.data, &Command::Invite { token, ssrc: self.ssrc, name: self.name.clone() })
+                        }
+                        (State::Data, true) => {
+                            s.state = State::Connected;
+                            s.last_sent = now;
+                            send(&self.data, s.data, &Command::Clock { ssrc: self.ssrc, count: 0, ts: [clock_time(now), 0, 0] });
+                            print_line(format!("{}: connected to {}", self.name, s.name))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Command::Reject { token, .. } => {
+                if let Some(idx) = self.sessions.iter().position(|s| s.initiator && s.token == token) {
+                    let s = self.sessions.remove(idx);
+                    print_line(format!("{}: {} turned down the invitation", self.name, s.name))
+                }
+            }
+            Command::Bye { ssrc, .. } => {
+                if let Some(idx) = self.sessions.iter().position(|s| s.ssrc == Some(ssrc)) {
+                    let s = self.sessions.remove(idx);
+                    print_line(format!("{}: {} ended the session", self.name, s.name))
+                }
+            }
+            Command::Clock { ssrc, count, ts } => {
+                let t = clock_time(now);
+                let latency = match count {
+                    0 => {
+                        send(socket, from, &Command::Clock { ssrc: self.ssrc, count: 1, ts: [ts[0], t, 0] });
+                        None
+                    }
+                    1 => {
+                        send(socket, from, &Command::Clock { ssrc: self.ssrc, count: 2, ts: [ts[0], ts[1], t] });
+                        Some(t.saturating_sub(ts[0]))
+                    }
+                    _ => Some(ts[2].saturating_sub(ts[0]))
+                };
+                if let (Some(l), Some(s)) = (latency, self.sessions.iter_mut().find(|s| s.ssrc == Some(ssrc))) {
+                    // half the round trip
+                    s.latency = Some(Duration::from_micros(l * 50))
+                }
+            }
+            Command::Feedback { ssrc, seq } => {
+                if let Some(s) = self.sessions.iter_mut().find(|s| s.ssrc == Some(ssrc)) {
+                    // feedback can arrive out of order, so only ever move forwards
+                    match s.acked {
+                        Some(a) if seq.wrapping_sub(a) >= 0x8000 => {}
+                        _ => s.acked = Some(seq)
+                    }
+                }
+                self.trim_journal()
+            }
+        }
+    }
+    /// drops what every connected session has said it received from the journal
+    fn trim_journal(&mut self) {
+        // how far behind it is tells which is oldest, whatever's wrapped around
+        let behind = |seq: u16| self.seq.wrapping_sub(seq);
+        let mut oldest = None;
+        for s in self.sessions.iter().filter(|s| s.state == State::Connected) {
+            match (s.acked, oldest) {
+                (None, _) => return,
+                (Some(a), Some(o)) if behind(a) <= behind(o) => {}
+                (Some(a), _) => oldest = Some(a)
+            }
+        }
+        if let Some(seq) = oldest {
+            self.sent.checkpoint(seq);
+            self.checkpoint = seq.wrapping_add(1)
+        }
+    }
+    fn receive(&mut self, p: Packet) -> Vec<MidiMessage> {
+        let s = match self.sessions.iter_mut().find(|s| s.state == State::Connected && s.ssrc == Some(p.ssrc)) {
+            Some(s) => s,
+            None => return Vec::new()
+        };
+        let mut ret = Vec::new();
+        if let Some(expected) = s.next_seq {
+            let gap = p.seq.wrapping_sub(expected);
+            if gap >= 0x8000 {
+                // late or repeated, and anything in it has been recovered already
+                return ret
+            }
+            if gap != 0 {
+                s.lost += gap as u64;
+                ret.extend(s.received.recover(&p.journal))
+            }
+        }
+        s.next_seq = Some(p.seq.wrapping_add(1));
+        s.feedback = Some(p.seq);
+        for m in &p.messages {
+            s.received.track(m)
+        }
+        ret.extend(p.messages);
+        ret
+    }
+    fn timers(&mut self, now: Duration) {
+        let mut gave_up = Vec::new();
+        for (idx, s) in self.sessions.iter_mut().enumerate() {
+            match s.state {
+                State::Control | State::Data if s.initiator && now - s.last_sent >= INVITE_INTERVAL => {
+                    if s.tries >= INVITE_TRIES {
+                        gave_up.push(idx);
+                        continue
+                    }
+                    let cmd = Command::Invite { token: s.token, ssrc: self.ssrc, name: self.name.clone() };
+                    if s.state == State::Control {
+                        send(&self.control, s.control, &cmd)
+                    }
+                    else {
+                        send(&self.data, s.data, &cmd)
+                    }
+                    s.tries += 1;
+                    s.last_sent = now
+                }
+                State::Connected => {
+                    if s.initiator && now - s.last_sent >= CLOCK_INTERVAL {
+                        send(&self.data, s.data, &Command::Clock { ssrc: self.ssrc, count: 0, ts: [clock_time(now), 0, 0] });
+                        s.last_sent = now
+                    }
+                    if let Some(seq) = s.feedback {
+                        if now - s.last_feedback >= FEEDBACK_INTERVAL {
+                            send(&self.control, s.control, &Command::Feedback { ssrc: self.ssrc, seq });
+                            s.feedback = None;
+                            s.last_feedback = now
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        for idx in gave_up.into_iter().rev() {
+            let s = self.sessions.remove(idx);
+            print_line(format!("{}: no answer from {}", self.name, s.name))
+        }
+    }
+    fn send_packet(&mut self, messages: &[MidiMessage]) {
+        // the journal goes from before these messages
+        let journal = self.sent.journal(self.checkpoint);
+        let packet = encode_packet(self.seq, clock_time(timestamp()) as u32, self.ssrc, messages, journal.as_deref());
+        for s in self.sessions.iter().filter(|s| s.state == State::Connected) {
+            let _ = self.data.send_to(&packet, s.data);
+        }
+        for m in messages {
+            self.sent.track_sent(self.seq, m)
+        }
+        self.seq = self.seq.wrapping_add(1)
+    }
+}
+impl MidiIO for RtpMidi {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String {
+        let connected = self.sessions.iter().filter(|s| s.state == State::Connected).count();
+        let lost: u64 = self.sessions.iter().map(|s| s.lost).sum();
+        format!("udp {} <-> {} (rtpmidi, {} connected, {} lost)", self.port, self.name, connected, lost)
+    }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("rtpmidi on udp ports {} and {}, with {} sessions", self.port, self.port + 1, self.sessions.len());
+            for s in &self.sessions {
+                let state = match s.state {
+                    State::Connected => String::from("connected"),
+                    _ => String::from("inviting")
+                };
+                let latency = s.latency.map(|l| format!(", latency {:.2}ms", l.as_secs_f64() * 1000.0)).unwrap_or_default();
+                outln!("\t{} at {}: {}{}, {} lost", s.name, s.control, state, latency, s.lost)
+            }
+        }
+        else {
+            match shortened_keyword_match(&command[0], RTPMIDI_CMDS) {
+                Some(IDX_INVITE) => match command.get(1) {
+                    Some(addr) => if let Err(e) = self.invite(addr) {
+                        outln!("failed to invite: {}", e)
+                    }
+                    None => outln!("address required")
+                }
+                Some(IDX_BYE) => {
+                    if let Some(name) = command.get(1) {
+                        self.invites.retain(|a| a != name)
+                    }
+                    else {
+                        self.invites.clear()
+                    }
+                    self.bye(command.get(1))
+                }
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in RTPMIDI_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> { vec![String::from("rtpmidi"), self.port.to_string()] }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        self.invites.iter().map(|a| vec![String::from("invite"), a.clone()]).collect()
+    }
+    fn release(&mut self) -> Vec<MidiMessage> {
+        self.bye(None);
+        Vec::new()
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        let mut start = 0;
+        let mut len = 0;
+        for (i, m) in messages.iter().enumerate() {
+            let m_len = m.to_bytes().len() + 1;
+            if len + m_len > MAX_LIST_LEN && i > start {
+                self.send_packet(&messages[start..i]);
+                start = i;
+                len = 0
+            }
+            len += m_len
+        }
+        if start < messages.len() {
+            self.send_packet(&messages[start..])
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let now = timestamp();
+        let mut ret = Vec::new();
+        let mut buf = [0; 65536];
+        while let Ok((len, from)) = self.control.recv_from(&mut buf) {
+            if let Some(cmd) = Command::parse(&buf[..len]) {
+                self.handle_command(now, from, cmd, false)
+            }
+        }
+        while let Ok((len, from)) = self.data.recv_from(&mut buf) {
+            if let Some(cmd) = Command::parse(&buf[..len]) {
+                self.handle_command(now, from, cmd, true)
+            }
+            else if let Some(p) = parse_packet(&buf[..len]) {
+                ret.extend(self.receive(p))
+            }
+        }
+        self.timers(now);
+        ret
+    }
+
+    fn delete(mut self: Box<Self>) {
+        self.bye(None)
+    }
+}
+
+fn send(socket: &UdpSocket, to: SocketAddr, cmd: &Command) {
+    let _ = socket.send_to(&cmd.to_bytes(), to);
+}
+/// in units of 100 microseconds, like rtp-midi timestamps
+fn clock_time(now: Duration) -> u64 {
+    (now.as_micros() / 100) as u64
+}
+/// the data port is always the one after the control port, so with port 0 we have to find a pair that's free
+fn bind_pair(port: u16) -> Result<(UdpSocket, UdpSocket)> {
+    if port != 0 {
+        let data_port = port.checked_add(1).ok_or(MMMErr::ArgError)?;
+        return Ok((UdpSocket::bind(("0.0.0.0", port))?, UdpSocket::bind(("0.0.0.0", data_port))?))
+    }
+    let mut tries = 0;
+    loop {
+        let control = UdpSocket::bind(("0.0.0.0", 0))?;
+        let p = control.local_addr()?.port();
+        match p.checked_add(1).map(|p| UdpSocket::bind(("0.0.0.0", p))) {
+            Some(Ok(data)) => return Ok((control, data)),
+            Some(Err(e)) if tries >= 20 => return Err(e.into()),
+            _ => tries += 1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll(a: &mut RtpMidi, b: &mut RtpMidi, until: impl Fn(&RtpMidi, &RtpMidi, &[MidiMessage]) -> bool) -> Vec<MidiMessage> {
+        let mut received = Vec::new();
+        for _ in 0..200 {
+            received.extend(a.read());
+            b.read();
+            if until(a, b, &received) {
+                break
+            }
+            sleep_ms(5)
+        }
+        received
+    }
+
+    #[test]
+    fn localhost_session() {
+        let mut a = RtpMidi::new(0, String::from("a")).unwrap();
+        let mut b = RtpMidi::new(0, String::from("b")).unwrap();
+        b.invite(&format!("127.0.0.1:{}", a.port)).unwrap();
+        let connected = |r: &RtpMidi| r.sessions.iter().any(|s| s.state == State::Connected);
+        poll(&mut a, &mut b, |a, b, _| connected(a) && connected(b));
+        assert!(connected(&a) && connected(&b));
+
+        b.write(&[MidiMessage::note_on(1, 60, 100)]);
+        let received = poll(&mut a, &mut b, |_, _, r| !r.is_empty());
+        assert_eq!(received, vec![MidiMessage::note_on(1, 60, 100)]);
+
+        // a packet that never arrives
+        b.sent.track_sent(b.seq, &MidiMessage::note_on(1, 62, 100));
+        b.seq += 1;
+        b.write(&[MidiMessage::note_on(1, 64, 100)]);
+        let received = poll(&mut a, &mut b, |_, _, r| r.len() >= 2);
+        assert_eq!(received, vec![MidiMessage::note_on(1, 62, 100), MidiMessage::note_on(1, 64, 100)]);
+        assert_eq!(a.sessions[0].lost, 1);
+
+        b.release();
+        poll(&mut a, &mut b, |a, _, _| a.sessions.is_empty());
+        assert!(a.sessions.is_empty())
+    }
+
+    #[test]
+    fn journal_kept_until_everyone_has_caught_up() {
+        let mut r = RtpMidi::new(0, String::from("r")).unwrap();
+        for ssrc in [1, 2] {
+            let mut s = Session::new("127.0.0.1:9".parse().unwrap(), ssrc, false).unwrap();
+            s.ssrc = Some(ssrc);
+            s.state = State::Connected;
+            r.sessions.push(s)
+        }
+        r.write(&[MidiMessage::note_on(1, 60, 100)]);
+        r.write(&[MidiMessage::note_off(1, 60)]);
+        let feedback = |r: &mut RtpMidi, ssrc, seq| r.handle_command(Duration::ZERO, "127.0.0.1:9".parse().unwrap(), Command::Feedback { ssrc, seq }, false);
+        feedback(&mut r, 1, 1);
+        feedback(&mut r, 2, 0);
+        // 2 hasn't got the note off yet
+        assert!(r.sent.journal(r.checkpoint).is_some());
+        assert_eq!(r.checkpoint, 1);
+        feedback(&mut r, 2, 1);
+        feedback(&mut r, 1, 0);
+        assert!(r.sent.journal(r.checkpoint).is_none());
+        assert_eq!(r.checkpoint, 2);
+
+        // no room for a data port
+        assert!(bind_pair(u16::MAX).is_err());
+        assert!(Session::new("127.0.0.1:65535".parse().unwrap(), 0, true).is_none());
+    }
+}
@@ -0,0 +1,410 @@
+use crate::utils::*;
+use crate::consts::seq_cmds::*;
+use crate::consts::seq_step_kwds::*;
+use crate::MidiIO;
+use std::time::Duration;
+
+/// positions are measured in midi clocks, so 24 to a beat
+const CLOCKS_PER_BEAT: f64 = 24.0;
+
+#[derive(Clone, PartialEq)]
+struct Step {
+    note: u8,
+    velocity: u8,
+    gate: u8, // percent of the step length
+    probability: u8, // percent
+    active: bool
+}
+impl Default for Step {
+    fn default() -> Step {
+        Step {
+            note: 60,
+            velocity: 100,
+            gate: 50,
+            probability: 100,
+            active: true
+        }
+    }
+}
+
+pub struct Seq {
+    patterns: Vec<Vec<Step>>,
+    pattern: usize,
+    step: usize, // the next step to play
+    channel: u8,
+    rate: u8, // steps per beat
+    tempo: f64, // in bpm, only used when not synced
+    sync: bool,
+    running: bool,
+    abs: f64, // clocks since the start
+    next_step_at: f64,
+    clocks: u64, // clock messages since external start
+    last_clock: Duration,
+    clock_interval: Option<Duration>,
+    last: Duration,
+    pending_offs: Vec<(f64, u8, u8)>, // (due, channel, note)
+    rng: Rng,
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+impl Seq {
+    fn new(channel: u8, steps: usize, name: String) -> Seq {
+        Seq {
+            channel, name,
+            patterns: vec![vec![Step::default(); steps]],
+            pattern: 0,
+            step: 0,
+            rate: 4,
+            tempo: 120.0,
+            sync: false,
+            running: false,
+            abs: 0.0,
+            next_step_at: 0.0,
+            clocks: 0,
+            last_clock: Duration::ZERO,
+            clock_interval: None,
+            last: timestamp(),
+            pending_offs: Vec::new(),
+            rng: Rng::from_time(),
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        if !(1..=2).contains(&args.len()) {
+            Err(MMMErr::ArgError)
+        }
+        else {
+            let c: u8 = args[0].parse()?;
+            let steps = match args.get(1) {
+                Some(s) => s.parse()?,
+                None => 16
+            };
+            if (1..=16).contains(&c) && steps > 0 {
+                Ok(Box::new(Self::new(c, steps, name)) as Box<dyn MidiIO>)
+            }
+            else {
+                Err(MMMErr::ArgError)
+            }
+        }
+    }
+
+    fn clocks_per_step(&self) -> f64 {
+        CLOCKS_PER_BEAT / self.rate as f64
+    }
+    fn update(&mut self, now: Duration) {
+        let target = if !self.running {
+            self.abs
+        }
+        else if self.sync {
+            // between clocks, guess how far along we are, but never past the next one
+            let frac = match self.clock_interval {
+                Some(i) => ((now - self.last_clock).as_secs_f64() / i.as_secs_f64()).min(1.0),
+                None => 0.0
+            };
+            (self.clocks as f64 + frac).max(self.abs)
+        }
+        else {
+            self.abs + (now - self.last).as_secs_f64() * self.tempo / 60.0 * CLOCKS_PER_BEAT
+        };
+        self.last = now;
+
+        // note offs and steps have to go out in order, so a retriggered note doesn't get cut off
+        loop {
+            let next_off = self.pending_offs.iter().enumerate().min_by(|a, b| a.1.0.partial_cmp(&b.1.0).unwrap());
+            match next_off {
+                Some((idx, (due, _, _))) if *due <= target && (*due <= self.next_step_at || !self.running) => {
+                    let (_, c, n) = self.pending_offs.remove(idx);
+                    self.buf.push(MidiMessage::note_off(c, n))
+                }
+                _ if self.running && self.next_step_at <= target => self.trigger(),
+                _ => break
+            }
+        }
+        self.abs = target
+    }
+    fn trigger(&mut self) {
+        let at = self.next_step_at;
+        let length = self.clocks_per_step();
+        self.next_step_at += length;
+
+        let pattern = &self.patterns[self.pattern];
+        let step = pattern[self.step % pattern.len()].clone();
+        self.step = (self.step + 1) % pattern.len();
+        if step.active && self.rng.up_to(99) < step.probability as u64 {
+            self.buf.push(MidiMessage::note_on(self.channel, step.note, step.velocity));
+            self.pending_offs.push((at + length * step.gate as f64 / 100.0, self.channel, step.note))
+        }
+    }
+    fn start(&mut self) {
+        self.step = 0;
+        self.next_step_at = self.abs;
+        self.running = true
+    }
+    fn stop(&mut self) {
+        self.running = false;
+        for (_, c, n) in self.pending_offs.drain(..) {
+            self.buf.push(MidiMessage::note_off(c, n))
+        }
+    }
+    fn clock(&mut self, now: Duration, m: &MidiMessage) {
+        match m {
+            MidiMessage::SystemRealtime(0xf8) if self.running => {
+                self.clocks += 1;
+                self.clock_interval = Some(now - self.last_clock);
+                self.last_clock = now
+            }
+            MidiMessage::SystemRealtime(0xfa) => {
+                self.stop();
+                self.clocks = 0;
+                self.abs = 0.0;
+                self.last_clock = now;
+                self.start()
+            }
+            MidiMessage::SystemRealtime(0xfb) => {
+                self.last_clock = now;
+                self.running = true
+            }
+            MidiMessage::SystemRealtime(0xfc) => self.stop(),
+            _ => {}
+        }
+    }
+
+    fn edit_step(&mut self, args: &[String]) {
+        let pattern = &mut self.patterns[self.pattern];
+        let idx = match args.first().map(|s| s.parse::<usize>()) {
+            Some(Ok(i)) if (1..=pattern.len()).contains(&i) => i - 1,
+            _ => {
+                outln!("step number between 1 and {} required", pattern.len());
+                return
+            }
+        };
+        let step = &mut pattern[idx];
+        let mut rest = args[1..].iter();
+        while let Some(word) = rest.next() {
+            let kwd = shortened_keyword_match(word, SEQ_STEP_KWDS);
+            let ok = match kwd {
+                Some(IDX_NOTE) => rest.next().and_then(|s| parse_note(s)).map(|n| step.note = n).is_some(),
+                Some(IDX_VELOCITY) => rest.next().and_then(|s| s.parse().ok()).filter(|v| (1..128).contains(v)).map(|v| step.velocity = v).is_some(),
+                Some(IDX_GATE) => rest.next().and_then(|s| s.parse().ok()).filter(|g| (1..=100).contains(g)).map(|g| step.gate = g).is_some(),
+                Some(IDX_PROBABILITY) => rest.next().and_then(|s| s.parse().ok()).filter(|p| *p <= 100).map(|p| step.probability = p).is_some(),
+                Some(IDX_ON) => { step.active = true; true }
+                Some(IDX_OFF) => { step.active = false; true }
+                _ => {
+                    outln!("no match for {}! valid step settings are:", word);
+                    for k in SEQ_STEP_KWDS {
+                        outln!("\t{}", k)
+                    }
+                    return
+                }
+            };
+            if !ok {
+                outln!("bad or missing value for {}", SEQ_STEP_KWDS[kwd.unwrap()]);
+                return
+            }
+        }
+        if args.len() == 1 {
+            outln!("{}", describe_step(idx, step))
+        }
+    }
+    fn set_steps(&mut self, args: &[String]) {
+        match args.first().map(|s| s.parse::<usize>()) {
+            Some(Ok(n)) if n > 0 => {
+                self.patterns[self.pattern].resize(n, Step::default());
+                self.step %= n
+            }
+            _ => outln!("number of steps required")
+        }
+    }
+    fn set_pattern(&mut self, args: &[String]) {
+        match args.first().map(|s| s.parse::<usize>()) {
+            Some(Ok(n)) if n > 0 => {
+                let len = self.patterns[self.pattern].len();
+                while self.patterns.len() < n {
+                    self.patterns.push(vec![Step::default(); len])
+                }
+                // switches straight away, carrying on from the same step
+                self.pattern = n - 1;
+                self.step %= self.patterns[self.pattern].len()
+            }
+            _ => outln!("pattern number required")
+        }
+    }
+    fn set_value<T: std::str::FromStr>(args: &[String], valid: impl Fn(&T) -> bool, val: &mut T) {
+        match args.first().map(|s| s.parse::<T>()) {
+            Some(Ok(v)) if valid(&v) => *val = v,
+            _ => outln!("bad or missing value")
+        }
+    }
+}
+impl MidiIO for Seq {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (seq)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            let timing = if self.sync { String::from("synced to clock") } else { format!("at {}bpm", self.tempo) };
+            let state = if self.running { "playing" } else { "stopped" };
+            outln!("seq on channel {}, pattern {} of {}, {} steps per beat {}, {}",
+                self.channel, self.pattern + 1, self.patterns.len(), self.rate, timing, state);
+            for (idx, step) in self.patterns[self.pattern].iter().enumerate() {
+                outln!("\t{}", describe_step(idx, step))
+            }
+        }
+        else {
+            self.update(timestamp());
+            match shortened_keyword_match(&command[0], SEQ_CMDS) {
+                Some(IDX_PLAY) => self.start(),
+                Some(IDX_STOP) => self.stop(),
+                Some(IDX_STEP) => self.edit_step(&command[1..]),
+                Some(IDX_STEPS) => self.set_steps(&command[1..]),
+                Some(IDX_PATTERN) => self.set_pattern(&command[1..]),
+                Some(IDX_TEMPO) => Self::set_value(&command[1..], |t| *t > 0.0, &mut self.tempo),
+                Some(IDX_RATE) => Self::set_value(&command[1..], |r| [1, 2, 3, 4, 6, 8, 12, 24].contains(r), &mut self.rate),
+                Some(IDX_CHANNEL) => Self::set_value(&command[1..], |c| (1..=16).contains(c), &mut self.channel),
+                Some(IDX_SYNC) => {
+                    self.stop();
+                    self.sync = !self.sync
+                }
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in SEQ_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> { vec![String::from("seq"), self.channel.to_string()] }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        let mut ret = vec![
+            vec![String::from("tempo"), self.tempo.to_string()],
+            vec![String::from("rate"), self.rate.to_string()],
+        ];
+        if self.sync {
+            ret.push(vec![String::from("sync")])
+        }
+        for (p, pattern) in self.patterns.iter().enumerate() {
+            ret.push(vec![String::from("pattern"), (p + 1).to_string()]);
+            ret.push(vec![String::from("steps"), pattern.len().to_string()]);
+            for (idx, step) in pattern.iter().enumerate().filter(|(_, s)| **s != Step::default()) {
+                ret.push(vec![
+                    String::from("step"), (idx + 1).to_string(),
+                    String::from("note"), step.note.to_string(),
+                    String::from("velocity"), step.velocity.to_string(),
+                    String::from("gate"), step.gate.to_string(),
+                    String::from("probability"), step.probability.to_string(),
+                    String::from(if step.active { "on" } else { "off" }),
+                ])
+            }
+        }
+        ret.push(vec![String::from("pattern"), (self.pattern + 1).to_string()]);
+        ret
+    }
+    fn release(&mut self) -> Vec<MidiMessage> {
+        self.stop();
+        std::mem::take(&mut self.buf)
+    }
+
+    /// only clock, start, stop and continue are listened to, and only when synced
+    fn write(&mut self, messages: &[MidiMessage]) {
+        if self.sync {
+            let now = timestamp();
+            for m in messages {
+                self.update(now);
+                self.clock(now, m)
+            }
+            self.update(now)
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        self.update(timestamp());
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+fn describe_step(idx: usize, step: &Step) -> String {
+    if step.active {
+        format!("{}: {} vel {} gate {}% prob {}%", idx + 1, note_name(step.note), step.velocity, step.gate, step.probability)
+    }
+    else {
+        format!("{}: off", idx + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+    fn cfg(s: &mut Seq, line: &str) {
+        let words: Vec<String> = line.split(' ').map(String::from).collect();
+        s.cfg(&words)
+    }
+
+    #[test]
+    fn plays_steps_in_time() {
+        // 16th notes at 120bpm are 125ms apart
+        let mut s = Seq::new(2, 4, String::from("s"));
+        cfg(&mut s, "step 2 note D4 vel 90 gate 100");
+        cfg(&mut s, "step 3 off");
+        s.last = Duration::ZERO;
+        s.abs = 0.0;
+        s.start();
+        let mut out = Vec::new();
+        for t in (0..=505).step_by(5) {
+            s.update(ms(t));
+            out.extend(s.buf.drain(..).map(|m| (t, m.to_bytes())))
+        }
+        let expected = vec![
+            (0, vec![0x91, 60, 100]),
+            (65, vec![0x81, 60, 0]),
+            (125, vec![0x91, 62, 90]),
+            (250, vec![0x81, 62, 0]),
+            (375, vec![0x91, 60, 100]),
+            (440, vec![0x81, 60, 0]),
+            (500, vec![0x91, 60, 100]),
+        ];
+        assert_eq!(out.len(), expected.len());
+        for ((t, m), (t_expected, m_expected)) in out.iter().zip(expected) {
+            // floating point error can push things back one update
+            assert!(*t == t_expected || *t == t_expected + 5);
+            assert_eq!(*m, m_expected);
+        }
+    }
+
+    #[test]
+    fn patterns_switch_and_save() {
+        let mut s = Seq::new(1, 16, String::from("s"));
+        cfg(&mut s, "pattern 2");
+        cfg(&mut s, "steps 3");
+        cfg(&mut s, "step 1 note 40 prob 0");
+        assert_eq!(s.patterns.len(), 2);
+        assert_eq!(s.patterns[0].len(), 16);
+        s.start();
+        s.update(s.last);
+        assert!(s.buf.is_empty());
+
+        let lines: Vec<String> = s.cfg_lines().iter().map(|l| l.join(" ")).collect();
+        assert!(lines.contains(&String::from("step 1 note 40 velocity 100 gate 50 probability 0 on")));
+        assert_eq!(lines.last().unwrap(), "pattern 2");
+    }
+}
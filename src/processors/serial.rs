@@ -0,0 +1,267 @@
+use crate::utils::*;
+use crate::consts::serial_cmds::*;
+use crate::shell::print_line;
+use crate::stream::{StreamEncoder, StreamParser};
+use crate::MidiIO;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+
+const DEFAULT_BAUD: u32 = 31250;
+/// at 31250 baud this is a couple of seconds' worth, so anything more means the device isn't taking it
+const MAX_BUFFER: usize = 1 << 13;
+
+pub struct Serial {
+    file: File,
+    path: String,
+    baud: u32,
+    parser: StreamParser,
+    encoder: StreamEncoder,
+    running_status: bool,
+    outbuf: Vec<u8>,
+    dropped: u64, // bytes
+    name: String,
+    outputs: Vec<Id>,
+}
+impl Serial {
+    fn new(path: String, baud: u32, name: String) -> Result<Serial> {
+        let file = open(&path)?;
+        configure(&file, baud)?;
+        Ok(Serial {
+            file, path, baud, name,
+            parser: StreamParser::default(),
+            encoder: StreamEncoder::new(true),
+            running_status: true,
+            outbuf: Vec::new(),
+            dropped: 0,
+            outputs: Vec::new()
+        })
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        let baud = match args.len() {
+            1 => DEFAULT_BAUD,
+            2 => args[1].parse()?,
+            _ => return Err(MMMErr::ArgError)
+        };
+        Self::new(args[0].clone(), baud, name).map(|s| Box::new(s) as Box<dyn MidiIO>)
+    }
+
+    fn flush(&mut self) {
+        while !self.outbuf.is_empty() {
+            match self.file.write(&self.outbuf) {
+                Ok(len) if len > 0 => {
+                    self.outbuf.drain(..len);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                _ => break
+            }
+        }
+        if self.outbuf.len() > MAX_BUFFER {
+            self.dropped += self.outbuf.len() as u64;
+            self.outbuf.clear();
+            // the device may have been cut off part way through a message, so it needs a status byte to get going again
+            self.encoder.reset();
+            print_line(format!("{}: {} can't keep up, dropped what was waiting", self.name, self.path))
+        }
+    }
+}
+impl MidiIO for Serial {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} <-> {} (serial, {} baud)", self.path, self.name, self.baud) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("serial on {} at {} baud, running status {}, {} bytes dropped",
+                self.path, self.baud, if self.running_status { "on" } else { "off" }, self.dropped)
+        }
+        else {
+            match shortened_keyword_match(&command[0], SERIAL_CMDS) {
+                Some(IDX_BAUD) => match command.get(1).map(|b| b.parse()) {
+                    Some(Ok(baud)) => match configure(&self.file, baud) {
+                        Ok(_) => self.baud = baud,
+                        Err(e) => outln!("failed to set baud rate: {}", e)
+                    }
+                    Some(Err(_)) => outln!("invalid baud rate"),
+                    None => outln!("baud rate required")
+                }
+                Some(IDX_RUNNING) => {
+                    self.running_status = !self.running_status;
+                    self.encoder = StreamEncoder::new(self.running_status)
+                }
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in SERIAL_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        vec![String::from("serial"), self.path.clone(), self.baud.to_string()]
+    }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        if self.running_status {
+            Vec::new()
+        }
+        else {
+            vec![vec![String::from("running")]]
+        }
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        for m in messages {
+            let bytes = self.encoder.encode(m);
+            self.outbuf.extend(bytes)
+        }
+        self.flush()
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            match self.file.read(&mut buf) {
+                Ok(len) if len > 0 => ret.extend(self.parser.feed(&buf[..len])),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                // nothing waiting, or the device has gone away
+                _ => break
+            }
+        }
+        self.flush();
+        ret
+    }
+
+    /// gives the device a moment to take whatever's left
+    fn delete(mut self: Box<Self>) {
+        for _ in 0..10 {
+            self.flush();
+            if self.outbuf.is_empty() {
+                break
+            }
+            sleep_ms(10)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn open(path: &str) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    // without O_NOCTTY, a serial port could end up as our controlling terminal
+    OpenOptions::new().read(true).write(true).custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK).open(path)
+}
+#[cfg(not(unix))]
+fn open(_path: &str) -> io::Result<File> {
+    Err(io::Error::new(ErrorKind::Unsupported, "serial devices aren't supported on this platform"))
+}
+
+/// raw mode at any baud rate. devices that aren't terminals (like /dev/snd/midiC0D0) are left alone
+#[cfg(unix)]
+fn configure(file: &File, baud: u32) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let fd = file.as_raw_fd();
+    // safe because the fd stays open for as long as file does, and the structs are only written by the calls
+    unsafe {
+        let mut t: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut t) != 0 {
+            let e = io::Error::last_os_error();
+            return if e.raw_os_error() == Some(libc::ENOTTY) { Ok(()) } else { Err(e) }
+        }
+        libc::cfmakeraw(&mut t);
+        t.c_cflag |= libc::CLOCAL | libc::CREAD;
+        t.c_cc[libc::VMIN] = 0;
+        t.c_cc[libc::VTIME] = 0;
+        if libc::tcsetattr(fd, libc::TCSANOW, &t) != 0 {
+            return Err(io::Error::last_os_error())
+        }
+        set_speed(fd, baud)
+    }
+}
+/// 31250 isn't one of the standard speeds, so on linux it has to be set the long way round
+#[cfg(target_os = "linux")]
+unsafe fn set_speed(fd: libc::c_int, baud: u32) -> io::Result<()> {
+    let mut t: libc::termios2 = std::mem::zeroed();
+    if libc::ioctl(fd, libc::TCGETS2, &mut t) != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    t.c_cflag &= !libc::CBAUD;
+    t.c_cflag |= libc::BOTHER;
+    t.c_ispeed = baud;
+    t.c_ospeed = baud;
+    if libc::ioctl(fd, libc::TCSETS2, &t) != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+/// elsewhere the speed is just a number, though not every driver takes every number
+#[cfg(all(unix, not(target_os = "linux")))]
+unsafe fn set_speed(fd: libc::c_int, baud: u32) -> io::Result<()> {
+    let mut t: libc::termios = std::mem::zeroed();
+    if libc::tcgetattr(fd, &mut t) != 0 || libc::cfsetspeed(&mut t, baud as libc::speed_t) != 0 || libc::tcsetattr(fd, libc::TCSANOW, &t) != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+#[cfg(not(unix))]
+fn configure(_file: &File, _baud: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::os::unix::io::FromRawFd;
+
+    /// the controlling end, and the path of the device end
+    fn pty() -> (File, String) {
+        let mut controller = 0;
+        let mut device = 0;
+        let mut path = [0 as libc::c_char; 128];
+        unsafe {
+            assert_eq!(libc::openpty(&mut controller, &mut device, path.as_mut_ptr(), std::ptr::null(), std::ptr::null()), 0);
+            let path = std::ffi::CStr::from_ptr(path.as_ptr()).to_string_lossy().into_owned();
+            // the processor opens it again by name
+            libc::close(device);
+            (File::from_raw_fd(controller), path)
+        }
+    }
+
+    #[test]
+    fn through_a_pty() {
+        let (mut controller, path) = pty();
+        let mut s = Serial::new(path, 31250, String::from("s")).unwrap();
+
+        // running status, with a clock in the middle
+        controller.write_all(&[0x90, 60, 100, 62, 0xf8, 100]).unwrap();
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            received.extend(s.read());
+            if received.len() == 3 {
+                break
+            }
+            sleep_ms(10)
+        }
+        assert_eq!(received, vec![MidiMessage::note_on(1, 60, 100), MidiMessage::SystemRealtime(0xf8), MidiMessage::note_on(1, 62, 100)]);
+
+        s.write(&[MidiMessage::control_change(1, 7, 1), MidiMessage::control_change(1, 7, 2)]);
+        let mut buf = [0; 5];
+        controller.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0xb0, 7, 1, 7, 2]);
+
+        s.cfg(&[String::from("running")]);
+        s.write(&[MidiMessage::control_change(1, 7, 3), MidiMessage::control_change(1, 7, 4)]);
+        let mut buf = [0; 6];
+        controller.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0xb0, 7, 3, 0xb0, 7, 4]);
+    }
+}
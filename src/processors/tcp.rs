@@ -0,0 +1,445 @@
+use crate::utils::*;
+use crate::consts::tcp_cmds::*;
+use crate::consts::tcp_protocols::*;
+use crate::json::{self, Json};
+use crate::shell::print_line;
+use crate::stream::{StreamEncoder, StreamParser};
+use crate::ws::*;
+use crate::MidiIO;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+/// clients that send this much without finishing a message, or don't read what we send them, get dropped
+const MAX_BUFFER: usize = 1 << 20;
+
+struct Client {
+    stream: TcpStream,
+    addr: SocketAddr,
+    protocol: usize,
+    inbuf: Vec<u8>, // json lines, or the websocket handshake and frames, that aren't complete yet
+    outbuf: Vec<u8>, // what the socket hasn't taken yet
+    parser: StreamParser,
+    encoder: StreamEncoder,
+    upgraded: bool, // websocket handshake done
+    fragments: Vec<u8>,
+    fragment_op: u8,
+    closing: bool, // finish sending, then close
+    closed: bool,
+}
+impl Client {
+    fn new(stream: TcpStream, addr: SocketAddr, protocol: usize) -> Client {
+        Client {
+            stream, addr, protocol,
+            inbuf: Vec::new(),
+            outbuf: Vec::new(),
+            parser: StreamParser::default(),
+            encoder: StreamEncoder::new(false),
+            upgraded: false,
+            fragments: Vec::new(),
+            fragment_op: OP_TEXT,
+            closing: false,
+            closed: false
+        }
+    }
+
+    fn receive(&mut self) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.closed = true;
+                    break
+                }
+                Ok(len) if self.protocol == IDX_RAW => ret.extend(self.parser.feed(&buf[..len])),
+                Ok(len) => self.inbuf.extend(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.closed = true;
+                    break
+                }
+            }
+        }
+        match self.protocol {
+            IDX_JSON => {
+                while let Some(end) = self.inbuf.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = self.inbuf.drain(..=end).collect();
+                    ret.extend(self.json_line(&String::from_utf8_lossy(&line)))
+                }
+            }
+            IDX_WEBSOCKET => ret.extend(self.websocket()),
+            _ => {}
+        }
+        // unfinished sysex and websocket messages count too
+        if self.inbuf.len() + self.fragments.len() + self.parser.pending() > MAX_BUFFER {
+            self.closed = true
+        }
+        ret
+    }
+    fn json_line(&mut self, line: &str) -> Option<MidiMessage> {
+        let line = line.trim();
+        if line.is_empty() {
+            return None
+        }
+        let ret = Json::parse(line).as_ref().and_then(json::to_message);
+        if ret.is_none() {
+            let error = Json::object([("error", Json::from("invalid message")), ("line", Json::from(line))]);
+            self.send_text(&error.to_string())
+        }
+        ret
+    }
+    fn websocket(&mut self) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        if !self.upgraded {
+            let end = match self.inbuf.windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(end) => end,
+                None => return ret
+            };
+            let request = String::from_utf8_lossy(&self.inbuf[..end]).into_owned();
+            self.inbuf.drain(..end + 4);
+            match handshake_key(&request) {
+                Some(key) => {
+                    self.outbuf.extend(handshake_response(&key).as_bytes());
+                    self.upgraded = true
+                }
+                None => {
+                    self.outbuf.extend(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+                    self.closing = true;
+                    return ret
+                }
+            }
+        }
+        while let Some((frame, len)) = parse_frame(&self.inbuf) {
+            self.inbuf.drain(..len);
+            match frame.opcode {
+                OP_PING => self.outbuf.extend(encode_frame(OP_PONG, &frame.payload)),
+                OP_PONG => {}
+                OP_CLOSE => {
+                    self.outbuf.extend(encode_frame(OP_CLOSE, &[]));
+                    self.closing = true;
+                    break
+                }
+                op => {
+                    if op != OP_CONTINUATION {
+                        self.fragment_op = op;
+                        self.fragments.clear()
+                    }
+                    self.fragments.extend(frame.payload);
+                    if !frame.fin {
+                        continue
+                    }
+                    let data = std::mem::take(&mut self.fragments);
+                    if self.fragment_op == OP_BINARY {
+                        ret.extend(self.parser.feed(&data))
+                    }
+                    else {
+                        for line in String::from_utf8_lossy(&data).lines() {
+                            ret.extend(self.json_line(line))
+                        }
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    fn send(&mut self, m: &MidiMessage) {
+        match self.protocol {
+            IDX_RAW => {
+                let bytes = self.encoder.encode(m);
+                self.outbuf.extend(bytes)
+            }
+            _ => self.send_text(&json::from_message(m).to_string())
+        }
+    }
+    /// a line, or a text frame for websockets
+    fn send_text(&mut self, s: &str) {
+        if self.protocol != IDX_WEBSOCKET {
+            self.outbuf.extend(s.as_bytes());
+            self.outbuf.push(b'\n')
+        }
+        // websocket clients still in the middle of their handshake don't get anything
+        else if self.upgraded {
+            self.outbuf.extend(encode_frame(OP_TEXT, s.as_bytes()))
+        }
+    }
+    fn flush(&mut self) {
+        while !self.outbuf.is_empty() {
+            match self.stream.write(&self.outbuf) {
+                Ok(0) => self.closed = true,
+                Ok(len) => {
+                    self.outbuf.drain(..len);
+                    continue
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(_) => self.closed = true
+            }
+            break
+        }
+        if self.outbuf.len() > MAX_BUFFER || (self.closing && self.outbuf.is_empty()) {
+            self.closed = true
+        }
+    }
+}
+
+pub struct TcpServer {
+    listener: TcpListener,
+    port: u16,
+    protocol: usize, // index into TCP_PROTOCOLS
+    clients: Vec<Client>,
+    name: String,
+    outputs: Vec<Id>,
+}
+impl TcpServer {
+    fn new(port: u16, protocol: usize, name: String) -> Result<TcpServer> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        Ok(TcpServer {
+            listener, port, protocol, name,
+            clients: Vec::new(),
+            outputs: Vec::new()
+        })
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        let protocol = match args.len() {
+            1 => IDX_RAW,
+            2 => shortened_keyword_match(&args[1], TCP_PROTOCOLS).ok_or(MMMErr::ArgError)?,
+            _ => return Err(MMMErr::ArgError)
+        };
+        Self::new(args[0].parse()?, protocol, name).map(|t| Box::new(t) as Box<dyn MidiIO>)
+    }
+
+    /// drops closed clients, after letting everyone know
+    fn tidy(&mut self) {
+        let name = &self.name;
+        self.clients.retain(|c| {
+            if c.closed {
+                print_line(format!("{}: {} disconnected", name, c.addr))
+            }
+            !c.closed
+        })
+    }
+}
+impl MidiIO for TcpServer {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String {
+        format!("tcp {} <-> {} ({} clients, {})", self.port, self.name, self.clients.len(), TCP_PROTOCOLS[self.protocol])
+    }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            outln!("tcp server on port {} using {}, with {} clients", self.port, TCP_PROTOCOLS[self.protocol], self.clients.len());
+            for c in &self.clients {
+                outln!("\t{}", c.addr)
+            }
+        }
+        else {
+            match shortened_keyword_match(&command[0], TCP_CMDS) {
+                Some(IDX_KICK) => match command.get(1) {
+                    Some(addr) => match self.clients.iter().position(|c| c.addr.to_string() == *addr) {
+                        Some(idx) => drop(self.clients.remove(idx)),
+                        None => outln!("no client at that address")
+                    }
+                    None => self.clients.clear()
+                }
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in TCP_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        vec![String::from("tcp"), self.port.to_string(), String::from(TCP_PROTOCOLS[self.protocol])]
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        for c in &mut self.clients {
+            for m in messages {
+                c.send(m)
+            }
+            c.flush()
+        }
+        self.tidy()
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        while let Ok((stream, addr)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                print_line(format!("{}: {} connected", self.name, addr));
+                self.clients.push(Client::new(stream, addr, self.protocol))
+            }
+        }
+        let mut ret = Vec::new();
+        for c in &mut self.clients {
+            ret.extend(c.receive());
+            c.flush()
+        }
+        self.tidy();
+        ret
+    }
+
+    /// gives clients a moment to take whatever's left
+    fn delete(mut self: Box<Self>) {
+        for _ in 0..10 {
+            for c in &mut self.clients {
+                c.flush()
+            }
+            if self.clients.iter().all(|c| c.closed || c.outbuf.is_empty()) {
+                break
+            }
+            sleep_ms(10)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn connect(t: &mut TcpServer) -> TcpStream {
+        let client = TcpStream::connect(("127.0.0.1", t.port)).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        for _ in 0..100 {
+            t.read();
+            if !t.clients.is_empty() {
+                break
+            }
+            sleep_ms(10)
+        }
+        client
+    }
+    /// keeps sending the same thing after the start until the server hangs up, and checks that it does
+    fn flood(t: &mut TcpServer, mut client: TcpStream, start: Vec<u8>, chunk: Vec<u8>) {
+        let writer = std::thread::spawn(move || {
+            client.write_all(&start).ok();
+            for _ in 0..(4 * MAX_BUFFER / chunk.len()) {
+                if client.write_all(&chunk).is_err() {
+                    return
+                }
+            }
+            // hanging on until the server hangs up, so it isn't just seeing us leave
+            let mut buf = [0; 512];
+            for _ in 0..10 {
+                match client.read(&mut buf) {
+                    Ok(0) => return,
+                    Err(e) if !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return,
+                    _ => {}
+                }
+            }
+        });
+        for _ in 0..500 {
+            t.read();
+            if t.clients.is_empty() {
+                break
+            }
+            sleep_ms(10)
+        }
+        assert!(t.clients.is_empty());
+        writer.join().unwrap()
+    }
+    fn read_until(t: &mut TcpServer, count: usize) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        for _ in 0..100 {
+            ret.extend(t.read());
+            if ret.len() >= count {
+                break
+            }
+            sleep_ms(10)
+        }
+        ret
+    }
+
+    #[test]
+    fn raw() {
+        let mut t = TcpServer::new(0, IDX_RAW, String::from("t")).unwrap();
+        let mut client = connect(&mut t);
+        client.write_all(&[0x90, 60, 100, 62, 100]).unwrap();
+        assert_eq!(read_until(&mut t, 2), vec![MidiMessage::note_on(1, 60, 100), MidiMessage::note_on(1, 62, 100)]);
+
+        t.write(&[MidiMessage::control_change(1, 1, 2)]);
+        let mut buf = [0; 3];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0xb0, 1, 2]);
+
+        drop(client);
+        read_until(&mut t, 1);
+        assert!(t.clients.is_empty());
+
+        // a sysex that never ends
+        let client = connect(&mut t);
+        flood(&mut t, client, vec![0xf0], vec![0; 4096])
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut t = TcpServer::new(0, IDX_JSON, String::from("t")).unwrap();
+        let mut client = connect(&mut t);
+        client.write_all(b"{\"type\":\"note_on\",\"channel\":1,\"note\":60,\"velocity\":100}\nnonsense\n").unwrap();
+        assert_eq!(read_until(&mut t, 1), vec![MidiMessage::note_on(1, 60, 100)]);
+
+        t.write(&[MidiMessage::program_change(2, 3)]);
+        let mut reader = std::io::BufReader::new(client);
+        let mut line = String::new();
+        std::io::BufRead::read_line(&mut reader, &mut line).unwrap();
+        assert!(line.starts_with("{\"error\":\"invalid message\""));
+        line.clear();
+        std::io::BufRead::read_line(&mut reader, &mut line).unwrap();
+        assert_eq!(line, "{\"type\":\"program\",\"channel\":2,\"program\":3}\n");
+    }
+
+    #[test]
+    fn websocket() {
+        let mut t = TcpServer::new(0, IDX_WEBSOCKET, String::from("t")).unwrap();
+        let mut client = connect(&mut t);
+        client.write_all(b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n").unwrap();
+        // a masked text frame, split into two fragments
+        let text = b"{\"type\":\"cc\",\"channel\":1,\"controller\":7,\"value\":9}";
+        let mask = [1, 2, 3, 4];
+        for (idx, part) in text.chunks(30).enumerate() {
+            let head = if idx == 0 { 0x01 } else { 0x80 };
+            client.write_all(&[head, 0x80 | part.len() as u8]).unwrap();
+            client.write_all(&mask).unwrap();
+            client.write_all(&part.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect::<Vec<_>>()).unwrap();
+        }
+        assert_eq!(read_until(&mut t, 1), vec![MidiMessage::control_change(1, 7, 9)]);
+
+        t.write(&[MidiMessage::SystemRealtime(0xfa)]);
+        let mut buf = vec![0; 512];
+        let mut received = Vec::new();
+        while !received.ends_with(b"[250]}") {
+            let len = client.read(&mut buf).unwrap();
+            received.extend(&buf[..len])
+        }
+        let end = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert!(received.starts_with(b"HTTP/1.1 101"));
+        let (frame, _) = parse_frame(&received[end..]).unwrap();
+        assert_eq!(frame.payload, b"{\"type\":\"raw\",\"data\":[250]}");
+
+        // a message that never gets its last fragment
+        drop(client);
+        read_until(&mut t, 1);
+        let client = connect(&mut t);
+        let mut start = b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n".to_vec();
+        let mut chunk = encode_frame(OP_BINARY, &[0; 4096]);
+        chunk[0] &= 0x7f;
+        start.extend(&chunk);
+        chunk[0] = OP_CONTINUATION;
+        flood(&mut t, client, start, chunk)
+    }
+}
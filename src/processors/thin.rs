@@ -0,0 +1,295 @@
+use crate::utils::*;
+use crate::consts::thin_cmds::*;
+use crate::MidiIO;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+pub struct Thin {
+    rate: Option<u32>, // messages per second
+    window: u64, // in ms
+    dedup: bool,
+    next_send: Duration, // when the limit next lets something through
+    queue: VecDeque<Waiting>, // kept in order
+    last_sent: HashMap<(u8, u8), MidiMessage>,
+    name: String,
+    buf: Vec<MidiMessage>,
+    outputs: Vec<Id>,
+}
+/// (status, controller) for values where only the latest matters, or None for anything else that counts towards the
+/// limit, with the message and when it's due to go
+type Waiting = (Option<(u8, u8)>, MidiMessage, Duration);
+
+impl Thin {
+    fn new(rate: Option<u32>, name: String) -> Thin {
+        Thin {
+            rate, name,
+            window: 10,
+            dedup: true,
+            next_send: Duration::ZERO,
+            queue: VecDeque::new(),
+            last_sent: HashMap::new(),
+            buf: Vec::new(),
+            outputs: Vec::new()
+        }
+    }
+    pub fn new_args(name: String, args: &[String]) -> Result<Box<dyn MidiIO>> {
+        let rate = match args {
+            [] => None,
+            [r] => Some(r.parse()?),
+            _ => return Err(MMMErr::ArgError)
+        };
+        if rate != Some(0) {
+            Ok(Box::new(Self::new(rate, name)) as Box<dyn MidiIO>)
+        }
+        else {
+            Err(MMMErr::ArgError)
+        }
+    }
+
+    fn process(&mut self, now: Duration, m: &MidiMessage) {
+        let passes = m.note().is_some() || matches!(m, MidiMessage::SystemRealtime(_)) || is_switch(m);
+        if passes && !self.queue.iter().any(|(k, _, _)| k.is_none()) {
+            // never held back, but still uses up some of the allowance
+            self.use_allowance(now);
+            self.buf.push(m.clone())
+        }
+        else if let Some(key) = coalesce_key(m) {
+            // values can only be merged with ones that haven't got anything kept in order behind them
+            let start = self.queue.iter().rposition(|(k, _, _)| k.is_none()).map_or(0, |idx| idx + 1);
+            let latest = self.queue.iter().rev().find(|(k, _, _)| *k == Some(key)).map(|(_, m, _)| m)
+                .or_else(|| self.last_sent.get(&key));
+            let dup = self.dedup && latest == Some(m);
+            match self.queue.range_mut(start..).find(|(k, _, _)| *k == Some(key)) {
+                Some((_, waiting, _)) => *waiting = m.clone(),
+                None if dup => {}
+                None => self.queue.push_back((Some(key), m.clone(), now + Duration::from_millis(self.window)))
+            }
+        }
+        else {
+            // values that came in earlier go out first, without waiting out their window. anything that would
+            // otherwise pass straight through waits here too if it's behind something kept in order
+            for (_, _, due) in &mut self.queue {
+                *due = Duration::ZERO
+            }
+            self.queue.push_back((None, m.clone(), now))
+        }
+    }
+    fn use_allowance(&mut self, now: Duration) {
+        if let Some(rate) = self.rate {
+            self.next_send = self.next_send.max(now) + Duration::from_secs(1) / rate
+        }
+    }
+    fn update(&mut self, now: Duration) {
+        while self.rate.is_none() || self.next_send <= now {
+            // values are queued in the order they came in, so if the first isn't ready, none of them are
+            match self.queue.front() {
+                Some((Some(_), _, due)) if *due > now => break,
+                None => break,
+                _ => {}
+            }
+            let (key, m, _) = self.queue.pop_front().unwrap();
+            if let Some(key) = key {
+                // it may have gone back to what was last sent while it was waiting
+                if self.dedup && self.last_sent.get(&key) == Some(&m) {
+                    continue
+                }
+                self.last_sent.insert(key, m.clone());
+            }
+            self.use_allowance(now);
+            self.buf.push(m)
+        }
+    }
+    /// send everything still waiting, ignoring the limit
+    fn flush(&mut self) {
+        for (key, m, _) in self.queue.drain(..) {
+            if let Some(key) = key {
+                self.last_sent.insert(key, m.clone());
+            }
+            self.buf.push(m)
+        }
+    }
+
+    fn set_rate(&mut self, args: &[String]) {
+        match args.first().map(|s| s.parse()) {
+            Some(Ok(r)) if r > 0 => self.rate = Some(r),
+            Some(_) => outln!("rate must be a positive number"),
+            None => self.rate = None
+        }
+    }
+    fn set_window(&mut self, args: &[String]) {
+        match args.first().map(|s| s.parse()) {
+            Some(Ok(w)) => self.window = w,
+            _ => outln!("window length in ms required")
+        }
+    }
+}
+impl MidiIO for Thin {
+    fn can_read(&self) -> bool { true }
+    fn can_write(&self) -> bool { true }
+
+    fn get_name(&self) -> String { self.name.clone() }
+    fn get_display_name(&self) -> String { format!("{} (thin)", self.name) }
+    fn set_name(&mut self, name: &str) { self.name = name.into() }
+
+    fn list_outputs(&self) -> &[Id] { &self.outputs }
+    fn add_output(&mut self, id: Id) {
+        push_if_not_present(id, &mut self.outputs)
+    }
+    fn rem_output(&mut self, id: Id) {
+        self.outputs.iter().position(|i| *i == id).map(|idx| self.outputs.remove(idx));
+    }
+
+    fn cfg(&mut self, command: &[String]) {
+        if command.is_empty() {
+            match self.rate {
+                Some(r) => out!("thin limited to {} messages per second", r),
+                None => out!("thin with no rate limit")
+            }
+            outln!(", {}ms window, dedup {}, {} messages waiting",
+                self.window, if self.dedup { "on" } else { "off" }, self.queue.len())
+        }
+        else {
+            match shortened_keyword_match(&command[0], THIN_CMDS) {
+                Some(IDX_RATE) => self.set_rate(&command[1..]),
+                Some(IDX_WINDOW) => self.set_window(&command[1..]),
+                Some(IDX_DEDUP) => self.dedup = !self.dedup,
+                Some(IDX_FLUSH) => self.flush(),
+                _ => {
+                    outln!("command not found! valid commands are:");
+                    for cmd in THIN_CMDS {
+                        outln!("\t{}", cmd)
+                    }
+                }
+            }
+        }
+    }
+    fn init_args(&self) -> Vec<String> {
+        let mut ret = vec![String::from("thin")];
+        ret.extend(self.rate.map(|r| r.to_string()));
+        ret
+    }
+    fn cfg_lines(&self) -> Vec<Vec<String>> {
+        let mut ret = vec![vec![String::from("window"), self.window.to_string()]];
+        if !self.dedup {
+            ret.push(vec![String::from("dedup")])
+        }
+        ret
+    }
+    fn release(&mut self) -> Vec<MidiMessage> {
+        self.flush();
+        self.read()
+    }
+
+    fn write(&mut self, messages: &[MidiMessage]) {
+        let now = timestamp();
+        for m in messages {
+            self.process(now, m)
+        }
+    }
+    fn read(&mut self) -> Vec<MidiMessage> {
+        self.update(timestamp());
+        let replacement = Vec::new();
+        std::mem::replace(&mut self.buf, replacement)
+    }
+
+    fn delete(self: Box<Self>) { }
+}
+
+/// ccs where every message matters, and in order with the notes: switches like the sustain pedal, channel mode
+/// messages, and the parameter numbers and data entry that make up (n)rpns
+fn is_switch(m: &MidiMessage) -> bool {
+    match m {
+        MidiMessage::Channel([s, cc, _]) if s & 0xf0 == 0xb0 => matches!(cc, 6 | 38 | 64..=69 | 96..=101 | 120..=127),
+        _ => false
+    }
+}
+
+/// messages where only the latest value matters, keyed by status and controller
+fn coalesce_key(m: &MidiMessage) -> Option<(u8, u8)> {
+    match m {
+        MidiMessage::Channel([s, a, _]) if matches!(s & 0xf0, 0xa0 | 0xb0) && !is_switch(m) => Some((*s, *a)),
+        MidiMessage::Channel([s, _, _]) if s & 0xf0 == 0xe0 => Some((*s, 0)),
+        MidiMessage::ChannelSmall([s, _]) if s & 0xf0 == 0xd0 => Some((*s, 0)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(msgs: Vec<MidiMessage>) -> Vec<Vec<u8>> {
+        msgs.iter().map(|m| m.to_bytes()).collect()
+    }
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn coalesces_and_dedups() {
+        let mut t = Thin::new(None, String::from("t"));
+        for v in 0..10 {
+            t.process(ms(v), &MidiMessage::control_change(1, 1, v as u8))
+        }
+        t.process(ms(5), &MidiMessage::note_on(1, 60, 100));
+        t.update(ms(9));
+        assert_eq!(bytes(std::mem::take(&mut t.buf)), vec![vec![0x90, 60, 100]]);
+        t.update(ms(10));
+        assert_eq!(bytes(std::mem::take(&mut t.buf)), vec![vec![0xb0, 1, 9]]);
+        t.process(ms(20), &MidiMessage::control_change(1, 1, 9));
+        t.update(ms(40));
+        assert!(t.buf.is_empty());
+    }
+
+    #[test]
+    fn rate_limited() {
+        let mut t = Thin::new(Some(100), String::from("t"));
+        t.window = 0;
+        for cc in 0..20 {
+            t.process(ms(0), &MidiMessage::control_change(1, cc, 1))
+        }
+        for now in 0..=100 {
+            t.update(ms(now))
+        }
+        // one straight away, then one every 10ms
+        assert_eq!(t.buf.len(), 11);
+        assert_eq!(t.queue.len(), 9);
+        assert_eq!(bytes(t.release()).len(), 20);
+    }
+
+    #[test]
+    fn order_is_kept() {
+        let mut t = Thin::new(None, String::from("t"));
+        t.process(ms(0), &MidiMessage::control_change(1, 7, 10));
+        t.process(ms(1), &MidiMessage::program_change(1, 5));
+        t.process(ms(2), &MidiMessage::control_change(1, 7, 20));
+        t.process(ms(3), &MidiMessage::control_change(1, 7, 30));
+        t.update(ms(4));
+        // the value from before the program change goes with it, and the last one waits out its window
+        assert_eq!(bytes(std::mem::take(&mut t.buf)), vec![vec![0xb0, 7, 10], vec![0xc0, 5]]);
+        t.update(ms(20));
+        assert_eq!(bytes(std::mem::take(&mut t.buf)), vec![vec![0xb0, 7, 30]]);
+
+        // notes don't overtake a program change or sysex that came in before them
+        t.process(ms(21), &MidiMessage::control_change(1, 0, 1));
+        t.process(ms(21), &MidiMessage::control_change(1, 32, 2));
+        t.process(ms(21), &MidiMessage::program_change(1, 6));
+        t.process(ms(21), &MidiMessage::note_on(1, 60, 100));
+        t.process(ms(22), &MidiMessage::SystemCommon(vec![0xf0, 0x7e, 0xf7]));
+        t.process(ms(22), &MidiMessage::note_on(1, 62, 100));
+        t.update(ms(22));
+        assert_eq!(bytes(std::mem::take(&mut t.buf)), vec![
+            vec![0xb0, 0, 1], vec![0xb0, 32, 2], vec![0xc0, 6], vec![0x90, 60, 100],
+            vec![0xf0, 0x7e, 0xf7], vec![0x90, 62, 100]
+        ]);
+
+        // switches and nrpns go straight through, every one of them
+        for (cc, v) in [(99, 1), (98, 2), (6, 3), (38, 4), (64, 127), (64, 0), (123, 0)] {
+            t.process(ms(30), &MidiMessage::control_change(1, cc, v))
+        }
+        assert_eq!(bytes(std::mem::take(&mut t.buf)), vec![
+            vec![0xb0, 99, 1], vec![0xb0, 98, 2], vec![0xb0, 6, 3], vec![0xb0, 38, 4],
+            vec![0xb0, 64, 127], vec![0xb0, 64, 0], vec![0xb0, 123, 0]
+        ]);
+    }
+}
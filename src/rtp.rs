@@ -0,0 +1,525 @@
+//! rtp-midi (rfc 6295) packets and recovery journals, and the applemidi session protocol that goes with them
+
+use crate::utils::*;
+use crate::stream::message_len;
+
+const PAYLOAD_TYPE: u8 = 0x61;
+const PROTOCOL_VERSION: u32 = 2;
+
+/// applemidi session commands, which go to both the control and data ports
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Invite { token: u32, ssrc: u32, name: String },
+    Accept { token: u32, ssrc: u32, name: String },
+    Reject { token: u32, ssrc: u32 },
+    Bye { token: u32, ssrc: u32 },
+    /// timestamps are in units of 100 microseconds
+    Clock { ssrc: u32, count: u8, ts: [u64; 3] },
+    /// the last sequence number received, so the sender can trim its journal
+    Feedback { ssrc: u32, seq: u16 },
+}
+impl Command {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = vec![0xff, 0xff];
+        let (code, token, ssrc, name) = match self {
+            Command::Invite { token, ssrc, name } => (b"IN", *token, *ssrc, Some(name)),
+            Command::Accept { token, ssrc, name } => (b"OK", *token, *ssrc, Some(name)),
+            Command::Reject { token, ssrc } => (b"NO", *token, *ssrc, None),
+            Command::Bye { token, ssrc } => (b"BY", *token, *ssrc, None),
+            Command::Clock { ssrc, count, ts } => {
+                ret.extend(b"CK");
+                ret.extend(ssrc.to_be_bytes());
+                ret.extend([*count, 0, 0, 0]);
+                for t in ts {
+                    ret.extend(t.to_be_bytes())
+                }
+                return ret
+            }
+            Command::Feedback { ssrc, seq } => {
+                ret.extend(b"RS");
+                ret.extend(ssrc.to_be_bytes());
+                ret.extend(seq.to_be_bytes());
+                ret.extend([0, 0]);
+                return ret
+            }
+        };
+        ret.extend(code);
+        ret.extend(PROTOCOL_VERSION.to_be_bytes());
+        ret.extend(token.to_be_bytes());
+        ret.extend(ssrc.to_be_bytes());
+        if let Some(name) = name {
+            ret.extend(name.as_bytes());
+            ret.push(0)
+        }
+        ret
+    }
+    pub fn parse(bytes: &[u8]) -> Option<Command> {
+        if bytes.len() < 8 || bytes[..2] != [0xff, 0xff] {
+            return None
+        }
+        let u32_at = |i: usize| bytes.get(i..i + 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()));
+        let u64_at = |i: usize| bytes.get(i..i + 8).map(|b| u64::from_be_bytes(b.try_into().unwrap()));
+        match &bytes[2..4] {
+            b"CK" => Some(Command::Clock {
+                ssrc: u32_at(4)?,
+                count: *bytes.get(8)?,
+                ts: [u64_at(12)?, u64_at(20)?, u64_at(28)?]
+            }),
+            b"RS" => Some(Command::Feedback { ssrc: u32_at(4)?, seq: (u32_at(8)? >> 16) as u16 }),
+            code => {
+                let (token, ssrc) = (u32_at(8)?, u32_at(12)?);
+                let name = bytes.get(16..).map(|b| {
+                    let end = b.iter().position(|c| *c == 0).unwrap_or(b.len());
+                    String::from_utf8_lossy(&b[..end]).into_owned()
+                }).unwrap_or_default();
+                match code {
+                    b"IN" => Some(Command::Invite { token, ssrc, name }),
+                    b"OK" => Some(Command::Accept { token, ssrc, name }),
+                    b"NO" => Some(Command::Reject { token, ssrc }),
+                    b"BY" => Some(Command::Bye { token, ssrc }),
+                    _ => None
+                }
+            }
+        }
+    }
+}
+
+pub struct Packet {
+    pub seq: u16,
+    pub ssrc: u32,
+    pub messages: Vec<MidiMessage>,
+    pub journal: Vec<ChannelJournal>,
+}
+
+/// every message goes at the same time. the journal, if there is one, should describe the state before these messages
+pub fn encode_packet(seq: u16, timestamp: u32, ssrc: u32, messages: &[MidiMessage], journal: Option<&[u8]>) -> Vec<u8> {
+    let mut ret = vec![0x80, PAYLOAD_TYPE];
+    ret.extend(seq.to_be_bytes());
+    ret.extend(timestamp.to_be_bytes());
+    ret.extend(ssrc.to_be_bytes());
+
+    let mut list = Vec::new();
+    for (i, m) in messages.iter().enumerate() {
+        // no delta time before the first one, and the rest have a delta time of 0
+        if i != 0 {
+            list.push(0)
+        }
+        list.extend(m.to_bytes())
+    }
+    let j = if journal.is_some() { 0x40 } else { 0 };
+    if list.len() <= 0x0f {
+        ret.push(j | list.len() as u8)
+    }
+    else {
+        ret.extend([0x80 | j | (list.len() >> 8) as u8 & 0x0f, list.len() as u8])
+    }
+    ret.extend(list);
+    ret.extend(journal.unwrap_or(&[]));
+    ret
+}
+/// messages that don't fit or don't parse are left out. a journal that doesn't parse is treated as missing
+pub fn parse_packet(bytes: &[u8]) -> Option<Packet> {
+    if bytes.len() < 13 || bytes[0] & 0xc0 != 0x80 || bytes[1] & 0x7f != PAYLOAD_TYPE {
+        return None
+    }
+    let seq = u16::from_be_bytes([bytes[2], bytes[3]]);
+    let ssrc = u32::from_be_bytes(bytes[8..12].try_into().unwrap());
+
+    let section = &bytes[12..];
+    let flags = section[0];
+    let (len, start) = if flags & 0x80 != 0 {
+        ((flags as usize & 0x0f) << 8 | *section.get(1)? as usize, 2)
+    }
+    else {
+        (flags as usize & 0x0f, 1)
+    };
+    let list = section.get(start..start + len)?;
+    let journal = if flags & 0x40 != 0 {
+        parse_journal(&section[start + len..]).unwrap_or_default()
+    }
+    else {
+        Vec::new()
+    };
+    Some(Packet { seq, ssrc, messages: parse_command_list(list, flags & 0x20 != 0), journal })
+}
+fn parse_command_list(list: &[u8], first_delta: bool) -> Vec<MidiMessage> {
+    let mut ret = Vec::new();
+    let mut running = None;
+    let mut pos = 0;
+    while pos < list.len() {
+        if pos != 0 || first_delta {
+            // delta times are up to 4 bytes, and we don't use them
+            for _ in 0..4 {
+                pos += 1;
+                if list[pos - 1] & 0x80 == 0 || pos >= list.len() {
+                    break
+                }
+            }
+        }
+        let status = match list.get(pos) {
+            Some(0xf0) => {
+                // only whole sysex, not the segmented kind
+                match list[pos..].iter().position(|b| *b == 0xf7) {
+                    Some(end) => {
+                        ret.push(MidiMessage::SystemCommon(list[pos..=pos + end].to_vec()));
+                        pos += end + 1;
+                        continue
+                    }
+                    None => break
+                }
+            }
+            Some(b) if *b >= 0x80 => {
+                pos += 1;
+                running = if *b < 0xf0 { Some(*b) } else { None };
+                *b
+            }
+            Some(_) => match running {
+                Some(s) => s,
+                None => break
+            }
+            None => break
+        };
+        let data_len = message_len(status) - 1;
+        let data = match list.get(pos..pos + data_len) {
+            Some(d) => d,
+            None => break
+        };
+        let mut bytes = vec![status];
+        bytes.extend(data);
+        pos += data_len;
+        ret.extend(MidiMessage::from_slice(0, &bytes))
+    }
+    ret
+}
+
+/// what a channel journal told us. channel is 1 based, and notes are (note, velocity, should be played)
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct ChannelJournal {
+    pub channel: u8,
+    pub program: Option<u8>,
+    pub ccs: Vec<(u8, u8)>,
+    pub wheel: Option<(u8, u8)>,
+    pub notes: Vec<(u8, u8, bool)>,
+    pub offs: Vec<u8>,
+}
+/// only the program, controller, pitch wheel and note chapters are read, since they're the ones that matter most
+/// after a lost packet. the system journal is skipped
+fn parse_journal(bytes: &[u8]) -> Option<Vec<ChannelJournal>> {
+    let header = *bytes.first()?;
+    let mut pos = 3;
+    if header & 0x40 != 0 {
+        // system journal
+        let len = (*bytes.get(pos)? as usize & 0x03) << 8 | *bytes.get(pos + 1)? as usize;
+        pos += len
+    }
+    let mut ret = Vec::new();
+    if header & 0x20 == 0 {
+        return Some(ret)
+    }
+    for _ in 0..=(header & 0x0f) {
+        let (b0, b1) = (*bytes.get(pos)?, *bytes.get(pos + 1)?);
+        let len = (b0 as usize & 0x03) << 8 | b1 as usize;
+        let journal = bytes.get(pos..pos + len)?;
+        ret.push(parse_channel_journal((b0 >> 3 & 0x0f) + 1, journal)?);
+        pos += len
+    }
+    Some(ret)
+}
+fn parse_channel_journal(channel: u8, bytes: &[u8]) -> Option<ChannelJournal> {
+    let toc = *bytes.get(2)?;
+    let mut ret = ChannelJournal { channel, ..Default::default() };
+    let mut pos = 3;
+    let byte = |i: usize| bytes.get(i).copied();
+    if toc & 0x80 != 0 {
+        ret.program = Some(byte(pos)? & 0x7f);
+        pos += 3
+    }
+    if toc & 0x40 != 0 {
+        let count = (byte(pos)? & 0x7f) as usize + 1;
+        for i in 0..count {
+            let (number, value) = (byte(pos + 1 + i * 2)?, byte(pos + 2 + i * 2)?);
+            // the other kind of log is for counting presses, which doesn't map back to a value
+            if value & 0x80 == 0 {
+                ret.ccs.push((number & 0x7f, value))
+            }
+        }
+        pos += 1 + count * 2
+    }
+    if toc & 0x20 != 0 {
+        // parameter system chapter, which has its own length
+        pos += (byte(pos)? as usize & 0x03) << 8 | byte(pos + 1)? as usize
+    }
+    if toc & 0x10 != 0 {
+        ret.wheel = Some((byte(pos)? & 0x7f, byte(pos + 1)? & 0x7f));
+        pos += 2
+    }
+    if toc & 0x08 != 0 {
+        let (len, range) = (byte(pos)? & 0x7f, byte(pos + 1)?);
+        let (low, high) = (range >> 4, range & 0x0f);
+        let count = if len == 127 && low == 15 && high == 0 { 128 } else { len as usize };
+        pos += 2;
+        for _ in 0..count {
+            let (note, vel) = (byte(pos)? & 0x7f, byte(pos + 1)?);
+            ret.notes.push((note, vel & 0x7f, vel & 0x80 != 0));
+            pos += 2
+        }
+        if low <= high {
+            for octet in low..=high {
+                let bits = byte(pos)?;
+                for bit in 0..8 {
+                    if bits & 0x80 >> bit != 0 {
+                        ret.offs.push(octet * 8 + bit)
+                    }
+                }
+                pos += 1
+            }
+        }
+    }
+    Some(ret)
+}
+
+#[derive(Clone)]
+struct ChannelState {
+    program: Option<u8>,
+    ccs: [Option<u8>; 128],
+    wheel: Option<(u8, u8)>, // (lsb, msb)
+    notes: [Option<u8>; 128], // velocity of sounding notes
+    offs: u128, // notes that have ended since the checkpoint
+    off_seqs: [u16; 128], // which packet each of those ended in, when sending
+}
+impl ChannelState {
+    fn new() -> ChannelState {
+        ChannelState { program: None, ccs: [None; 128], wheel: None, notes: [None; 128], offs: 0, off_seqs: [0; 128] }
+    }
+    /// (table of contents, chapters)
+    fn chapters(&self) -> Option<(u8, Vec<u8>)> {
+        let mut toc = 0;
+        let mut ret = Vec::new();
+        if let Some(p) = self.program {
+            toc |= 0x80;
+            ret.extend([p, 0, 0])
+        }
+        let ccs: Vec<(usize, u8)> = self.ccs.iter().enumerate().filter_map(|(n, v)| Some((n, (*v)?))).collect();
+        if !ccs.is_empty() {
+            toc |= 0x40;
+            ret.push(ccs.len() as u8 - 1);
+            for (n, v) in ccs {
+                ret.extend([n as u8, v])
+            }
+        }
+        if let Some((l, m)) = self.wheel {
+            toc |= 0x10;
+            ret.extend([l, m])
+        }
+        // 127 logs with no offbits means 128 logs, so stop short of that
+        let notes: Vec<(usize, u8)> = self.notes.iter().enumerate().filter_map(|(n, v)| Some((n, (*v)?))).take(126).collect();
+        if !notes.is_empty() || self.offs != 0 {
+            toc |= 0x08;
+            let (low, high) = if self.offs == 0 {
+                (15, 0)
+            }
+            else {
+                (self.offs.trailing_zeros() as u8 / 8, (127 - self.offs.leading_zeros()) as u8 / 8)
+            };
+            ret.extend([notes.len() as u8, low << 4 | high]);
+            for (n, v) in notes {
+                ret.extend([n as u8, 0x80 | v])
+            }
+            if low <= high {
+                for octet in low..=high {
+                    let bits = (self.offs >> (octet * 8)) as u8;
+                    // the lowest note is the top bit
+                    ret.push(bits.reverse_bits())
+                }
+            }
+        }
+        if toc == 0 { None } else { Some((toc, ret)) }
+    }
+}
+
+/// what's been sent or received on each channel, for writing and acting on journals
+pub struct MidiState {
+    channels: Vec<ChannelState>,
+}
+impl MidiState {
+    pub fn new() -> MidiState {
+        MidiState { channels: vec![ChannelState::new(); 16] }
+    }
+    pub fn track(&mut self, m: &MidiMessage) {
+        let ch = match m.channel() {
+            Some(c) => &mut self.channels[c as usize - 1],
+            None => return
+        };
+        if m.is_note_on() {
+            let n = m.note().unwrap();
+            ch.notes[n as usize] = m.velocity();
+            ch.offs &= !(1 << n)
+        }
+        else if m.is_note_off() {
+            let n = m.note().unwrap();
+            if ch.notes[n as usize].take().is_some() {
+                ch.offs |= 1 << n
+            }
+        }
+        else if let Some((cc, v)) = m.as_cc() {
+            match cc {
+                // all sound off and all notes off
+                120 | 123 => {
+                    for (n, vel) in ch.notes.iter_mut().enumerate() {
+                        if vel.take().is_some() {
+                            ch.offs |= 1 << n
+                        }
+                    }
+                }
+                // the other channel mode messages would do the wrong thing if they were sent again
+                121..=127 => {}
+                _ => ch.ccs[cc as usize] = Some(v)
+            }
+        }
+        else if let Some(p) = m.as_program_change() {
+            ch.program = Some(p)
+        }
+        else if let MidiMessage::Channel([s, l, h]) = m {
+            if s & 0xf0 == 0xe0 {
+                ch.wheel = Some((*l, *h))
+            }
+        }
+    }
+    /// tracks a message going out in packet seq, so the journal can be trimmed once that packet has been received
+    pub fn track_sent(&mut self, seq: u16, m: &MidiMessage) {
+        let idx = match m.channel() {
+            Some(c) => c as usize - 1,
+            None => return
+        };
+        let before = self.channels[idx].offs;
+        self.track(m);
+        let ch = &mut self.channels[idx];
+        let mut ended = ch.offs & !before;
+        while ended != 0 {
+            ch.off_seqs[ended.trailing_zeros() as usize] = seq;
+            ended &= ended - 1
+        }
+    }
+    /// the receivers have all got everything up to and including packet seq, so notes that ended by then don't need
+    /// mentioning again
+    pub fn checkpoint(&mut self, seq: u16) {
+        for ch in &mut self.channels {
+            for n in 0..128 {
+                if ch.offs & 1 << n != 0 && seq.wrapping_sub(ch.off_seqs[n]) < 0x8000 {
+                    ch.offs &= !(1 << n)
+                }
+            }
+        }
+    }
+    pub fn journal(&self, checkpoint: u16) -> Option<Vec<u8>> {
+        let channels: Vec<(usize, (u8, Vec<u8>))> = self.channels.iter().enumerate().filter_map(|(i, c)| Some((i, c.chapters()?))).collect();
+        if channels.is_empty() {
+            return None
+        }
+        let mut ret = vec![0x20 | (channels.len() as u8 - 1)];
+        ret.extend(checkpoint.to_be_bytes());
+        for (i, (toc, chapters)) in channels {
+            let len = chapters.len() + 3;
+            ret.extend([(i as u8) << 3 | (len >> 8) as u8 & 0x03, len as u8, toc]);
+            ret.extend(chapters)
+        }
+        Some(ret)
+    }
+    /// the messages needed to get from what we've received to what a journal says, after a packet has gone missing
+    pub fn recover(&mut self, journal: &[ChannelJournal]) -> Vec<MidiMessage> {
+        let mut ret = Vec::new();
+        for j in journal {
+            let c = j.channel;
+            let ch = &self.channels[c as usize - 1];
+            // ccs first, so bank selects come before the program change
+            for (n, v) in &j.ccs {
+                if ch.ccs[*n as usize] != Some(*v) {
+                    ret.push(MidiMessage::control_change(c, *n, *v))
+                }
+            }
+            if let Some(p) = j.program {
+                if ch.program != Some(p) {
+                    ret.push(MidiMessage::program_change(c, p))
+                }
+            }
+            if let Some((l, m)) = j.wheel {
+                if ch.wheel != Some((l, m)) {
+                    ret.push(MidiMessage::pitch_bend(c, (m as u16) << 7 | l as u16))
+                }
+            }
+            for n in &j.offs {
+                if ch.notes[*n as usize].is_some() {
+                    ret.push(MidiMessage::note_off(c, *n))
+                }
+            }
+            for (n, v, play) in &j.notes {
+                if *play && *v != 0 && ch.notes[*n as usize].is_none() {
+                    ret.push(MidiMessage::note_on(c, *n, *v))
+                }
+            }
+        }
+        for m in &ret {
+            self.track(m)
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        let cmds = [
+            Command::Invite { token: 1, ssrc: 2, name: String::from("mmm") },
+            Command::Bye { token: 1, ssrc: 2 },
+            Command::Clock { ssrc: 3, count: 1, ts: [4, 5, 0] },
+            Command::Feedback { ssrc: 3, seq: 500 }
+        ];
+        for c in cmds {
+            assert_eq!(Command::parse(&c.to_bytes()), Some(c))
+        }
+    }
+
+    #[test]
+    fn packets() {
+        let msgs = vec![
+            MidiMessage::note_on(1, 60, 100), MidiMessage::SystemCommon(vec![0xf0, 1, 2, 0xf7]),
+            MidiMessage::control_change(2, 7, 3), MidiMessage::SystemRealtime(0xf8)
+        ];
+        let p = parse_packet(&encode_packet(5, 1000, 42, &msgs, None)).unwrap();
+        assert_eq!((p.seq, p.ssrc, p.messages), (5, 42, msgs));
+        // running status and a long delta time, as another implementation might send them
+        let list = [0x90, 60, 100, 0x81, 0x00, 62, 100];
+        assert_eq!(parse_command_list(&list, false), vec![MidiMessage::note_on(1, 60, 100), MidiMessage::note_on(1, 62, 100)]);
+    }
+
+    #[test]
+    fn journal_recovery() {
+        let mut sender = MidiState::new();
+        let sent = [
+            MidiMessage::control_change(3, 0, 1), MidiMessage::program_change(3, 9), MidiMessage::note_on(3, 60, 100),
+            MidiMessage::note_on(3, 72, 90), MidiMessage::pitch_bend(4, 100)
+        ];
+        let mut receiver = MidiState::new();
+        // the receiver got as far as the first note, then lost everything after
+        for m in &sent {
+            sender.track(m)
+        }
+        for m in &sent[..3] {
+            receiver.track(m)
+        }
+        sender.track(&MidiMessage::note_off(3, 60));
+
+        let packet = encode_packet(9, 0, 1, &[], sender.journal(0).as_deref());
+        let journal = parse_packet(&packet).unwrap().journal;
+        assert_eq!(journal.len(), 2);
+        assert_eq!(journal[0].offs, vec![60]);
+        assert_eq!(receiver.recover(&journal), vec![
+            MidiMessage::note_off(3, 60), MidiMessage::note_on(3, 72, 90), MidiMessage::pitch_bend(4, 100)
+        ]);
+        // nothing more to do the second time
+        assert!(receiver.recover(&journal).is_empty());
+    }
+}
@@ -1,13 +1,100 @@
 use std::sync::{Mutex, Arc};
 use std::thread;
+use std::cell::RefCell;
 use std::collections::VecDeque;
-use std::fs::read_to_string;
+use std::fs::{read_to_string, File, OpenOptions};
+use std::io::Write;
 use std::process::Command;
-use rustyline::{Editor, Helper, completion::{FilenameCompleter, Completer, Pair}, hint::Hinter, highlight::Highlighter, validate::Validator};
+use rustyline::{Editor, Helper, ExternalPrinter, error::ReadlineError, completion::{FilenameCompleter, Completer, Pair}, hint::Hinter, highlight::Highlighter, validate::Validator};
 use shell_words::split;
+use lazy_static::lazy_static;
 use crate::utils::*;
 use crate::consts::metacommands::*;
 
+/// like println and print, but through output, so what a command prints can be captured for a control client. this
+/// module is declared first so they're in scope for all the others
+macro_rules! outln {
+    () => { $crate::shell::output(String::from("\n")) };
+    ($($arg:tt)*) => { $crate::shell::output(format!("{}\n", format_args!($($arg)*))) };
+}
+macro_rules! out {
+    ($($arg:tt)*) => { $crate::shell::output(format!($($arg)*)) };
+}
+
+lazy_static! {
+    /// prints above the prompt instead of through the middle of it. None if the terminal doesn't support that
+    static ref PRINTER: Mutex<Option<Box<dyn ExternalPrinter + Send>>> = Mutex::new(None);
+    static ref LOG: Mutex<Option<Log>> = Mutex::new(None);
+}
+#[cfg(unix)]
+lazy_static! {
+    /// how the terminal was before the shell started, so it isn't left in raw mode if mmm exits mid-prompt
+    static ref TERMINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+}
+thread_local! {
+    /// Some while capture is running
+    static CAPTURED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// a copy of everything printed, a line at a time, each marked with the time since startup
+struct Log {
+    file: File,
+    partial: String
+}
+impl Log {
+    fn write(&mut self, s: &str) {
+        self.partial.push_str(s);
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            let t = timestamp();
+            let _ = write!(self.file, "[{}.{:03}] {}", t.as_secs(), t.subsec_millis(), line);
+        }
+    }
+}
+/// starts appending to a log file
+pub fn set_log(path: &str) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *LOG.lock().unwrap() = Some(Log { file, partial: String::new() });
+    Ok(())
+}
+/// writes to the log only, for things that aren't worth printing
+pub fn log(s: &str) {
+    if let Some(l) = LOG.lock().unwrap().as_mut() {
+        l.write(s)
+    }
+}
+
+/// print a line from outside the shell thread without wrecking the prompt. never captured, since it isn't the answer
+/// to a command
+pub fn print_line(line: String) {
+    log(&format!("{}\n", line));
+    let mut printer = PRINTER.lock().unwrap();
+    match printer.as_mut() {
+        Some(p) => { let _ = p.print(line + "\n"); }
+        None => println!("{}", line)
+    }
+}
+pub fn output(s: String) {
+    let uncaptured = CAPTURED.with(|c| match c.borrow_mut().as_mut() {
+        Some(buf) => {
+            buf.push_str(&s);
+            None
+        }
+        None => Some(s)
+    });
+    if let Some(s) = uncaptured {
+        log(&s);
+        print!("{}", s)
+    }
+}
+/// runs f, and returns what it printed instead of printing it
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, String) {
+    CAPTURED.with(|c| *c.borrow_mut() = Some(String::new()));
+    let ret = f();
+    let printed = CAPTURED.with(|c| c.borrow_mut().take()).unwrap_or_default();
+    (ret, printed)
+}
+
 pub struct Messenger {
     pub shell_wait: bool,
     pub exiting: bool,
@@ -63,13 +150,18 @@ pub struct Shell {
     int_buf: VecDeque<String>
 }
 impl Shell {
-    pub fn new() -> (Arc<Mutex<Messenger>>, std::thread::JoinHandle<()>) {
+    pub fn spawn() -> (Arc<Mutex<Messenger>>, std::thread::JoinHandle<()>) {
         let msgr = Arc::new(Mutex::new(Messenger::new()));
         let msgr_ret = Arc::clone(&msgr);
+        #[cfg(unix)]
+        save_terminal();
 
         let mut rl = Editor::new().unwrap();
         let h = Complete::new();
         rl.set_helper(Some(h));
+        if let Ok(p) = rl.create_external_printer() {
+            *PRINTER.lock().unwrap() = Some(Box::new(p))
+        }
 
         let mut shell = Shell {
             msgr, rl,
@@ -93,8 +185,11 @@ impl Shell {
             else {
                 std::mem::drop(msgr);
                 if self.int_buf.is_empty() {
-                    if let Ok(line) = self.rl.readline("> ") {
-                        self.do_line(line)
+                    match self.rl.readline("> ") {
+                        Ok(line) => self.do_line(line),
+                        Err(ReadlineError::Interrupted) => {}
+                        // ctrl-d, or stdin has gone away. either way there won't be any more commands
+                        Err(_) => self.int_buf.push_back(String::from("exit"))
                     }
                 }
                 else {
@@ -105,9 +200,9 @@ impl Shell {
     }
     fn do_line(&mut self, line: String) {
         if line.is_empty() { return }
-        if line.starts_with('.') {
-            if line.len() > 1 {
-                self.do_command(&line[1..])
+        if let Some(command) = line.strip_prefix('.') {
+            if !command.is_empty() {
+                self.do_command(command)
             }
         }
         else {
@@ -126,9 +221,9 @@ impl Shell {
                 }
             }
             else {
-                println!("command not found! valid metacommands are:");
+                outln!("command not found! valid metacommands are:");
                 for c in METACOMMANDS {
-                    println!("\t{}", c)
+                    outln!("\t{}", c)
                 }
             }
         }
@@ -142,28 +237,28 @@ impl Shell {
 
     fn run_mc(&mut self, args: &[String]) {
         if args.is_empty() {
-            println!("run metacommand requires at least 1 argument")
+            outln!("run metacommand requires at least 1 argument")
         }
         else {
             if let Ok(output) = Command::new(&args[0]).args(&args[1..]).output() {
-                println!("{}", String::from_utf8_lossy(&output.stderr));
+                outln!("{}", String::from_utf8_lossy(&output.stderr));
                 if let Ok(s) = std::str::from_utf8(&output.stdout) {    
                     for line in s.split('\n') {
                         self.do_line(line.into());
                     }
                 }
                 else {
-                    println!("command returned invalid utf8")
+                    outln!("command returned invalid utf8")
                 }
             }
             else {
-                println!("failed to run command!")
+                outln!("failed to run command!")
             }
         }
     }
     fn load(&mut self, args: &[String]) {
         if args.len() != 1 {
-            println!("load metacommand requires 1 argument")
+            outln!("load metacommand requires 1 argument")
         }
         else {
             if let Ok(s) = read_to_string(&args[0]) {
@@ -172,9 +267,33 @@ impl Shell {
                 }
             }
             else {
-                println!("failed to read file")
+                outln!("failed to read file")
             }
         }
     }
 }
 
+#[cfg(unix)]
+fn save_terminal() {
+    // safe because termios is plain data, only written by the call
+    unsafe {
+        let mut t: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut t) == 0 {
+            *TERMINAL.lock().unwrap() = Some(t)
+        }
+    }
+}
+/// puts the terminal back how it was before the shell started, for when mmm exits with the shell still waiting on a
+/// line. a new line is started as well, since the prompt will still be there
+#[cfg(unix)]
+pub fn restore_terminal() {
+    if let Some(t) = TERMINAL.lock().unwrap().as_ref() {
+        // safe for the same reason as save_terminal
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, t);
+        }
+        println!()
+    }
+}
+#[cfg(not(unix))]
+pub fn restore_terminal() { }
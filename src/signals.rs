@@ -0,0 +1,33 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle(signal: libc::c_int) {
+    // only atomics in here, since almost nothing else is safe in a signal handler
+    match signal {
+        libc::SIGHUP => RELOAD.store(true, Ordering::SeqCst),
+        _ => TERMINATE.store(true, Ordering::SeqCst)
+    }
+}
+
+/// SIGTERM (and SIGINT, when there's no prompt to catch ctrl-c) asks for a shutdown, and SIGHUP for a reload. the main
+/// loop picks them up with terminate_requested and reload_requested
+pub fn install(catch_interrupt: bool) {
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // safe because the handler only touches atomics
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGHUP, handler);
+        if catch_interrupt {
+            libc::signal(libc::SIGINT, handler);
+        }
+    }
+}
+pub fn terminate_requested() -> bool {
+    TERMINATE.load(Ordering::SeqCst)
+}
+/// true once per SIGHUP, however many arrived since the last call
+pub fn reload_requested() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,302 @@
+use crate::utils::*;
+use std::time::Duration;
+
+pub const DEFAULT_PPQ: u16 = 480;
+/// microseconds per quarter note, so 120bpm
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+/// events are (time since the start, message), and have to be in order. realtime and most system common messages
+/// can't go in a file, so they're left out. format 1 files get a tempo track followed by one track per channel
+pub fn write_smf(events: &[(Duration, MidiMessage)], format: u16) -> Vec<u8> {
+    let ticks: Vec<(u32, Vec<u8>, Option<u8>)> = events.iter().filter_map(|(t, m)| {
+        let tick = (t.as_micros() * DEFAULT_PPQ as u128 / DEFAULT_TEMPO as u128) as u32;
+        event_bytes(m).map(|b| (tick, b, m.channel()))
+    }).collect();
+    let tempo = (0, vec![0xff, 0x51, 0x03, (DEFAULT_TEMPO >> 16) as u8, (DEFAULT_TEMPO >> 8) as u8, DEFAULT_TEMPO as u8]);
+
+    let mut tracks = Vec::new();
+    if format == 0 {
+        let mut track = vec![tempo];
+        track.extend(ticks.into_iter().map(|(t, b, _)| (t, b)));
+        tracks.push(track)
+    }
+    else {
+        // sysex goes in with the tempo
+        let mut conductor = vec![tempo];
+        conductor.extend(ticks.iter().filter(|(_, _, c)| c.is_none()).map(|(t, b, _)| (*t, b.clone())));
+        tracks.push(conductor);
+        for channel in 1..=16 {
+            let track: Vec<(u32, Vec<u8>)> = ticks.iter().filter(|(_, _, c)| *c == Some(channel)).map(|(t, b, _)| (*t, b.clone())).collect();
+            if !track.is_empty() {
+                tracks.push(track)
+            }
+        }
+    }
+
+    let mut header = Vec::new();
+    header.extend(format.to_be_bytes());
+    header.extend((tracks.len() as u16).to_be_bytes());
+    header.extend(DEFAULT_PPQ.to_be_bytes());
+
+    let mut ret = chunk(b"MThd", &header);
+    for t in tracks {
+        ret.extend(track_chunk(&t))
+    }
+    ret
+}
+
+pub struct Smf {
+    pub ppq: u16,
+    /// (tick, message) for every track, merged and in order
+    pub events: Vec<(u64, MidiMessage)>,
+    /// (tick, microseconds per quarter note), in order. there's always one at tick 0
+    pub tempos: Vec<(u64, u32)>,
+    /// tick of the last end of track
+    pub length: u64,
+}
+impl Smf {
+    pub fn tempo_at(&self, tick: u64) -> u32 {
+        let idx = self.tempos.partition_point(|(t, _)| *t <= tick);
+        self.tempos[idx.max(1) - 1].1
+    }
+}
+
+/// formats 0 and 1 only, and no smpte timing
+pub fn read_smf(bytes: &[u8]) -> Result<Smf> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.take(4)? != b"MThd" || r.u32()? < 6 {
+        return Err(MMMErr::InvalidFile)
+    }
+    let format = r.u16()?;
+    let ntracks = r.u16()?;
+    let ppq = r.u16()?;
+    if format > 1 || ppq & 0x8000 != 0 || ppq == 0 {
+        return Err(MMMErr::InvalidFile)
+    }
+    r.pos = 8 + r.bytes[4..8].iter().fold(0, |acc, b| acc << 8 | *b as usize);
+
+    let mut smf = Smf { ppq, events: Vec::new(), tempos: Vec::new(), length: 0 };
+    for _ in 0..ntracks {
+        // skip anything that isn't a track
+        while r.take(4)? != b"MTrk" {
+            let len = r.u32()? as usize;
+            r.take(len)?;
+        }
+        let len = r.u32()? as usize;
+        let mut t = Reader { bytes: r.take(len)?, pos: 0 };
+        read_track(&mut t, &mut smf)?
+    }
+    // stable, so events at the same tick stay in track order
+    smf.events.sort_by_key(|(t, _)| *t);
+    smf.tempos.sort_by_key(|(t, _)| *t);
+    if smf.tempos.first().map(|(t, _)| *t != 0).unwrap_or(true) {
+        smf.tempos.insert(0, (0, DEFAULT_TEMPO))
+    }
+    Ok(smf)
+}
+fn read_track(r: &mut Reader, smf: &mut Smf) -> Result<()> {
+    let mut tick = 0;
+    let mut status = None;
+    while r.pos < r.bytes.len() {
+        tick += r.varlen()? as u64;
+        let mut first = r.u8()?;
+        match first {
+            0xff => {
+                let kind = r.u8()?;
+                let len = r.varlen()? as usize;
+                let data = r.take(len)?;
+                match kind {
+                    0x51 if len == 3 => smf.tempos.push((tick, (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32)),
+                    0x2f => break,
+                    _ => {}
+                }
+            }
+            0xf0 | 0xf7 => {
+                let len = r.varlen()? as usize;
+                let data = r.take(len)?;
+                let mut v = Vec::new();
+                if first == 0xf0 {
+                    v.push(0xf0)
+                }
+                v.extend(data);
+                // f7 is an escape for arbitrary bytes
+                if let Some(m) = v.first().and_then(|_| MidiMessage::from_slice(0, &v)) {
+                    smf.events.push((tick, m))
+                }
+            }
+            _ => {
+                if first < 0x80 {
+                    // running status
+                    r.pos -= 1;
+                    first = status.ok_or(MMMErr::InvalidFile)?
+                }
+                else {
+                    status = Some(first)
+                }
+                let len = if first & 0xe0 == 0xc0 { 1 } else { 2 };
+                let mut v = vec![first];
+                v.extend(r.take(len)?);
+                smf.events.push((tick, MidiMessage::from_slice(0, &v).ok_or(MMMErr::InvalidFile)?))
+            }
+        }
+    }
+    smf.length = smf.length.max(tick);
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let ret = self.bytes.get(self.pos..self.pos + len).ok_or(MMMErr::InvalidFile)?;
+        self.pos += len;
+        Ok(ret)
+    }
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn varlen(&mut self) -> Result<u32> {
+        let mut ret = 0;
+        for _ in 0..4 {
+            let b = self.u8()?;
+            ret = ret << 7 | (b & 0x7f) as u32;
+            if b & 0x80 == 0 {
+                return Ok(ret)
+            }
+        }
+        Err(MMMErr::InvalidFile)
+    }
+}
+
+fn event_bytes(m: &MidiMessage) -> Option<Vec<u8>> {
+    match m {
+        MidiMessage::Channel(_) | MidiMessage::ChannelSmall(_) => Some(m.to_bytes()),
+        MidiMessage::SystemCommon(v) if v[0] == 0xf0 => {
+            let mut ret = vec![0xf0];
+            write_varlen(&mut ret, v.len() as u32 - 1);
+            ret.extend(&v[1..]);
+            Some(ret)
+        }
+        _ => None
+    }
+}
+fn track_chunk(events: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut last = 0;
+    for (tick, bytes) in events {
+        write_varlen(&mut data, tick - last);
+        data.extend(bytes);
+        last = *tick
+    }
+    data.extend([0x00, 0xff, 0x2f, 0x00]); // end of track
+    chunk(b"MTrk", &data)
+}
+fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut ret = kind.to_vec();
+    ret.extend((data.len() as u32).to_be_bytes());
+    ret.extend(data);
+    ret
+}
+fn write_varlen(buf: &mut Vec<u8>, mut val: u32) {
+    let mut bytes = vec![(val & 0x7f) as u8];
+    val >>= 7;
+    while val > 0 {
+        bytes.push((val & 0x7f) as u8 | 0x80);
+        val >>= 7
+    }
+    buf.extend(bytes.iter().rev())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varlen() {
+        let enc = |v| { let mut b = Vec::new(); write_varlen(&mut b, v); b };
+        assert_eq!(enc(0), vec![0]);
+        assert_eq!(enc(0x7f), vec![0x7f]);
+        assert_eq!(enc(0x80), vec![0x81, 0x00]);
+        assert_eq!(enc(0x0fff_ffff), vec![0xff, 0xff, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn format_0() {
+        let events = vec![
+            (Duration::ZERO, MidiMessage::note_on(1, 60, 100)),
+            (Duration::from_millis(500), MidiMessage::note_off(1, 60)),
+            (Duration::from_millis(500), MidiMessage::SystemRealtime(0xf8)),
+        ];
+        let f = write_smf(&events, 0);
+        assert_eq!(&f[..14], &[b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0x01, 0xe0]);
+        assert_eq!(&f[14..18], b"MTrk");
+        // tempo, note on, note off half a second (one beat, 480 ticks) later, end of track
+        assert_eq!(&f[22..], &[
+            0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20,
+            0x00, 0x90, 60, 100,
+            0x83, 0x60, 0x80, 60, 0,
+            0x00, 0xff, 0x2f, 0x00
+        ]);
+    }
+
+    #[test]
+    fn format_1_splits_channels() {
+        let events = vec![
+            (Duration::ZERO, MidiMessage::note_on(1, 60, 100)),
+            (Duration::ZERO, MidiMessage::note_on(3, 60, 100)),
+        ];
+        let f = write_smf(&events, 1);
+        assert_eq!(&f[8..12], &[0, 1, 0, 3]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let events = vec![
+            (Duration::ZERO, MidiMessage::note_on(1, 60, 100)),
+            (Duration::from_millis(250), MidiMessage::SystemCommon(vec![0xf0, 1, 2, 0xf7])),
+            (Duration::from_millis(500), MidiMessage::note_off(1, 60)),
+            (Duration::from_millis(1000), MidiMessage::program_change(2, 5)),
+        ];
+        for format in 0..=1 {
+            let smf = read_smf(&write_smf(&events, format)).unwrap();
+            assert_eq!(smf.ppq, DEFAULT_PPQ);
+            assert_eq!(smf.tempo_at(1000), DEFAULT_TEMPO);
+            assert_eq!(smf.length, 960);
+            let got: Vec<(u64, Vec<u8>)> = smf.events.iter().map(|(t, m)| (*t, m.to_bytes())).collect();
+            assert_eq!(got, vec![
+                (0, vec![0x90, 60, 100]),
+                (240, vec![0xf0, 1, 2, 0xf7]),
+                (480, vec![0x80, 60, 0]),
+                (960, vec![0xc1, 5]),
+            ]);
+        }
+    }
+
+    #[test]
+    fn running_status_and_tempo_map() {
+        let track = [
+            0x00, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40, // 60bpm
+            0x00, 0x90, 60, 100,
+            0x10, 64, 100, // running status
+            0x10, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20, // 120bpm
+            0x00, 0xff, 0x2f, 0x00
+        ];
+        let mut f = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk".to_vec();
+        f.extend((track.len() as u32).to_be_bytes());
+        f.extend(track);
+        let smf = read_smf(&f).unwrap();
+        assert_eq!(smf.events[1].0, 16);
+        assert_eq!(smf.events[1].1.to_bytes(), vec![0x90, 64, 100]);
+        assert_eq!(smf.tempo_at(31), 1_000_000);
+        assert_eq!(smf.tempo_at(32), 500_000);
+        assert!(read_smf(&f[..30]).is_err());
+    }
+}
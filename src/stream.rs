@@ -0,0 +1,148 @@
+use crate::utils::*;
+
+/// turns a raw byte stream into messages, for sources that don't split it up for us. handles running status, realtime
+/// bytes in the middle of other messages and sysex spread over any number of chunks. data bytes with no status to go
+/// with them are thrown away, as are sysex messages that get cut off by another status byte
+#[derive(Default)]
+pub struct StreamParser {
+    running: Option<u8>,
+    data: Vec<u8>, // the message so far, starting with its status
+    sysex: Option<Vec<u8>>,
+}
+impl StreamParser {
+    /// how much of an unfinished sysex is being held onto
+    pub fn pending(&self) -> usize {
+        self.sysex.as_ref().map_or(0, |s| s.len())
+    }
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<MidiMessage> {
+        bytes.iter().filter_map(|b| self.push(*b)).collect()
+    }
+    pub fn push(&mut self, b: u8) -> Option<MidiMessage> {
+        match b {
+            // realtime can turn up anywhere, and doesn't affect anything else
+            0xf8..=0xff => Some(MidiMessage::SystemRealtime(b)),
+            0xf0 => {
+                self.running = None;
+                self.data.clear();
+                self.sysex = Some(vec![0xf0]);
+                None
+            }
+            0xf7 => {
+                let mut sysex = self.sysex.take()?;
+                sysex.push(0xf7);
+                Some(MidiMessage::SystemCommon(sysex))
+            }
+            0x80..=0xf6 => {
+                self.sysex = None;
+                // system common cancels running status
+                self.running = if b < 0xf0 { Some(b) } else { None };
+                self.data = vec![b];
+                self.complete()
+            }
+            _ => {
+                if let Some(sysex) = &mut self.sysex {
+                    sysex.push(b);
+                    return None
+                }
+                if self.data.is_empty() {
+                    // carrying on with the last status
+                    self.data.push(self.running?)
+                }
+                self.data.push(b);
+                self.complete()
+            }
+        }
+    }
+    fn complete(&mut self) -> Option<MidiMessage> {
+        let status = self.data[0];
+        if self.data.len() < message_len(status) {
+            return None
+        }
+        let data = std::mem::take(&mut self.data);
+        match status {
+            // undefined
+            0xf4 | 0xf5 => None,
+            _ => MidiMessage::from_slice(0, &data)
+        }
+    }
+}
+
+/// turns messages back into a byte stream, optionally leaving out repeated status bytes
+pub struct StreamEncoder {
+    running_status: bool,
+    last: Option<u8>,
+}
+impl StreamEncoder {
+    pub fn new(running_status: bool) -> StreamEncoder {
+        StreamEncoder { running_status, last: None }
+    }
+    pub fn encode(&mut self, m: &MidiMessage) -> Vec<u8> {
+        let mut bytes = m.to_bytes();
+        match m {
+            MidiMessage::Channel([s, ..]) | MidiMessage::ChannelSmall([s, ..]) => {
+                if self.running_status && self.last == Some(*s) {
+                    bytes.remove(0);
+                }
+                self.last = Some(*s)
+            }
+            MidiMessage::SystemCommon(_) => self.last = None,
+            MidiMessage::SystemRealtime(_) => {}
+        }
+        bytes
+    }
+    /// the next message will have its status byte, whatever it is. for after a gap, so a receiver that's only just
+    /// started listening can pick up where we are
+    pub fn reset(&mut self) {
+        self.last = None
+    }
+}
+
+/// including the status byte. sysex is counted as 1, since its length isn't fixed
+pub fn message_len(status: u8) -> usize {
+    match status {
+        0x80..=0xbf | 0xe0..=0xef | 0xf2 => 3,
+        0xc0..=0xdf | 0xf1 | 0xf3 => 2,
+        _ => 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(msgs: Vec<MidiMessage>) -> Vec<Vec<u8>> {
+        msgs.iter().map(|m| m.to_bytes()).collect()
+    }
+
+    #[test]
+    fn running_status_and_realtime() {
+        let mut p = StreamParser::default();
+        // junk first, then a clock in the middle of a note
+        let msgs = p.feed(&[0x12, 0x90, 60, 0xf8, 100, 62, 0, 0xc1, 5, 6]);
+        assert_eq!(bytes(msgs), vec![vec![0xf8], vec![0x90, 60, 100], vec![0x90, 62, 0], vec![0xc1, 5], vec![0xc1, 6]]);
+        // system common ends running status, so the data bytes after it are junk
+        assert_eq!(bytes(p.feed(&[0xf3, 1, 2, 3, 0xf6])), vec![vec![0xf3, 1], vec![0xf6]]);
+    }
+
+    #[test]
+    fn sysex_in_chunks() {
+        let mut p = StreamParser::default();
+        assert!(p.feed(&[0xf0, 1, 2]).is_empty());
+        assert_eq!(bytes(p.feed(&[3, 0xfe, 4])), vec![vec![0xfe]]);
+        assert_eq!(bytes(p.feed(&[0xf7, 0xf7])), vec![vec![0xf0, 1, 2, 3, 4, 0xf7]]);
+        // cut off by a note
+        assert_eq!(bytes(p.feed(&[0xf0, 1, 0x80, 60, 0])), vec![vec![0x80, 60, 0]]);
+    }
+
+    #[test]
+    fn encoder() {
+        let msgs = [
+            MidiMessage::note_on(1, 60, 100), MidiMessage::SystemRealtime(0xf8), MidiMessage::note_on(1, 62, 100),
+            MidiMessage::SystemCommon(vec![0xf0, 1, 0xf7]), MidiMessage::note_on(1, 64, 100)
+        ];
+        let mut e = StreamEncoder::new(true);
+        let stream: Vec<u8> = msgs.iter().flat_map(|m| e.encode(m)).collect();
+        assert_eq!(stream, vec![0x90, 60, 100, 0xf8, 62, 100, 0xf0, 1, 0xf7, 0x90, 64, 100]);
+        assert_eq!(StreamParser::default().feed(&stream), msgs);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::utils::*;
+use crate::consts::message_kinds::*;
+use crate::stream::StreamParser;
+
+/// one message per line, like `note_on ch=1 n=60 v=100`. the fields are `ch` (channel), `n` (note), `v` (velocity,
+/// pressure or value), `c` (controller) and `p` (program). pitch bend goes from -8192 to 8191, and anything else is
+/// written as `raw` and its bytes in hex
+pub fn to_text(m: &MidiMessage) -> String {
+    match m {
+        MidiMessage::Channel([s, a, b]) if s & 0xf0 != 0xf0 => {
+            let c = (s & 0xf) + 1;
+            match s & 0xf0 {
+                0x80 => format!("{} ch={} n={} v={}", MESSAGE_KINDS[IDX_NOTE_OFF], c, a, b),
+                0x90 => format!("{} ch={} n={} v={}", MESSAGE_KINDS[IDX_NOTE_ON], c, a, b),
+                0xa0 => format!("{} ch={} n={} v={}", MESSAGE_KINDS[IDX_POLY_PRESSURE], c, a, b),
+                0xb0 => format!("{} ch={} c={} v={}", MESSAGE_KINDS[IDX_CC], c, a, b),
+                _ => format!("{} ch={} v={}", MESSAGE_KINDS[IDX_PITCH_BEND], c, ((*b as i32) << 7 | *a as i32) - 8192)
+            }
+        }
+        MidiMessage::ChannelSmall([s, a]) if s & 0xf0 == 0xc0 => format!("{} ch={} p={}", MESSAGE_KINDS[IDX_PROGRAM], (s & 0xf) + 1, a),
+        MidiMessage::ChannelSmall([s, a]) if s & 0xf0 == 0xd0 => format!("{} ch={} v={}", MESSAGE_KINDS[IDX_CHANNEL_PRESSURE], (s & 0xf) + 1, a),
+        _ => {
+            let bytes: Vec<String> = m.to_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{} {}", MESSAGE_KINDS[IDX_RAW], bytes.join(" "))
+        }
+    }
+}
+/// the opposite of to_text. the kind can be shortened, fields can be in any order, and notes can be names like `C#4`.
+/// note_off can leave out its velocity
+pub fn from_text(line: &str) -> Option<MidiMessage> {
+    let mut words = line.split_whitespace();
+    let kind = shortened_keyword_match(words.next()?, MESSAGE_KINDS)?;
+    if kind == IDX_RAW {
+        let bytes = words.map(|w| u8::from_str_radix(w, 16).ok()).collect::<Option<Vec<_>>>()?;
+        // has to be exactly one complete message
+        let mut parser = StreamParser::default();
+        let (last, rest) = bytes.split_last()?;
+        if !parser.feed(rest).is_empty() {
+            return None
+        }
+        return parser.push(*last)
+    }
+
+    let mut fields = Vec::new();
+    for w in words {
+        fields.push(w.split_once('=')?)
+    }
+    let field = |name: &str| fields.iter().find(|(k, _)| *k == name).map(|(_, v)| *v);
+    let value = |name: &str| field(name)?.parse().ok().filter(|v: &u8| *v < 128);
+    let note = || field("n").and_then(|n| n.parse().ok().filter(|n: &u8| *n < 128).or_else(|| parse_note(n)));
+    let c = field("ch")?.parse().ok().filter(|c| (1..=16).contains(c))?;
+    Some(match kind {
+        IDX_NOTE_ON => MidiMessage::note_on(c, note()?, value("v")?),
+        IDX_NOTE_OFF => MidiMessage::note_off(c, note()?).with_velocity(value("v").unwrap_or(0)),
+        IDX_POLY_PRESSURE => MidiMessage::poly_pressure(c, note()?, value("v")?),
+        IDX_CC => MidiMessage::control_change(c, value("c")?, value("v")?),
+        IDX_PROGRAM => MidiMessage::program_change(c, value("p")?),
+        IDX_CHANNEL_PRESSURE => MidiMessage::channel_pressure(c, value("v")?),
+        IDX_PITCH_BEND => {
+            let v: i32 = field("v")?.parse().ok().filter(|v| (-8192..8192).contains(v))?;
+            MidiMessage::pitch_bend(c, (v + 8192) as u16)
+        }
+        _ => unreachable!()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let msgs = [
+            MidiMessage::note_on(2, 60, 100), MidiMessage::note_off(2, 60), MidiMessage::poly_pressure(1, 3, 4),
+            MidiMessage::control_change(16, 74, 0), MidiMessage::program_change(1, 5), MidiMessage::channel_pressure(1, 9),
+            MidiMessage::pitch_bend(1, 16383), MidiMessage::SystemRealtime(0xf8), MidiMessage::SystemCommon(vec![0xf0, 1, 0xf7])
+        ];
+        for m in msgs {
+            assert_eq!(from_text(&to_text(&m)), Some(m))
+        }
+        assert_eq!(to_text(&MidiMessage::note_on(1, 60, 100)), "note_on ch=1 n=60 v=100");
+        assert_eq!(to_text(&MidiMessage::SystemCommon(vec![0xf0, 0x7e, 0xf7])), "raw f0 7e f7");
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(from_text("note_on v=100 ch=1 n=C4"), Some(MidiMessage::note_on(1, 60, 100)));
+        assert_eq!(from_text("pitch ch=3 v=-8192"), Some(MidiMessage::pitch_bend(3, 0)));
+        assert_eq!(from_text("note_off ch=1 n=60 v=64"), Some(MidiMessage::note_off(1, 60).with_velocity(64)));
+        for bad in ["", "note ch=1 n=60 v=1", "cc ch=0 c=1 v=1", "cc ch=1 c=1", "cc ch=1 c=1 v=128", "raw 90 3c", "raw f8 f8", "program ch=1 5"] {
+            assert_eq!(from_text(bad), None, "{}", bad)
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use crate::utils::*;
+use crate::params::{Param, ParamKind};
+use crate::smf::{Smf, DEFAULT_PPQ, DEFAULT_TEMPO};
+use std::time::Duration;
+
+const CLIP_MAGIC: &[u8] = b"SMF2CLIP";
+const START_OF_CLIP: u32 = 0x20;
+const END_OF_CLIP: u32 = 0x21;
+
+impl MidiMessage {
+    /// turns a message into a universal midi packet. channel voice messages use the midi 2.0 protocol if midi2 is set,
+    /// with values scaled up the way the spec says to. sysex can take more than one packet
+    pub fn to_ump(&self, group: u8, midi2: bool) -> Vec<u32> {
+        let g = (group as u32 & 0xf) << 24;
+        match self {
+            MidiMessage::Channel(_) | MidiMessage::ChannelSmall(_) if !midi2 => {
+                let b = self.to_bytes();
+                vec![0x2 << 28 | g | (b[0] as u32) << 16 | (b[1] as u32) << 8 | *b.get(2).unwrap_or(&0) as u32]
+            }
+            MidiMessage::Channel([s, a, b]) => {
+                let (s, a, b) = (*s as u32, *a as u32, *b as u32);
+                let w0 = 0x4 << 28 | g | s << 16 | a << 8;
+                match s & 0xf0 {
+                    // a note on with velocity 0 is a real note on in midi 2.0, so it has to become a note off
+                    0x90 if b == 0 => vec![w0 & !(0x10 << 16), 0],
+                    0x80 | 0x90 => vec![w0, scale_up(b, 7, 16) << 16],
+                    0xe0 => vec![w0 & !0xff00, scale_up(b << 7 | a, 14, 32)],
+                    _ => vec![w0, scale_up(b, 7, 32)]
+                }
+            }
+            MidiMessage::ChannelSmall([s, a]) => {
+                let w0 = 0x4 << 28 | g | (*s as u32) << 16;
+                match s & 0xf0 {
+                    0xc0 => vec![w0, (*a as u32) << 24],
+                    _ => vec![w0, scale_up(*a as u32, 7, 32)]
+                }
+            }
+            MidiMessage::SystemCommon(v) if v[0] == 0xf0 => {
+                let data = v[1..].strip_suffix(&[0xf7]).unwrap_or(&v[1..]);
+                let chunks: Vec<&[u8]> = if data.is_empty() { vec![&[]] } else { data.chunks(6).collect() };
+                let mut ret = Vec::new();
+                for (i, chunk) in chunks.iter().enumerate() {
+                    let status = match (i == 0, i == chunks.len() - 1) {
+                        (true, true) => 0,
+                        (true, false) => 1,
+                        (false, false) => 2,
+                        (false, true) => 3
+                    };
+                    let mut b = [0u32; 6];
+                    for (dst, src) in b.iter_mut().zip(chunk.iter()) {
+                        *dst = *src as u32 & 0x7f
+                    }
+                    ret.push(0x3 << 28 | g | status << 20 | (chunk.len() as u32) << 16 | b[0] << 8 | b[1]);
+                    ret.push(b[2] << 24 | b[3] << 16 | b[4] << 8 | b[5])
+                }
+                ret
+            }
+            MidiMessage::SystemCommon(v) => {
+                let byte = |i: usize| *v.get(i).unwrap_or(&0) as u32;
+                vec![0x1 << 28 | g | byte(0) << 16 | byte(1) << 8 | byte(2)]
+            }
+            MidiMessage::SystemRealtime(b) => vec![0x1 << 28 | g | (*b as u32) << 16]
+        }
+    }
+    /// turns any number of whole universal midi packets back into messages, the way UmpReader does
+    #[allow(dead_code)]
+    pub fn from_ump(words: &[u32]) -> Vec<MidiMessage> {
+        let mut reader = UmpReader::default();
+        let mut ret = Vec::new();
+        let mut pos = 0;
+        while pos < words.len() {
+            let len = packet_len(words[pos]);
+            match words.get(pos..pos + len) {
+                Some(packet) => ret.extend(reader.feed(packet)),
+                None => break
+            }
+            pos += len
+        }
+        ret
+    }
+}
+
+/// number of 32 bit words in a packet, going by its message type
+pub fn packet_len(word: u32) -> usize {
+    match word >> 28 {
+        0x0..=0x2 | 0x6 | 0x7 => 1,
+        0x3 | 0x4 | 0x8..=0xa => 2,
+        0xb | 0xc => 3,
+        _ => 4
+    }
+}
+
+/// turns universal midi packets back into midi 1.0 messages, in either protocol. groups are ignored, and anything
+/// without a midi 1.0 equivalent (per-note controllers, flex data, mixed data sets etc) is dropped. 8 bit sysex is
+/// kept if every byte would fit in 7 bits
+#[derive(Default)]
+pub struct UmpReader {
+    sysex: Vec<u8>,
+    sysex8: Option<Vec<u8>>, // None if there's no 8 bit sysex going, or it's been given up on
+}
+impl UmpReader {
+    /// takes one whole packet
+    pub fn feed(&mut self, packet: &[u32]) -> Vec<MidiMessage> {
+        let w0 = packet[0];
+        let w1 = *packet.get(1).unwrap_or(&0);
+        let s = (w0 >> 16) as u8;
+        let (a, b) = ((w0 >> 8) as u8 & 0x7f, w0 as u8 & 0x7f);
+        match w0 >> 28 {
+            0x1 => match s {
+                0xf8..=0xff => vec![MidiMessage::SystemRealtime(s)],
+                0xf1 | 0xf3 => vec![MidiMessage::SystemCommon(vec![s, a])],
+                0xf2 => vec![MidiMessage::SystemCommon(vec![s, a, b])],
+                _ => vec![MidiMessage::SystemCommon(vec![s])]
+            }
+            0x2 => match s & 0xf0 {
+                0x80..=0xb0 | 0xe0 => vec![MidiMessage::Channel([s, a, b])],
+                0xc0 | 0xd0 => vec![MidiMessage::ChannelSmall([s, a])],
+                _ => Vec::new()
+            }
+            0x3 => {
+                let bytes = [w0 >> 8, w0, w1 >> 24, w1 >> 16, w1 >> 8, w1].map(|b| b as u8 & 0x7f);
+                let data = &bytes[..((w0 >> 16) as usize & 0xf).min(6)];
+                match (w0 >> 20) & 0xf {
+                    0 | 1 => {
+                        self.sysex = vec![0xf0];
+                        self.sysex.extend_from_slice(data)
+                    }
+                    _ => self.sysex.extend_from_slice(data)
+                }
+                if matches!((w0 >> 20) & 0xf, 0 | 3) {
+                    self.sysex.push(0xf7);
+                    vec![MidiMessage::SystemCommon(std::mem::take(&mut self.sysex))]
+                }
+                else {
+                    Vec::new()
+                }
+            }
+            0x5 => match (w0 >> 20) & 0xf {
+                status @ 0..=3 => {
+                    // a stream id, then up to 13 bytes
+                    let len = ((w0 >> 16) as usize & 0xf).saturating_sub(1);
+                    let data = packet.iter().flat_map(|w| w.to_be_bytes()).skip(3).take(len);
+                    if matches!(status, 0 | 1) {
+                        self.sysex8 = Some(vec![0xf0])
+                    }
+                    if let Some(sysex) = &mut self.sysex8 {
+                        sysex.extend(data)
+                    }
+                    if !matches!(status, 0 | 3) {
+                        return Vec::new()
+                    }
+                    match self.sysex8.take() {
+                        Some(mut sysex) if sysex[1..].iter().all(|b| *b < 0x80) => {
+                            sysex.push(0xf7);
+                            vec![MidiMessage::SystemCommon(sysex)]
+                        }
+                        _ => Vec::new()
+                    }
+                }
+                // mixed data set header or payload
+                _ => Vec::new()
+            }
+            0x4 => {
+                let c = (s & 0xf) + 1;
+                match s & 0xf0 {
+                    0x80 => vec![MidiMessage::Channel([s, a, scale_down(w1 >> 16, 16, 7) as u8])],
+                    // midi 1.0 can't have a note on with velocity 0
+                    0x90 => vec![MidiMessage::Channel([s, a, scale_down(w1 >> 16, 16, 7).max(1) as u8])],
+                    0xa0 | 0xb0 => vec![MidiMessage::Channel([s, a, scale_down(w1, 32, 7) as u8])],
+                    0xc0 => {
+                        let mut ret = Vec::new();
+                        if w0 & 1 != 0 {
+                            // bank is valid
+                            ret.push(MidiMessage::control_change(c, 0, (w1 >> 8) as u8 & 0x7f));
+                            ret.push(MidiMessage::control_change(c, 32, w1 as u8 & 0x7f))
+                        }
+                        ret.push(MidiMessage::program_change(c, (w1 >> 24) as u8 & 0x7f));
+                        ret
+                    }
+                    0xd0 => vec![MidiMessage::ChannelSmall([s, scale_down(w1, 32, 7) as u8])],
+                    0xe0 => vec![MidiMessage::pitch_bend(c, scale_down(w1, 32, 14) as u16)],
+                    0x20 | 0x30 => {
+                        let kind = if s & 0xf0 == 0x20 { ParamKind::Rpn } else { ParamKind::Nrpn };
+                        Param { channel: c, kind, number: (a as u16) << 7 | b as u16, value: scale_down(w1, 32, 14) as u16 }.to_messages()
+                    }
+                    _ => Vec::new()
+                }
+            }
+            _ => Vec::new()
+        }
+    }
+}
+
+/// the min-centre-max scaling from the midi 2.0 spec, so the centre stays the centre and the top stays the top
+pub fn scale_up(value: u32, src_bits: u32, dst_bits: u32) -> u32 {
+    let scale_bits = dst_bits - src_bits;
+    let shifted = value << scale_bits;
+    if value <= 1 << (src_bits - 1) {
+        return shifted
+    }
+    // fill the bottom bits with repeats of everything below the top bit
+    let repeat_bits = src_bits - 1;
+    let mut repeat = value & ((1 << repeat_bits) - 1);
+    repeat = if scale_bits > repeat_bits { repeat << (scale_bits - repeat_bits) } else { repeat >> (repeat_bits - scale_bits) };
+    let mut ret = shifted;
+    while repeat != 0 {
+        ret |= repeat;
+        repeat >>= repeat_bits
+    }
+    ret
+}
+pub fn scale_down(value: u32, src_bits: u32, dst_bits: u32) -> u32 {
+    value >> (src_bits - dst_bits)
+}
+
+/// a midi clip file, which is universal midi packets with delta clockstamps between them. events are the same as for
+/// write_smf, and channel voice messages are written using the midi 2.0 protocol
+pub fn write_clip(events: &[(Duration, MidiMessage)]) -> Vec<u8> {
+    let mut words = vec![0x0030_0000 | DEFAULT_PPQ as u32, delta_clockstamp(0)];
+    words.extend(stream_message(START_OF_CLIP));
+    let mut last = 0;
+    for (t, m) in events {
+        let tick = (t.as_micros() * DEFAULT_PPQ as u128 / DEFAULT_TEMPO as u128) as u32;
+        let mut delta = tick - last;
+        while delta > 0xfffff {
+            words.push(delta_clockstamp(0xfffff));
+            delta -= 0xfffff
+        }
+        words.push(delta_clockstamp(delta));
+        words.extend(m.to_ump(0, true));
+        last = tick
+    }
+    words.push(delta_clockstamp(0));
+    words.extend(stream_message(END_OF_CLIP));
+
+    let mut ret = CLIP_MAGIC.to_vec();
+    for w in words {
+        ret.extend(w.to_be_bytes())
+    }
+    ret
+}
+pub fn is_clip(bytes: &[u8]) -> bool {
+    bytes.starts_with(CLIP_MAGIC)
+}
+/// tempo changes aren't supported, so it always plays at 120bpm
+#[allow(clippy::manual_is_multiple_of)] // is_multiple_of is too new for older compilers
+pub fn read_clip(bytes: &[u8]) -> Result<Smf> {
+    if !is_clip(bytes) || (bytes.len() - CLIP_MAGIC.len()) % 4 != 0 {
+        return Err(MMMErr::InvalidFile)
+    }
+    let words: Vec<u32> = bytes[CLIP_MAGIC.len()..].chunks(4).map(|c| u32::from_be_bytes(c.try_into().unwrap())).collect();
+    let mut smf = Smf { ppq: DEFAULT_PPQ, events: Vec::new(), tempos: vec![(0, DEFAULT_TEMPO)], length: 0 };
+    let mut reader = UmpReader::default();
+    let mut tick = 0;
+    let mut pos = 0;
+    while pos < words.len() {
+        let len = packet_len(words[pos]);
+        let packet = words.get(pos..pos + len).ok_or(MMMErr::InvalidFile)?;
+        let w0 = packet[0];
+        match w0 >> 28 {
+            0x0 => match (w0 >> 20) & 0xf {
+                0x3 if w0 & 0xffff != 0 => smf.ppq = w0 as u16,
+                0x3 => return Err(MMMErr::InvalidFile),
+                0x4 => tick += (w0 & 0xfffff) as u64,
+                _ => {}
+            }
+            0xf if (w0 >> 16) & 0x3ff == END_OF_CLIP => break,
+            _ => smf.events.extend(reader.feed(packet).into_iter().map(|m| (tick, m)))
+        }
+        pos += len
+    }
+    smf.length = tick;
+    Ok(smf)
+}
+
+fn delta_clockstamp(ticks: u32) -> u32 {
+    0x0040_0000 | ticks
+}
+/// 128 bit ump stream message with no data
+fn stream_message(status: u32) -> [u32; 4] {
+    [0xf << 28 | status << 16, 0, 0, 0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(m: MidiMessage, midi2: bool) -> Vec<MidiMessage> {
+        MidiMessage::from_ump(&m.to_ump(0, midi2))
+    }
+    /// a 128 bit data packet, with the stream id as 0
+    fn data128(status: u32, data: &[u8]) -> Vec<u32> {
+        let mut b = vec![0x50, (status << 4) as u8 | (data.len() as u8 + 1), 0];
+        b.extend(data);
+        b.resize(16, 0);
+        b.chunks(4).map(|c| u32::from_be_bytes(c.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn scaling() {
+        assert_eq!(scale_up(0, 7, 16), 0);
+        assert_eq!(scale_up(64, 7, 16), 0x8000);
+        assert_eq!(scale_up(127, 7, 16), 0xffff);
+        assert_eq!(scale_up(127, 7, 32), 0xffff_ffff);
+        assert_eq!(scale_up(8192, 14, 32), 0x8000_0000);
+        assert_eq!(scale_up(16383, 14, 32), 0xffff_ffff);
+        for v in 0..128 {
+            assert_eq!(scale_down(scale_up(v, 7, 32), 32, 7), v)
+        }
+    }
+
+    #[test]
+    fn midi2_packets() {
+        assert_eq!(MidiMessage::note_on(2, 60, 127).to_ump(3, true), vec![0x4391_3c00, 0xffff_0000]);
+        assert_eq!(MidiMessage::note_on(1, 60, 0).to_ump(0, true), vec![0x4080_3c00, 0]);
+        assert_eq!(MidiMessage::pitch_bend(1, 8192).to_ump(0, true), vec![0x40e0_0000, 0x8000_0000]);
+        assert_eq!(MidiMessage::SystemRealtime(0xf8).to_ump(0, true), vec![0x10f8_0000]);
+        assert_eq!(MidiMessage::control_change(1, 7, 100).to_ump(0, false), vec![0x20b0_0764]);
+    }
+
+    #[test]
+    fn round_trips() {
+        let msgs = [
+            MidiMessage::note_on(5, 60, 100), MidiMessage::note_off(5, 60), MidiMessage::control_change(1, 74, 3),
+            MidiMessage::program_change(2, 7), MidiMessage::pitch_bend(16, 1234), MidiMessage::channel_pressure(3, 90),
+            MidiMessage::poly_pressure(3, 61, 1), MidiMessage::SystemCommon(vec![0xf2, 1, 2]),
+            MidiMessage::SystemCommon((0..20).map(|b| if b == 0 { 0xf0 } else { b }).chain([0xf7]).collect())
+        ];
+        for m in msgs {
+            assert_eq!(round_trip(m.clone(), true), vec![m.clone()]);
+            assert_eq!(round_trip(m.clone(), false), vec![m])
+        }
+    }
+
+    #[test]
+    fn clip() {
+        let events = vec![
+            (Duration::ZERO, MidiMessage::note_on(1, 60, 100)),
+            (Duration::from_millis(500), MidiMessage::note_off(1, 60)),
+        ];
+        let smf = read_clip(&write_clip(&events)).unwrap();
+        assert_eq!(smf.events, vec![(0, MidiMessage::note_on(1, 60, 100)), (480, MidiMessage::note_off(1, 60))]);
+        assert_eq!(smf.length, 480);
+    }
+
+    #[test]
+    fn sysex8_and_mixed_data() {
+        let mut words = data128(1, &(1..=13).collect::<Vec<u8>>());
+        // nothing to do with the sysex, and dropped
+        words.extend(data128(8, &[0; 13]));
+        words.extend(data128(3, &[14, 15]));
+        assert_eq!(MidiMessage::from_ump(&words), vec![MidiMessage::SystemCommon([0xf0].into_iter().chain(1..=15).chain([0xf7]).collect())]);
+        // can't be sent as midi 1.0
+        assert!(MidiMessage::from_ump(&data128(0, &[1, 0x80])).is_empty());
+        assert!(MidiMessage::from_ump(&data128(3, &[1])).is_empty());
+    }
+}
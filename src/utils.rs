@@ -2,12 +2,25 @@ use std::iter::IntoIterator;
 use thiserror::Error;
 use midir::*;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::num::ParseIntError;
+use std::sync::Mutex;
+use lazy_static::lazy_static;
 
 pub const CLIENT_NAME: &str = "MidiMappingManager";
 
-pub fn shortened_keyword_match<'a, T, S>(kwd: &str, options: T) -> Option<usize> 
+lazy_static! {
+    static ref CLIENT_NAME_OVERRIDE: Mutex<Option<String>> = Mutex::new(None);
+}
+/// the name ports are created under. CLIENT_NAME unless --client-name was given
+pub fn client_name() -> String {
+    CLIENT_NAME_OVERRIDE.lock().unwrap().clone().unwrap_or(String::from(CLIENT_NAME))
+}
+pub fn set_client_name(name: &str) {
+    *CLIENT_NAME_OVERRIDE.lock().unwrap() = Some(String::from(name))
+}
+
+pub fn shortened_keyword_match<T, S>(kwd: &str, options: T) -> Option<usize> 
 where T: IntoIterator<Item = S>, S: AsRef<str> {
     let mut ret = 0;
     let mut found_match = false;
@@ -42,13 +55,79 @@ pub fn sleep_ms(ms: u64) {
 
 pub fn push_if_not_present<T>(val: T, vec: &mut Vec<T>)
 where T: PartialEq {
-    if !vec.iter().any(|v| *v == val ) {
+    if !vec.contains(&val) {
         vec.push(val)
     }
 }
 
+/// time since the application started
+pub fn timestamp() -> Duration {
+    crate::INIT_TIME.elapsed()
+}
+
+/// xorshift64*. nowhere near good enough for anything serious, but it's seedable, which is the important part
+pub struct Rng(u64);
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift gets stuck on zero
+        Rng(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+    pub fn from_time() -> Rng {
+        Rng::new(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64)
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+    /// in 0..=max
+    pub fn up_to(&mut self, max: u64) -> u64 {
+        match max.checked_add(1) {
+            Some(n) => self.next_u64() % n,
+            None => self.next_u64()
+        }
+    }
+    /// in -range..=range. ranges past i64::MAX are cut down to it
+    pub fn jitter(&mut self, range: u64) -> i64 {
+        let range = range.min(i64::MAX as u64);
+        (self.up_to(range * 2) as i128 - range as i128) as i64
+    }
+}
+
+const NOTE_NAMES: &[&str] = &["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+/// middle c (60) is C4
+pub fn note_name(note: u8) -> String {
+    format!("{}{}", NOTE_NAMES[note as usize % 12], note as i32 / 12 - 1)
+}
+
+/// takes either a note number or a name like C4, F#2 or Bb-1
+pub fn parse_note(s: &str) -> Option<u8> {
+    if let Ok(n) = s.parse::<u8>() {
+        return Some(n).filter(|n| *n < 128)
+    }
+    let mut chars = s.chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    let mut semitone = NOTE_NAMES.iter().position(|n| n.starts_with(letter) && n.len() == 1)? as i32;
+    let rest = chars.as_str();
+    let octave = if let Some(o) = rest.strip_prefix('#') {
+        semitone += 1;
+        o
+    }
+    else if let Some(o) = rest.strip_prefix('b') {
+        semitone -= 1;
+        o
+    }
+    else {
+        rest
+    };
+    let n = (octave.parse::<i32>().ok()? + 1) * 12 + semitone;
+    (0..128).contains(&n).then_some(n as u8)
+}
+
 pub type Id = u32;
 pub type Result<T> = std::result::Result<T, MMMErr>;
+pub type ProcessorCtor = fn(String, &[String]) -> Result<Box<dyn crate::MidiIO>>;
 
 #[derive(Error, Debug)]
 pub enum MMMErr {
@@ -64,10 +143,16 @@ pub enum MMMErr {
     ArgError,
     #[error("parse error")]
     ParseError(#[from] ParseIntError),
+    #[error("io error")]
+    IoError(#[from] std::io::Error),
+    #[error("syntax error on line {0}")]
+    SyntaxError(usize),
+    #[error("invalid or unsupported file")]
+    InvalidFile,
 }
 
 #[allow(dead_code)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum MidiMessage {
     Channel([u8; 3]), // always the same length
     ChannelSmall([u8; 2]),
@@ -97,6 +182,34 @@ impl MidiMessage {
             Some(MidiMessage::SystemRealtime(bytes[0]))
         }
     }
+    /// goes by midi channel number, not binary value (the lowest channel is 1)
+    pub fn note_on(channel: u8, note: u8, velocity: u8) -> MidiMessage {
+        Self::Channel([0x90 | (channel - 1), note, velocity])
+    }
+    /// goes by midi channel number, not binary value (the lowest channel is 1)
+    pub fn note_off(channel: u8, note: u8) -> MidiMessage {
+        Self::Channel([0x80 | (channel - 1), note, 0])
+    }
+    /// goes by midi channel number, not binary value (the lowest channel is 1)
+    pub fn control_change(channel: u8, controller: u8, value: u8) -> MidiMessage {
+        Self::Channel([0xb0 | (channel - 1), controller, value])
+    }
+    /// goes by midi channel number, not binary value (the lowest channel is 1)
+    pub fn program_change(channel: u8, program: u8) -> MidiMessage {
+        Self::ChannelSmall([0xc0 | (channel - 1), program])
+    }
+    /// goes by midi channel number, not binary value (the lowest channel is 1). value is 0 to 16383, centred on 8192
+    pub fn pitch_bend(channel: u8, value: u16) -> MidiMessage {
+        Self::Channel([0xe0 | (channel - 1), (value & 0x7f) as u8, (value >> 7 & 0x7f) as u8])
+    }
+    /// goes by midi channel number, not binary value (the lowest channel is 1)
+    pub fn poly_pressure(channel: u8, note: u8, value: u8) -> MidiMessage {
+        Self::Channel([0xa0 | (channel - 1), note, value])
+    }
+    /// goes by midi channel number, not binary value (the lowest channel is 1)
+    pub fn channel_pressure(channel: u8, value: u8) -> MidiMessage {
+        Self::ChannelSmall([0xd0 | (channel - 1), value])
+    }
     pub fn to_bytes(&self) -> Vec<u8> {
         if let Self::SystemCommon(v) = &self {
             v.clone()
@@ -121,8 +234,50 @@ impl MidiMessage {
             _ => None
         }.map(|b| (b & 0xf) + 1)
     }
+    /// a note on with velocity 0 counts as a note off, not a note on
+    pub fn is_note_on(&self) -> bool {
+        matches!(self, Self::Channel([s, _, v]) if s & 0xf0 == 0x90 && *v != 0)
+    }
+    pub fn is_note_off(&self) -> bool {
+        matches!(self, Self::Channel([s, _, v]) if s & 0xf0 == 0x80 || (s & 0xf0 == 0x90 && *v == 0))
+    }
+    /// returns None for anything that isn't a note on or note off
+    pub fn note(&self) -> Option<u8> {
+        match self {
+            Self::Channel([s, n, _]) if s & 0xe0 == 0x80 => Some(*n),
+            _ => None
+        }
+    }
+    /// returns None for anything that isn't a note on or note off
+    pub fn velocity(&self) -> Option<u8> {
+        match self {
+            Self::Channel([s, _, v]) if s & 0xe0 == 0x80 => Some(*v),
+            _ => None
+        }
+    }
+    /// returns (controller, value), or None for anything that isn't a control change
+    pub fn as_cc(&self) -> Option<(u8, u8)> {
+        match self {
+            Self::Channel([s, c, v]) if s & 0xf0 == 0xb0 => Some((*c, *v)),
+            _ => None
+        }
+    }
+    /// returns None for anything that isn't a program change
+    pub fn as_program_change(&self) -> Option<u8> {
+        match self {
+            Self::ChannelSmall([s, p]) if s & 0xf0 == 0xc0 => Some(*p),
+            _ => None
+        }
+    }
+    /// only affects note ons and note offs
+    pub fn with_velocity(&self, velocity: u8) -> MidiMessage {
+        match self {
+            Self::Channel([s, n, _]) if s & 0xe0 == 0x80 => Self::Channel([*s, *n, velocity]),
+            _ => self.clone()
+        }
+    }
+
     /// goes by midi channel number, not binary value (the lowest channel is 1)
-    
     pub fn with_channel(&self, channel: u8) -> MidiMessage {
         match self {
             Self::Channel(mut b) => {
@@ -164,4 +319,39 @@ mod tests {
         assert_eq!(m.with_channel(8).channel(), Some(8));
         
     }
+
+    #[test]
+    fn notes() {
+        let on = MidiMessage::from_slice(0, &[0x93, 60, 100]).unwrap();
+        let off = MidiMessage::from_slice(0, &[0x93, 60, 0]).unwrap();
+        let cc = MidiMessage::from_slice(0, &[0xb3, 60, 100]).unwrap();
+        assert!(on.is_note_on() && !on.is_note_off());
+        assert!(off.is_note_off() && !off.is_note_on());
+        assert_eq!(on.note(), Some(60));
+        assert_eq!(cc.note(), None);
+        assert_eq!(on.with_velocity(20).velocity(), Some(20));
+        assert_eq!(note_name(60), "C4");
+        assert_eq!(note_name(1), "C#-1");
+        assert_eq!(parse_note("C4"), Some(60));
+        assert_eq!(parse_note("db4"), Some(61));
+        assert_eq!(parse_note("B#-1"), Some(12));
+        assert_eq!(parse_note("64"), Some(64));
+        assert_eq!(parse_note("G9"), Some(127));
+        assert_eq!(parse_note("G#9"), None);
+        assert_eq!(parse_note("H2"), None);
+    }
+
+    #[test]
+    fn rng_is_repeatable() {
+        let mut a = Rng::new(1234);
+        let mut b = Rng::new(1234);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+            assert!(a.jitter(5).abs() <= 5);
+            b.jitter(5);
+        }
+        // no overflow at the extremes
+        a.up_to(u64::MAX);
+        a.jitter(u64::MAX);
+    }
 }
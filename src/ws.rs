@@ -0,0 +1,153 @@
+// the server side of websockets (rfc 6455): the opening handshake and frames
+
+pub const OP_CONTINUATION: u8 = 0;
+pub const OP_TEXT: u8 = 1;
+pub const OP_BINARY: u8 = 2;
+pub const OP_CLOSE: u8 = 8;
+pub const OP_PING: u8 = 9;
+pub const OP_PONG: u8 = 10;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// the key from a client's opening handshake (everything before the blank line), or None if it isn't asking for a
+/// websocket
+pub fn handshake_key(request: &str) -> Option<String> {
+    request.lines().skip(1)
+        .filter_map(|l| l.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-key"))
+        .map(|(_, key)| String::from(key.trim()))
+}
+pub fn handshake_response(key: &str) -> String {
+    let accept = base64(&sha1(format!("{}{}", key, GUID).as_bytes()));
+    format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept)
+}
+
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>, // unmasked
+}
+/// the frame at the start of buf and how many bytes it took, or None if it isn't all there yet
+pub fn parse_frame(buf: &[u8]) -> Option<(Frame, usize)> {
+    let fin = buf.first()? & 0x80 != 0;
+    let opcode = buf[0] & 0xf;
+    let masked = buf.get(1)? & 0x80 != 0;
+    let (len, mut pos) = match buf[1] & 0x7f {
+        126 => (u16::from_be_bytes(buf.get(2..4)?.try_into().unwrap()) as usize, 4),
+        127 => (u64::from_be_bytes(buf.get(2..10)?.try_into().unwrap()) as usize, 10),
+        len => (len as usize, 2)
+    };
+    let mask = if masked {
+        pos += 4;
+        buf.get(pos - 4..pos)?.try_into().unwrap()
+    }
+    else {
+        [0; 4]
+    };
+    let payload = buf.get(pos..pos.checked_add(len)?)?.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect();
+    Some((Frame { fin, opcode, payload }, pos + len))
+}
+/// servers don't mask their frames
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut ret = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => ret.push(len as u8),
+        len @ 126..=0xffff => {
+            ret.push(126);
+            ret.extend((len as u16).to_be_bytes())
+        }
+        len => {
+            ret.push(127);
+            ret.extend((len as u64).to_be_bytes())
+        }
+    }
+    ret.extend(payload);
+    ret
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0)
+    }
+    msg.extend((data.len() as u64 * 8).to_be_bytes());
+    for chunk in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes(chunk[i * 4..i * 4 + 4].try_into().unwrap())
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1)
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6)
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v)
+        }
+    }
+    let mut ret = [0; 20];
+    for (i, v) in h.iter().enumerate() {
+        ret[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes())
+    }
+    ret
+}
+fn base64(data: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut ret = String::new();
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(CHARS[(n >> (18 - i * 6) & 0x3f) as usize] as char)
+            }
+            else {
+                ret.push('=')
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake() {
+        // the example from the rfc
+        let request = "GET /chat HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==";
+        let key = handshake_key(request).unwrap();
+        assert!(handshake_response(&key).contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert_eq!(handshake_key("GET / HTTP/1.1\r\nHost: x"), None);
+        assert_eq!(base64(b"ab"), "YWI=");
+    }
+
+    #[test]
+    fn frames() {
+        // a masked "Hello" from the rfc
+        let masked = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let (frame, len) = parse_frame(&masked).unwrap();
+        assert_eq!((frame.fin, frame.opcode, frame.payload.as_slice(), len), (true, OP_TEXT, &b"Hello"[..], masked.len()));
+        assert!(parse_frame(&masked[..6]).is_none());
+
+        let long = vec![7; 300];
+        let bytes = encode_frame(OP_BINARY, &long);
+        assert_eq!(&bytes[..4], &[0x82, 126, 1, 44]);
+        assert_eq!(parse_frame(&bytes).unwrap().0.payload, long);
+    }
+}